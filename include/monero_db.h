@@ -0,0 +1,30 @@
+/* C bindings for the monero_db crate, built with the `ffi` feature. */
+#ifndef MONERO_DB_H
+#define MONERO_DB_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#define MONERO_DB_OK 0
+#define MONERO_DB_NOT_FOUND 1
+#define MONERO_DB_ERROR -1
+
+typedef struct MoneroDB MoneroDB;
+
+typedef struct MoneroDbBuffer {
+    uint8_t *data;
+    size_t len;
+} MoneroDbBuffer;
+
+MoneroDB *monero_db_open(const char *path, bool read_only);
+void monero_db_close(MoneroDB *db);
+
+int monero_db_height(const MoneroDB *db, uint64_t *height);
+int monero_db_get_block_blob(const MoneroDB *db, uint64_t height, MoneroDbBuffer *out);
+int monero_db_get_tx_blob(const MoneroDB *db, const uint8_t tx_hash[32], MoneroDbBuffer *out);
+int monero_db_is_key_image_spent(const MoneroDB *db, const uint8_t key_image[32], bool *spent);
+
+void monero_db_buffer_free(MoneroDbBuffer buffer);
+
+#endif
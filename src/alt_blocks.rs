@@ -0,0 +1,164 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Alternative block queries
+
+use lmdb::{Cursor, Transaction};
+use monero::consensus::deserialize;
+use monero::database::block::AltBlock;
+use monero::Hash;
+
+use super::monero_db::{clear_db, delete_item};
+use super::{Error, MoneroDB, ReadWriteDb};
+
+/// Iterator over the alternative blocks stored in the database, created by
+/// [`MoneroDB::alt_blocks_iter`]
+///
+/// Each step reads in its own transaction, seeking past the last returned hash,
+/// so blocks added or removed while iterating may or may not be seen.
+///
+pub struct AltBlocksIter<'a> {
+    db: &'a MoneroDB,
+    last_hash: Option<Vec<u8>>,
+    done: bool,
+}
+
+impl<'a> AltBlocksIter<'a> {
+    fn read_next(&mut self) -> Result<Option<(Hash, AltBlock)>, Error> {
+        self.db.check_table_version(self.db.sub_dbs.alt_blocks)?;
+        let transaction = self.db.begin_read()?;
+        let curser = transaction.open_ro_cursor(self.db.sub_dbs.alt_blocks)?;
+        let entry = match &self.last_hash {
+            None => curser.get(None, None, 0),
+            Some(last_hash) => match curser.get(Some(last_hash), None, 17) {
+                Ok((Some(key), _)) if key == last_hash.as_slice() => curser.get(None, None, 8),
+                entry => entry,
+            },
+        };
+        let (key, value) = match entry {
+            Ok(entry) => entry,
+            Err(lmdb::Error::NotFound) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let key = key.unwrap_or_default();
+        self.last_hash = Some(key.to_vec());
+        Ok(Some((Hash::from_slice(key), deserialize(value)?)))
+    }
+}
+
+impl<'a> Iterator for AltBlocksIter<'a> {
+    type Item = Result<(Hash, AltBlock), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let next = self.read_next().transpose();
+        if !matches!(next, Some(Ok(_))) {
+            self.done = true;
+        }
+        next
+    }
+}
+
+/// Result of comparing an alternative chain with the main chain
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AltChainEvaluation {
+    /// Height of the last main chain block the alternative chain builds on
+    pub fork_height: u64,
+    /// Hashes of the alternative blocks, from the fork point to the tip
+    pub alt_blocks: Vec<Hash>,
+    /// Cumulative difficulty of the alternative chain at its tip
+    pub alt_cumulative_difficulty: u128,
+    /// Cumulative difficulty of the main chain at its tip
+    pub main_cumulative_difficulty: u128,
+}
+
+impl AltChainEvaluation {
+    /// Returns if monerod would switch to the alternative chain
+    ///
+    pub fn triggers_reorg(&self) -> bool {
+        self.alt_cumulative_difficulty > self.main_cumulative_difficulty
+    }
+}
+
+impl MoneroDB {
+    /// Iterates over all the alternative blocks and their hashes
+    ///
+    pub fn alt_blocks_iter(&self) -> AltBlocksIter<'_> {
+        AltBlocksIter {
+            db: self,
+            last_hash: None,
+            done: false,
+        }
+    }
+
+    /// Gets the alternative blocks at `height`
+    ///
+    pub fn get_alt_blocks_at_height(&self, height: u64) -> Result<Vec<(Hash, AltBlock)>, Error> {
+        let mut alt_blocks = Vec::new();
+        for alt_block in self.alt_blocks_iter() {
+            let (hash, alt_block) = alt_block?;
+            if alt_block.height == height {
+                alt_blocks.push((hash, alt_block));
+            }
+        }
+        Ok(alt_blocks)
+    }
+
+    /// Walks the alternative chain ending at `tip_hash` back to the main chain and
+    /// compares its cumulative difficulty with the main chain's.
+    ///
+    pub fn evaluate_alt_chain(&self, tip_hash: &Hash) -> Result<AltChainEvaluation, Error> {
+        let tip = self.get_alt_block(tip_hash)?;
+        let alt_cumulative_difficulty = ((tip.cumulative_difficulty_high as u128) << 64)
+            | tip.cumulative_difficulty_low as u128;
+
+        let mut alt_blocks = vec![*tip_hash];
+        let mut prev_id = tip.block.header.prev_id;
+        let fork_height = loop {
+            match self.get_block_height(&prev_id) {
+                Ok(block_height) => break block_height.height,
+                Err(e) if e.is_not_found() => {
+                    let alt_block = self.get_alt_block(&prev_id)?;
+                    alt_blocks.push(prev_id);
+                    prev_id = alt_block.block.header.prev_id;
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        alt_blocks.reverse();
+
+        let main_tip = self.get_blockchain_height()? - 1;
+        Ok(AltChainEvaluation {
+            fork_height,
+            alt_blocks,
+            alt_cumulative_difficulty,
+            main_cumulative_difficulty: self.get_block_info(main_tip)?.cumulative_difficulty(),
+        })
+    }
+}
+
+impl ReadWriteDb {
+    /// Removes an alternative block from the database
+    ///
+    pub fn remove_alt_block(&self, block_hash: &Hash) -> Result<(), Error> {
+        delete_item(&self.env, self.sub_dbs.alt_blocks, block_hash.as_bytes())
+    }
+
+    /// Removes every alternative block from the database
+    ///
+    pub fn flush_alt_blocks(&self) -> Result<(), Error> {
+        clear_db(&self.env, self.sub_dbs.alt_blocks)
+    }
+}
@@ -0,0 +1,565 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Chain analysis passes
+
+use monero::blockdata::transaction::{SubField, TransactionPrefix, TxIn, TxOutTarget};
+use monero::consensus::serialize;
+use monero::util::ringct::RctSig;
+use monero::{Hash, PublicKey};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Range;
+
+use super::consensus::{
+    LOCKED_TX_ALLOWED_DELTA_BLOCKS, LOCKED_TX_ALLOWED_DELTA_SECONDS, MAX_BLOCK_NUMBER,
+};
+use super::rings::resolve_key_offsets;
+use super::scan::unix_time;
+use super::{Amount, Error, MoneroDB};
+
+/// RingCT types using bulletproofs, the CLSAG type uses them too
+const RCT_TYPE_BULLETPROOF: u8 = 3;
+const RCT_TYPE_CLSAG: u8 = 5;
+const RCT_TYPE_BULLETPROOF_PLUS: u8 = 6;
+
+/// A transaction input located in the chain
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputLocation {
+    /// Height of the block containing the transaction
+    pub height: u64,
+    /// Hash of the transaction
+    pub tx_hash: Hash,
+    /// Index of the input in the transaction
+    pub input_index: usize,
+}
+
+/// A set of ring members used by more than one input
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateRing {
+    /// Sorted global output ids of the ring members
+    pub members: Vec<u64>,
+    /// Inputs using this ring
+    pub inputs: Vec<InputLocation>,
+}
+
+/// Offset patterns a normal wallet would not produce
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OffsetPattern {
+    /// The same output appears more than once in the ring
+    RepeatedMember,
+    /// Every ring member directly follows the previous one
+    Consecutive,
+}
+
+/// An input whose key offsets follow an [`OffsetPattern`]
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnusualOffsets {
+    /// The input with the unusual offsets
+    pub input: InputLocation,
+    /// The pattern found
+    pub pattern: OffsetPattern,
+}
+
+/// Results of [`MoneroDB::analyze_rings`]
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RingAnalysis {
+    /// Rings shared by more than one input
+    pub duplicate_rings: Vec<DuplicateRing>,
+    /// Inputs with unusual offsets
+    pub unusual_offsets: Vec<UnusualOffsets>,
+}
+
+/// Kinds of unusual transactions found by [`MoneroDB::scan_anomalies`]
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnomalyKind {
+    /// The transaction weighs more than the requested threshold
+    Oversized {
+        /// Weight of the transaction
+        weight: u64,
+    },
+    /// A non-coinbase transaction paying no fee
+    ZeroFee,
+    /// A non-coinbase transaction with a non-zero unlock time
+    UnlockTime {
+        /// The unlock time of the transaction
+        unlock_time: u64,
+    },
+    /// The extra field is missing a transaction public key, repeats it or
+    /// contains fields wallets do not create
+    NonstandardExtra,
+}
+
+/// An unusual transaction
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Anomaly {
+    /// Height of the block containing the transaction
+    pub height: u64,
+    /// Hash of the transaction
+    pub tx_hash: Hash,
+    /// What is unusual about the transaction
+    pub kind: AnomalyKind,
+}
+
+/// When a time-locked output becomes spendable
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnlockPoint {
+    /// The output unlocks at this block height
+    Height(u64),
+    /// The output unlocks at this unix timestamp
+    Timestamp(u64),
+}
+
+impl UnlockPoint {
+    /// Converts a transactions unlock time to an unlock point
+    ///
+    pub fn from_unlock_time(unlock_time: u64) -> Self {
+        if unlock_time < MAX_BLOCK_NUMBER {
+            UnlockPoint::Height(unlock_time)
+        } else {
+            UnlockPoint::Timestamp(unlock_time)
+        }
+    }
+
+    /// Returns if the unlock point is still in the future at the given blockchain height
+    ///
+    pub fn is_locked(&self, blockchain_height: u64) -> bool {
+        match *self {
+            UnlockPoint::Height(height) => {
+                height > blockchain_height.saturating_sub(1) + LOCKED_TX_ALLOWED_DELTA_BLOCKS
+            }
+            UnlockPoint::Timestamp(time) => time > unix_time() + LOCKED_TX_ALLOWED_DELTA_SECONDS,
+        }
+    }
+}
+
+/// An output that cannot be spent yet
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockedOutput {
+    /// Height of the block containing the transaction
+    pub height: u64,
+    /// Hash of the transaction
+    pub tx_hash: Hash,
+    /// Index of the output in the transaction
+    pub index: usize,
+    /// One-time public key of the output
+    pub key: Option<PublicKey>,
+    /// Amount of the output, `None` for RCT outputs as the amount is hidden
+    pub amount: Option<u64>,
+    /// When the output unlocks
+    pub unlock: UnlockPoint,
+}
+
+fn push_locked_outputs(
+    height: u64,
+    tx_hash: Hash,
+    prefix: &TransactionPrefix,
+    blockchain_height: u64,
+    locked: &mut Vec<LockedOutput>,
+) {
+    let unlock = UnlockPoint::from_unlock_time(prefix.unlock_time.0);
+    if !unlock.is_locked(blockchain_height) {
+        return;
+    }
+    for (index, output) in prefix.outputs.iter().enumerate() {
+        locked.push(LockedOutput {
+            height,
+            tx_hash,
+            index,
+            key: match &output.target {
+                TxOutTarget::ToKey { key } => Some(*key),
+                _ => None,
+            },
+            amount: (output.amount.0 != 0).then_some(output.amount.0),
+            unlock,
+        });
+    }
+}
+
+/// Why an output is known to be unspendable
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BurnReason {
+    /// The output key is not a valid curve point
+    InvalidPoint,
+    /// The output key is a point of small order, including the identity
+    SmallOrder,
+    /// The output key is in the list of published keys given to the scan
+    PublishedKey,
+    /// The output key was already used by an earlier output in the scanned range,
+    /// so only one of the outputs can ever be spent
+    DuplicateKey,
+}
+
+/// An output that can never be spent
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BurnedOutput {
+    /// Height of the block containing the transaction
+    pub height: u64,
+    /// Hash of the transaction
+    pub tx_hash: Hash,
+    /// Index of the output in the transaction
+    pub index: usize,
+    /// Amount of the output, `None` for RCT outputs as the amount is hidden
+    pub amount: Option<u64>,
+    /// Why the output is unspendable
+    pub reason: BurnReason,
+}
+
+/// Burned outputs of a period of blocks
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BurnPeriod {
+    /// First height of the period
+    pub start_height: u64,
+    /// Number of burned outputs
+    pub outputs: u64,
+    /// Sum of the burned outputs with a visible amount
+    pub amount: u64,
+    /// Number of burned outputs with a hidden amount
+    pub hidden_amount_outputs: u64,
+}
+
+/// Results of [`MoneroDB::find_burned_outputs`]
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BurnReport {
+    /// Every burned output found
+    pub outputs: Vec<BurnedOutput>,
+    /// Burned outputs summed per period, periods without burns are left out
+    pub periods: Vec<BurnPeriod>,
+}
+
+fn burn_reason(
+    key: &PublicKey,
+    published_keys: &HashSet<PublicKey>,
+    seen_keys: &mut HashSet<PublicKey>,
+) -> Option<BurnReason> {
+    if !seen_keys.insert(*key) {
+        return Some(BurnReason::DuplicateKey);
+    }
+    if published_keys.contains(key) {
+        return Some(BurnReason::PublishedKey);
+    }
+    match key.point.decompress() {
+        None => Some(BurnReason::InvalidPoint),
+        Some(point) if point.is_small_order() => Some(BurnReason::SmallOrder),
+        Some(_) => None,
+    }
+}
+
+/// Gets the weight of a transaction of `blob_size` bytes as monerod's
+/// `get_transaction_weight`, adding back part of the space saved by aggregating the
+/// range proofs of more than two outputs. A single aggregated proof is assumed, as
+/// required since hard fork 10.
+pub(crate) fn tx_weight(version: u64, rct_type: u8, outputs: usize, blob_size: u64) -> u64 {
+    let plus = rct_type == RCT_TYPE_BULLETPROOF_PLUS;
+    let bulletproof = (RCT_TYPE_BULLETPROOF..=RCT_TYPE_CLSAG).contains(&rct_type);
+    if version < 2 || !(bulletproof || plus) || outputs <= 2 {
+        return blob_size;
+    }
+    let proof_scalars = if plus { 6 } else { 9 };
+    // notional size of a 2 output proof, per output
+    let bp_base = 32 * (proof_scalars + 7 * 2) / 2;
+    let rounds = usize::BITS - (outputs - 1).leading_zeros();
+    let padded_outputs = 1u64 << rounds;
+    let bp_size = 32 * (proof_scalars + 2 * (rounds as u64 + 6));
+    blob_size + (bp_base * padded_outputs - bp_size) * 4 / 5
+}
+
+/// Gets the fee paid by a transaction
+pub(crate) fn tx_fee(prefix: &TransactionPrefix, rct_signatures: &RctSig) -> u64 {
+    if let Some(sig) = &rct_signatures.sig {
+        return sig.txn_fee.0;
+    }
+    let inputs: u64 = prefix
+        .inputs
+        .iter()
+        .map(|input| match input {
+            TxIn::ToKey { amount, .. } => amount.0,
+            _ => 0,
+        })
+        .sum();
+    let outputs: u64 = prefix.outputs.iter().map(|output| output.amount.0).sum();
+    inputs.saturating_sub(outputs)
+}
+
+fn is_standard_extra(fields: &[SubField]) -> bool {
+    let mut tx_pub_keys = 0;
+    for field in fields {
+        match field {
+            SubField::TxPublicKey(_) => tx_pub_keys += 1,
+            SubField::Nonce(_) | SubField::AdditionalPublickKey(_) => {}
+            _ => return false,
+        }
+    }
+    tx_pub_keys == 1
+}
+
+impl MoneroDB {
+    /// Flags inputs in the blocks of `range` that share identical ring member sets
+    /// or whose key offsets follow an unusual pattern.
+    ///
+    pub fn analyze_rings(&self, range: Range<u64>) -> Result<RingAnalysis, Error> {
+        let mut rings: HashMap<Vec<u64>, Vec<InputLocation>> = HashMap::new();
+        let mut analysis = RingAnalysis::default();
+
+        for height in range {
+            let block = self.get_block(height)?;
+            for tx_hash in &block.tx_hashes {
+                let tx = self.get_tx_pruned_by_hash(tx_hash)?;
+                for (input_index, input) in tx.prefix.inputs.iter().enumerate() {
+                    let (amount, key_offsets) = match input {
+                        TxIn::ToKey {
+                            amount,
+                            key_offsets,
+                            ..
+                        } => (amount.0, key_offsets),
+                        _ => continue,
+                    };
+                    let location = InputLocation {
+                        height,
+                        tx_hash: *tx_hash,
+                        input_index,
+                    };
+
+                    let pattern = if key_offsets.iter().skip(1).any(|offset| offset.0 == 0) {
+                        Some(OffsetPattern::RepeatedMember)
+                    } else if key_offsets.len() > 2
+                        && key_offsets.iter().skip(1).all(|offset| offset.0 == 1)
+                    {
+                        Some(OffsetPattern::Consecutive)
+                    } else {
+                        None
+                    };
+                    if let Some(pattern) = pattern {
+                        analysis.unusual_offsets.push(UnusualOffsets {
+                            input: location,
+                            pattern,
+                        });
+                    }
+
+                    let mut members = self.get_ring_output_ids(amount, key_offsets)?;
+                    members.sort_unstable();
+                    rings.entry(members).or_default().push(location);
+                }
+            }
+        }
+
+        analysis.duplicate_rings = rings
+            .into_iter()
+            .filter(|(_, inputs)| inputs.len() > 1)
+            .map(|(members, inputs)| DuplicateRing { members, inputs })
+            .collect();
+        analysis
+            .duplicate_rings
+            .sort_by_key(|ring| (ring.inputs[0].height, ring.inputs[0].input_index));
+        Ok(analysis)
+    }
+
+    /// Builds a histogram of the age, in blocks, of every ring member referenced by
+    /// the inputs in the blocks of `range`, keyed by age.
+    ///
+    /// The age of a ring member is the height of the spending block minus the height
+    /// of the block that created the output.
+    ///
+    pub fn spend_age_histogram(&self, range: Range<u64>) -> Result<BTreeMap<u64, u64>, Error> {
+        let mut histogram = BTreeMap::new();
+        for height in range {
+            let block = self.get_block(height)?;
+            for tx_hash in &block.tx_hashes {
+                let tx = self.get_tx_pruned_by_hash(tx_hash)?;
+                for input in &tx.prefix.inputs {
+                    if let TxIn::ToKey {
+                        amount,
+                        key_offsets,
+                        ..
+                    } = input
+                    {
+                        let key_offsets: Vec<u64> =
+                            key_offsets.iter().map(|offset| offset.0).collect();
+                        for amount_index in resolve_key_offsets(&key_offsets) {
+                            let created = if amount.0 == 0 {
+                                self.get_output_rct_outkey(Amount(0), amount_index)?
+                                    .data
+                                    .height
+                            } else {
+                                self.get_output_pre_rct_outkey(amount.0, amount_index)?
+                                    .data
+                                    .height
+                            };
+                            *histogram.entry(height.saturating_sub(created)).or_default() += 1;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(histogram)
+    }
+
+    /// Scans the non-coinbase transactions in the blocks of `range` for the unusual
+    /// properties listed in [`AnomalyKind`].
+    ///
+    /// Transactions whose weight is over `max_tx_weight` are reported as oversized.
+    /// The weight counts the prunable part only while it is still stored.
+    ///
+    pub fn scan_anomalies(
+        &self,
+        range: Range<u64>,
+        max_tx_weight: u64,
+    ) -> Result<Vec<Anomaly>, Error> {
+        let mut anomalies = Vec::new();
+        for height in range {
+            let block = self.get_block(height)?;
+            for tx_hash in &block.tx_hashes {
+                let tx_id = self.get_tx_indices(tx_hash)?.data.tx_id;
+                let tx = self.get_tx_pruned(tx_id)?;
+                let mut report = |kind| {
+                    anomalies.push(Anomaly {
+                        height,
+                        tx_hash: *tx_hash,
+                        kind,
+                    })
+                };
+
+                let prunable_size = match self.get_tx_prunable(tx_id) {
+                    Ok(blob) => blob.len(),
+                    Err(Error::Pruned { .. }) => 0,
+                    Err(e) if e.is_not_found() => 0,
+                    Err(e) => return Err(e),
+                };
+                let pruned = serialize(&tx);
+                let rct_type = pruned
+                    .get(serialize(&tx.prefix).len())
+                    .copied()
+                    .unwrap_or(0);
+                let weight = tx_weight(
+                    tx.prefix.version.0,
+                    rct_type,
+                    tx.prefix.outputs.len(),
+                    (pruned.len() + prunable_size) as u64,
+                );
+                if weight > max_tx_weight {
+                    report(AnomalyKind::Oversized { weight });
+                }
+                if tx_fee(&tx.prefix, &tx.rct_signatures) == 0 {
+                    report(AnomalyKind::ZeroFee);
+                }
+                if tx.prefix.unlock_time.0 != 0 {
+                    report(AnomalyKind::UnlockTime {
+                        unlock_time: tx.prefix.unlock_time.0,
+                    });
+                }
+                if !is_standard_extra(&tx.prefix.extra.0) {
+                    report(AnomalyKind::NonstandardExtra);
+                }
+            }
+        }
+        Ok(anomalies)
+    }
+
+    /// Lists the outputs created after `after_height` whose unlock time, height or
+    /// timestamp based, is still in the future. Recent miner outputs are included.
+    ///
+    pub fn list_locked_outputs(&self, after_height: u64) -> Result<Vec<LockedOutput>, Error> {
+        let blockchain_height = self.get_blockchain_height()?;
+        let mut locked = Vec::new();
+        for height in after_height.saturating_add(1)..blockchain_height {
+            let block = self.get_block(height)?;
+            let miner_tx_hash = block.miner_tx.hash();
+            push_locked_outputs(
+                height,
+                miner_tx_hash,
+                &block.miner_tx.prefix,
+                blockchain_height,
+                &mut locked,
+            );
+
+            for tx_hash in &block.tx_hashes {
+                let prefix = self.get_tx_pruned_by_hash(tx_hash)?.prefix;
+                push_locked_outputs(height, *tx_hash, &prefix, blockchain_height, &mut locked);
+            }
+        }
+        Ok(locked)
+    }
+
+    /// Finds the outputs in the blocks of `range` that can provably never be spent,
+    /// summing them per `period_blocks` blocks.
+    ///
+    /// `published_keys` are output keys whose private keys are public knowledge
+    /// and which are therefore treated as burns.
+    ///
+    pub fn find_burned_outputs(
+        &self,
+        range: Range<u64>,
+        period_blocks: u64,
+        published_keys: &HashSet<PublicKey>,
+    ) -> Result<BurnReport, Error> {
+        let period_blocks = period_blocks.max(1);
+        let mut seen_keys = HashSet::new();
+        let mut periods: BTreeMap<u64, BurnPeriod> = BTreeMap::new();
+        let mut report = BurnReport::default();
+
+        for height in range {
+            let block = self.get_block(height)?;
+            let mut txs: Vec<(Hash, TransactionPrefix)> =
+                vec![(block.miner_tx.hash(), block.miner_tx.prefix)];
+            for tx_hash in &block.tx_hashes {
+                txs.push((*tx_hash, self.get_tx_pruned_by_hash(tx_hash)?.prefix));
+            }
+
+            for (tx_hash, prefix) in txs {
+                for (index, output) in prefix.outputs.iter().enumerate() {
+                    let key = match &output.target {
+                        TxOutTarget::ToKey { key } => key,
+                        _ => continue,
+                    };
+                    let reason = match burn_reason(key, published_keys, &mut seen_keys) {
+                        Some(reason) => reason,
+                        None => continue,
+                    };
+                    let amount = (output.amount.0 != 0).then_some(output.amount.0);
+                    report.outputs.push(BurnedOutput {
+                        height,
+                        tx_hash,
+                        index,
+                        amount,
+                        reason,
+                    });
+
+                    let start_height = height - height % period_blocks;
+                    let period = periods.entry(start_height).or_insert(BurnPeriod {
+                        start_height,
+                        ..Default::default()
+                    });
+                    period.outputs += 1;
+                    match amount {
+                        Some(amount) => period.amount += amount,
+                        None => period.hidden_amount_outputs += 1,
+                    }
+                }
+            }
+        }
+        report.periods = periods.into_values().collect();
+        Ok(report)
+    }
+}
@@ -0,0 +1,236 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Consistency audits of a downloaded database
+
+use monero::consensus::{deserialize, serialize};
+use monero::cryptonote::hash::{keccak_256, Hashable};
+use monero::database::transaction::TransactionPruned;
+use monero::Hash;
+use std::ops::Range;
+
+use super::monero_db::get_raw_item;
+use super::parallel::{scan_parallel, DEFAULT_CHUNK_SIZE};
+use super::pow::tree_hash;
+use super::{Error, MoneroDB};
+
+/// A block whose recomputed hash does not match the hash stored for it
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BlockHashMismatch {
+    /// Height of the block
+    pub height: u64,
+    /// Hash recomputed from the stored block
+    #[cfg_attr(
+        feature = "serde",
+        serde(serialize_with = "crate::json::serialize_hash")
+    )]
+    pub computed: Hash,
+    /// Hash stored in the `block_info` entry of the block
+    #[cfg_attr(
+        feature = "serde",
+        serde(serialize_with = "crate::json::serialize_hash")
+    )]
+    pub stored: Hash,
+    /// Height `block_heights` maps the recomputed hash to, `None` if it is not in
+    /// the table
+    pub indexed_height: Option<u64>,
+}
+
+/// A block whose transactions do not hash to the Merkle root committed to by its
+/// header
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TxRootMismatch {
+    /// Height of the block
+    pub height: u64,
+    /// Merkle root of the transaction hashes listed in the block
+    #[cfg_attr(
+        feature = "serde",
+        serde(serialize_with = "crate::json::serialize_key")
+    )]
+    pub expected_root: [u8; 32],
+    /// Merkle root of the hashes of the stored transactions
+    #[cfg_attr(
+        feature = "serde",
+        serde(serialize_with = "crate::json::serialize_key")
+    )]
+    pub computed_root: [u8; 32],
+}
+
+/// Results of [`MoneroDB::audit_tx_roots`]
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TxRootAudit {
+    /// Number of blocks audited
+    pub blocks: u64,
+    /// Version 1 transactions that could not be hashed because their signatures
+    /// were pruned, these are taken to match
+    pub unverified_txs: u64,
+    /// Blocks whose transactions do not match
+    pub mismatches: Vec<TxRootMismatch>,
+}
+
+impl MoneroDB {
+    /// Recomputes the hash of every block in `range` and checks it matches the
+    /// hash in `block_info` and that `block_heights` maps it to the block's height.
+    ///
+    /// The blocks are hashed in parallel, `progress` is called with the height
+    /// audited up to, exclusive, each time another chunk of blocks is done.
+    ///
+    pub fn audit_block_hashes<F: FnMut(u64)>(
+        &self,
+        range: Range<u64>,
+        mut progress: F,
+    ) -> Result<Vec<BlockHashMismatch>, Error> {
+        let range = range.start..range.end.min(self.get_blockchain_height()?);
+        let mut mismatches = Vec::new();
+        scan_parallel(
+            range,
+            &self.options.scan,
+            "audit_block_hashes",
+            DEFAULT_CHUNK_SIZE,
+            |chunk| self.find_block_hash_mismatches(chunk),
+            |chunk, found| {
+                mismatches.extend(found);
+                progress(chunk.end);
+                Ok(())
+            },
+        )?;
+        Ok(mismatches)
+    }
+
+    fn find_block_hash_mismatches(
+        &self,
+        range: Range<u64>,
+    ) -> Result<Vec<BlockHashMismatch>, Error> {
+        let mut mismatches = Vec::new();
+        for height in range {
+            let computed = self.get_block(height)?.id();
+            let stored = self.get_block_hash(height)?;
+            let indexed_height = match self.get_block_height(&computed) {
+                Ok(indexed) => Some(indexed.height),
+                Err(e) if e.is_not_found() => None,
+                Err(e) => return Err(e),
+            };
+            if computed != stored || indexed_height != Some(height) {
+                mismatches.push(BlockHashMismatch {
+                    height,
+                    computed,
+                    stored,
+                    indexed_height,
+                });
+            }
+        }
+        Ok(mismatches)
+    }
+
+    /// Recomputes the hash of every stored transaction of the blocks in `range` and
+    /// checks their Merkle root against the one committed to by each block.
+    ///
+    /// Version 2 transactions are hashed from their pruned part and the stored
+    /// prunable hash, so pruned databases can be audited. Version 1 transactions
+    /// need their signatures, when these were pruned they are counted as
+    /// unverified. `progress` is called as in [`MoneroDB::audit_block_hashes`].
+    ///
+    pub fn audit_tx_roots<F: FnMut(u64)>(
+        &self,
+        range: Range<u64>,
+        mut progress: F,
+    ) -> Result<TxRootAudit, Error> {
+        let range = range.start..range.end.min(self.get_blockchain_height()?);
+        let mut audit = TxRootAudit::default();
+        scan_parallel(
+            range,
+            &self.options.scan,
+            "audit_tx_roots",
+            DEFAULT_CHUNK_SIZE,
+            |chunk| self.audit_tx_roots_chunk(chunk),
+            |chunk, chunk_audit| {
+                audit.blocks += chunk_audit.blocks;
+                audit.unverified_txs += chunk_audit.unverified_txs;
+                audit.mismatches.extend(chunk_audit.mismatches);
+                progress(chunk.end);
+                Ok(())
+            },
+        )?;
+        Ok(audit)
+    }
+
+    fn audit_tx_roots_chunk(&self, range: Range<u64>) -> Result<TxRootAudit, Error> {
+        let mut audit = TxRootAudit::default();
+        for height in range {
+            let block = self.get_block(height)?;
+            let miner_tx_hash = *block.miner_tx.hash().as_fixed_bytes();
+            let mut expected = vec![miner_tx_hash];
+            let mut computed = vec![miner_tx_hash];
+            for tx_hash in &block.tx_hashes {
+                expected.push(*tx_hash.as_fixed_bytes());
+                computed.push(match self.stored_tx_hash(tx_hash)? {
+                    Some(stored_hash) => stored_hash,
+                    None => {
+                        audit.unverified_txs += 1;
+                        *tx_hash.as_fixed_bytes()
+                    }
+                });
+            }
+            let (expected_root, computed_root) = (tree_hash(&expected), tree_hash(&computed));
+            if expected_root != computed_root {
+                audit.mismatches.push(TxRootMismatch {
+                    height,
+                    expected_root,
+                    computed_root,
+                });
+            }
+            audit.blocks += 1;
+        }
+        Ok(audit)
+    }
+
+    /// Hashes the transaction stored under `tx_hash`, `None` if it is a version 1
+    /// transaction whose signatures were pruned
+    fn stored_tx_hash(&self, tx_hash: &Hash) -> Result<Option<[u8; 32]>, Error> {
+        let tx_id = self.get_tx_indices(tx_hash)?.data.tx_id;
+        let pruned = get_raw_item(
+            self,
+            self.sub_dbs.txs_pruned,
+            &tx_id.to_le_bytes(),
+            &[0],
+            15,
+        )?;
+        let tx: TransactionPruned = deserialize(&pruned)?;
+        if tx.prefix.version.0 < 2 {
+            return match self.get_tx_blob(tx_hash) {
+                Ok(blob) => Ok(Some(keccak_256(&blob))),
+                Err(Error::Pruned { .. }) => Ok(None),
+                Err(e) => Err(e),
+            };
+        }
+
+        // v2 hashes are the hash of the prefix, RCT base and prunable hashes
+        let prefix_len = serialize(&tx.prefix).len();
+        let (prefix, base) = pruned.split_at(prefix_len);
+        let prunable_hash = match base.first() {
+            // RCTTypeNull has no prunable part
+            None | Some(0) => [0; 32],
+            Some(_) => *self.get_txs_prunable_hash(tx_id)?.as_fixed_bytes(),
+        };
+        let mut hashes = [0; 96];
+        hashes[..32].copy_from_slice(&keccak_256(prefix));
+        hashes[32..64].copy_from_slice(&keccak_256(base));
+        hashes[64..].copy_from_slice(&prunable_hash);
+        Ok(Some(keccak_256(&hashes)))
+    }
+}
@@ -0,0 +1,138 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Micro-benchmarks runnable against any database
+//!
+//! Enabled with the `bench` feature. Every benchmark draws its inputs from a
+//! seeded generator, so runs with the same seed against the same database
+//! perform the same reads and can be compared across versions.
+
+use std::time::{Duration, Instant};
+
+use super::{Amount, Error, MoneroDB};
+
+/// Settings shared by all benchmarks
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BenchOptions {
+    /// Number of operations each benchmark performs
+    pub iterations: u64,
+    /// Seed for the random inputs
+    pub seed: u64,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        BenchOptions {
+            iterations: 10_000,
+            seed: 0x6d6f6e65726f,
+        }
+    }
+}
+
+/// Timing of a single benchmark
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BenchResult {
+    /// Name of the benchmark
+    pub name: &'static str,
+    /// Number of operations performed
+    pub iterations: u64,
+    /// Time taken by all operations
+    pub total: Duration,
+}
+
+impl BenchResult {
+    /// Gets the average time per operation
+    ///
+    pub fn per_op(&self) -> Duration {
+        let nanos = self.total.as_nanos() / u128::from(self.iterations.max(1));
+        Duration::from_nanos(nanos as u64)
+    }
+}
+
+/// xorshift64*, good enough to spread reads over the database
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.max(1))
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545f4914f6cdd1d) % bound.max(1)
+    }
+}
+
+fn time<F>(name: &'static str, iterations: u64, mut op: F) -> Result<BenchResult, Error>
+where
+    F: FnMut() -> Result<(), Error>,
+{
+    let start = Instant::now();
+    for _ in 0..iterations {
+        op()?;
+    }
+    Ok(BenchResult {
+        name,
+        iterations,
+        total: start.elapsed(),
+    })
+}
+
+/// Reads and decodes blocks at random heights
+///
+pub fn random_block_reads(db: &MoneroDB, options: BenchOptions) -> Result<BenchResult, Error> {
+    let height = db.get_blockchain_height()?;
+    let mut rng = Rng::new(options.seed);
+    time("random_block_reads", options.iterations, || {
+        db.get_block(rng.below(height)).map(drop)
+    })
+}
+
+/// Reads and decodes consecutive blocks and their block info from a random start
+///
+pub fn sequential_scan(db: &MoneroDB, options: BenchOptions) -> Result<BenchResult, Error> {
+    let height = db.get_blockchain_height()?;
+    let iterations = options.iterations.min(height);
+    let mut next = Rng::new(options.seed).below(height - iterations + 1);
+    time("sequential_scan", iterations, || {
+        db.get_block(next)?;
+        db.get_block_info(next)?;
+        next += 1;
+        Ok(())
+    })
+}
+
+/// Looks up random RCT outputs and the transactions that created them
+///
+pub fn output_lookups(db: &MoneroDB, options: BenchOptions) -> Result<BenchResult, Error> {
+    let top = db.get_blockchain_height()?.saturating_sub(1);
+    let rct_outputs = db.get_block_info(top)?.cumulative_rct_outs;
+    let mut rng = Rng::new(options.seed);
+    time("output_lookups", options.iterations, || {
+        let outkey = db.get_output_rct_outkey(Amount(0), rng.below(rct_outputs))?;
+        db.get_output_tx(outkey.output_id).map(drop)
+    })
+}
+
+/// Runs every benchmark with the same options
+///
+pub fn run_all(db: &MoneroDB, options: BenchOptions) -> Result<Vec<BenchResult>, Error> {
+    Ok(vec![
+        random_block_reads(db, options)?,
+        sequential_scan(db, options)?,
+        output_lookups(db, options)?,
+    ])
+}
@@ -0,0 +1,197 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Block info fields
+//!
+//! The `block_info` table is decoded here from its on disk layout rather than
+//! through monero-rs's `BlockInfo`, so callers don't depend on its field names.
+
+use monero::consensus::encode::Error::ParseFailed;
+use monero::Hash;
+
+use super::consensus::LONG_TERM_BLOCK_WEIGHT_WINDOW;
+use super::monero_db::read_item;
+use super::{BlockHeight, Error, MoneroDB, ZERO_KEY};
+
+/// Size of a version 5 `block_info` entry
+const BLOCK_INFO_SIZE: usize = 96;
+
+/// Gets the median of `values`, averaging the middle two values for even lengths
+/// like monerod does
+///
+fn median(values: &mut [u64]) -> u64 {
+    if values.is_empty() {
+        return 0;
+    }
+    let mid = values.len() / 2;
+    let (low, upper, _) = values.select_nth_unstable(mid);
+    let upper = *upper;
+    if values.len() % 2 == 1 {
+        return upper;
+    }
+    let lower = *low.iter().max().unwrap();
+    lower / 2 + upper / 2 + (lower % 2 + upper % 2) / 2
+}
+
+/// The `block_info` entry of a block
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BlockMetadata {
+    /// Height of the block
+    pub height: u64,
+    /// Block timestamp
+    pub timestamp: u64,
+    /// Coins emitted up to and including the block
+    pub already_generated_coins: u64,
+    /// Block weight
+    pub weight: u64,
+    /// Cumulative difficulty of the chain up to the block
+    pub cumulative_difficulty: u128,
+    /// Hash of the block
+    #[cfg_attr(
+        feature = "serde",
+        serde(serialize_with = "crate::json::serialize_hash")
+    )]
+    pub hash: Hash,
+    /// Number of RCT outputs up to and including the block
+    pub cumulative_rct_outputs: u64,
+    /// Long term weight of the block, used for the long term median from hard fork 10
+    pub long_term_weight: u64,
+}
+
+pub(crate) fn short_entry(key: &[u8]) -> Error {
+    Error::Corrupt {
+        db: "block_info",
+        key: key.to_vec(),
+        source: ParseFailed("block info entry too short"),
+    }
+}
+
+impl BlockMetadata {
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < BLOCK_INFO_SIZE {
+            return None;
+        }
+        let u64_at = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+        Some(BlockMetadata {
+            height: u64_at(0),
+            timestamp: u64_at(8),
+            already_generated_coins: u64_at(16),
+            weight: u64_at(24),
+            cumulative_difficulty: u128::from(u64_at(32)) | u128::from(u64_at(40)) << 64,
+            hash: Hash::from_slice(&bytes[48..80]),
+            cumulative_rct_outputs: u64_at(80),
+            long_term_weight: u64_at(88),
+        })
+    }
+}
+
+impl MoneroDB {
+    /// Gets the [`BlockMetadata`] of the block at `block_height`
+    ///
+    pub fn get_block_metadata(
+        &self,
+        block_height: impl Into<BlockHeight>,
+    ) -> Result<BlockMetadata, Error> {
+        let key = block_height.into().to_le_bytes();
+        read_item(
+            self,
+            self.sub_dbs.block_info,
+            &ZERO_KEY,
+            &key,
+            2,
+            BlockMetadata::from_bytes,
+        )?
+        .ok_or_else(|| short_entry(&key))
+    }
+
+    /// Gets the hash of the block at `block_height`
+    ///
+    pub fn get_block_hash(&self, block_height: impl Into<BlockHeight>) -> Result<Hash, Error> {
+        Ok(self.get_block_metadata(block_height)?.hash)
+    }
+
+    /// Gets the timestamp of the block at `block_height`
+    ///
+    pub fn get_block_timestamp(&self, block_height: impl Into<BlockHeight>) -> Result<u64, Error> {
+        Ok(self.get_block_metadata(block_height)?.timestamp)
+    }
+
+    /// Gets the weight of the block at `block_height`
+    ///
+    pub fn get_block_weight(&self, block_height: impl Into<BlockHeight>) -> Result<u64, Error> {
+        Ok(self.get_block_metadata(block_height)?.weight)
+    }
+
+    /// Gets the long term weight of the block at `block_height`
+    ///
+    pub fn get_block_long_term_weight(
+        &self,
+        block_height: impl Into<BlockHeight>,
+    ) -> Result<u64, Error> {
+        Ok(self.get_block_metadata(block_height)?.long_term_weight)
+    }
+
+    /// Gets the coins emitted up to and including the block at `block_height`
+    ///
+    pub fn get_block_already_generated_coins(
+        &self,
+        block_height: impl Into<BlockHeight>,
+    ) -> Result<u64, Error> {
+        Ok(self
+            .get_block_metadata(block_height)?
+            .already_generated_coins)
+    }
+
+    /// Gets the cumulative difficulty of the chain up to the block at `block_height`
+    ///
+    pub fn get_block_cumulative_difficulty(
+        &self,
+        block_height: impl Into<BlockHeight>,
+    ) -> Result<u128, Error> {
+        Ok(self.get_block_metadata(block_height)?.cumulative_difficulty)
+    }
+
+    /// Gets the number of RCT outputs up to and including the block at `block_height`
+    ///
+    pub fn get_block_cumulative_rct_outputs(
+        &self,
+        block_height: impl Into<BlockHeight>,
+    ) -> Result<u64, Error> {
+        Ok(self
+            .get_block_metadata(block_height)?
+            .cumulative_rct_outputs)
+    }
+
+    /// Gets the median of the long term weights of the up to
+    /// [`LONG_TERM_BLOCK_WEIGHT_WINDOW`] blocks ending with the block at `height`.
+    ///
+    /// From hard fork 10 this is the long term median the next block is checked
+    /// against, before applying the 300000 byte minimum of the full reward zone.
+    ///
+    pub fn get_long_term_weight_median(
+        &self,
+        height: impl Into<BlockHeight>,
+    ) -> Result<u64, Error> {
+        let height = height.into().get();
+        let start = (height + 1).saturating_sub(LONG_TERM_BLOCK_WEIGHT_WINDOW);
+        let mut weights = Vec::with_capacity((height + 1 - start) as usize);
+        self.for_each_raw_block_info(start..height + 1, |value| {
+            let metadata = BlockMetadata::from_bytes(value)
+                .ok_or_else(|| short_entry(value.get(..8).unwrap_or_default()))?;
+            weights.push(metadata.long_term_weight);
+            Ok(())
+        })?;
+        Ok(median(&mut weights))
+    }
+}
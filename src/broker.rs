@@ -0,0 +1,117 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Publishing chain events to a NATS server
+//!
+//! A [`BrokerPublisher`] is an [`IndexHandler`], registered with an
+//! [`Indexer`](crate::Indexer) it publishes every block added or popped and every
+//! new pool transaction under a subject prefix:
+//!
+//! - `<prefix>.block` for blocks added to the main chain
+//! - `<prefix>.block_popped` for blocks removed by a reorganization
+//! - `<prefix>.txpool_add` for new pool transactions
+
+use monero::consensus::serialize;
+use monero::cryptonote::hash::Hashable;
+use monero::{Block, Hash};
+use serde_json::json;
+
+use super::analysis::tx_fee;
+use super::indexer::IndexHandler;
+use super::Error;
+
+/// Encoding of published events
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventFormat {
+    /// JSON objects modelled on monerod's `json-minimal` ZMQ notifications
+    Json,
+    /// The consensus serialized block or transaction, popped blocks are sent as
+    /// their hash
+    Binary,
+}
+
+/// Publishes chain events to a NATS server
+///
+pub struct BrokerPublisher {
+    connection: nats::Connection,
+    prefix: String,
+    format: EventFormat,
+}
+
+impl BrokerPublisher {
+    /// Connects to the NATS server at `url`, publishing under `prefix`
+    ///
+    pub fn connect(url: &str, prefix: &str, format: EventFormat) -> Result<Self, Error> {
+        let connection = nats::connect(url).map_err(|e| Error::NotifyError(e.to_string()))?;
+        Ok(BrokerPublisher {
+            connection,
+            prefix: prefix.to_string(),
+            format,
+        })
+    }
+
+    fn publish(&self, topic: &str, body: &[u8]) -> Result<(), Error> {
+        self.connection
+            .publish(&format!("{}.{}", self.prefix, topic), body)
+            .map_err(|e| Error::NotifyError(e.to_string()))
+    }
+}
+
+impl IndexHandler for BrokerPublisher {
+    fn on_block_added(&mut self, height: u64, block: &Block) -> Result<(), Error> {
+        let body = match self.format {
+            EventFormat::Json => json!({
+                "height": height,
+                "id": hex::encode(block.id().as_bytes()),
+                "prev_id": hex::encode(block.header.prev_id.as_bytes()),
+                "tx_hashes": block
+                    .tx_hashes
+                    .iter()
+                    .map(|tx_hash| hex::encode(tx_hash.as_bytes()))
+                    .collect::<Vec<_>>(),
+            })
+            .to_string()
+            .into_bytes(),
+            EventFormat::Binary => serialize(block),
+        };
+        self.publish("block", &body)
+    }
+
+    fn on_block_popped(&mut self, height: u64, block_hash: &Hash) -> Result<(), Error> {
+        let body = match self.format {
+            EventFormat::Json => json!({
+                "height": height,
+                "id": hex::encode(block_hash.as_bytes()),
+            })
+            .to_string()
+            .into_bytes(),
+            EventFormat::Binary => block_hash.as_bytes().to_vec(),
+        };
+        self.publish("block_popped", &body)
+    }
+
+    fn on_pool_tx(&mut self, tx_hash: &Hash, tx: &monero::Transaction) -> Result<(), Error> {
+        let blob = serialize(tx);
+        let body = match self.format {
+            EventFormat::Json => json!([{
+                "id": hex::encode(tx_hash.as_bytes()),
+                "blob_size": blob.len(),
+                "fee": tx_fee(&tx.prefix, &tx.rct_signatures),
+            }])
+            .to_string()
+            .into_bytes(),
+            EventFormat::Binary => blob,
+        };
+        self.publish("txpool_add", &body)
+    }
+}
@@ -0,0 +1,78 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Cancelling long running operations
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use super::{Error, MoneroDB};
+
+/// Cancels the scans, audits and exports of the handles it is set on with
+/// [`ScanOptions::cancel_handle`], clones share the same state.
+///
+/// Cancelled operations stop at the next block they reach and fail with
+/// [`Error::Cancelled`], holding the height below which every block was processed.
+/// What was handed to progress callbacks, checkpoints and sinks before then stays
+/// valid, so the operation can be resumed from that height.
+///
+/// [`ScanOptions::cancel_handle`]: crate::ScanOptions::cancel_handle
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelHandle {
+    /// Creates a handle that is not cancelled
+    ///
+    pub fn new() -> Self {
+        CancelHandle::default()
+    }
+
+    /// Cancels the running operations and every operation started until
+    /// [`CancelHandle::reset`] is called
+    ///
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns if [`CancelHandle::cancel`] was called
+    ///
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Clears the cancellation so new operations run again
+    ///
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::Relaxed);
+    }
+}
+
+impl PartialEq for CancelHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.cancelled, &other.cancelled)
+    }
+}
+
+impl Eq for CancelHandle {}
+
+impl MoneroDB {
+    /// Fails with [`Error::Cancelled`] if the operations of this handle were
+    /// cancelled, `height` is the height processed up to, exclusive
+    pub(crate) fn check_cancelled(&self, height: u64) -> Result<(), Error> {
+        if self.options.scan.is_cancelled() {
+            return Err(Error::Cancelled { height });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Block hash checkpoints
+//!
+//! Checkpoints use the same `checkpoints.json` format as monerod.
+
+use monero::Hash;
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+#[cfg(feature = "serde")]
+use std::path::Path;
+
+use super::{Error, MoneroDB};
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct HashLine {
+    height: u64,
+    hash: String,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct HashLines {
+    hashlines: Vec<HashLine>,
+}
+
+/// A set of block hashes the chain must contain at the given heights
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Checkpoints {
+    points: BTreeMap<u64, Hash>,
+}
+
+impl Checkpoints {
+    /// Parses checkpoints in the `checkpoints.json` format, enabled with the
+    /// `serde` feature
+    ///
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        let lines: HashLines = serde_json::from_str(json)?;
+        let mut checkpoints = Checkpoints::default();
+        for line in lines.hashlines {
+            let bytes = hex::decode(&line.hash)
+                .ok()
+                .filter(|bytes| bytes.len() == 32)
+                .ok_or_else(|| Error::InvalidCheckpoint(line.hash.clone()))?;
+            checkpoints.add(line.height, Hash::from_slice(&bytes))?;
+        }
+        Ok(checkpoints)
+    }
+
+    /// Loads checkpoints from a `checkpoints.json` file, enabled with the `serde`
+    /// feature
+    ///
+    #[cfg(feature = "serde")]
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Checkpoints::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    /// Adds a checkpoint, fails if a different hash is already checkpointed at the height
+    ///
+    pub fn add(&mut self, height: u64, hash: Hash) -> Result<(), Error> {
+        match self.points.entry(height) {
+            Entry::Occupied(existing) if *existing.get() != hash => Err(Error::InvalidCheckpoint(
+                format!("conflicting hashes at height {}", height),
+            )),
+            Entry::Occupied(_) => Ok(()),
+            Entry::Vacant(entry) => {
+                entry.insert(hash);
+                Ok(())
+            }
+        }
+    }
+
+    /// Gets the checkpointed hash at `height`
+    ///
+    pub fn get(&self, height: u64) -> Option<&Hash> {
+        self.points.get(&height)
+    }
+
+    /// Gets the height of the highest checkpoint
+    ///
+    pub fn highest(&self) -> Option<u64> {
+        self.points.keys().next_back().copied()
+    }
+
+    /// Returns if `height` is at or below the highest checkpoint
+    ///
+    pub fn is_in_checkpoint_zone(&self, height: u64) -> bool {
+        self.highest().map_or(false, |highest| height <= highest)
+    }
+
+    /// Iterates over the checkpoints in height order
+    ///
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &Hash)> {
+        self.points.iter().map(|(height, hash)| (*height, hash))
+    }
+}
+
+impl MoneroDB {
+    /// Sets the checkpoints enforced by this database handle
+    ///
+    pub fn set_checkpoints(&mut self, checkpoints: Checkpoints) {
+        self.checkpoints = checkpoints;
+    }
+
+    /// Gets the checkpoints enforced by this database handle
+    ///
+    pub fn checkpoints(&self) -> &Checkpoints {
+        &self.checkpoints
+    }
+
+    /// Checks every checkpoint below the blockchain height against the stored
+    /// block hashes, returning the height of the first mismatch
+    ///
+    pub fn verify_checkpoints(&self) -> Result<Option<u64>, Error> {
+        let blockchain_height = self.get_blockchain_height()?;
+        for (height, hash) in self.checkpoints.iter() {
+            if height >= blockchain_height {
+                break;
+            }
+            if self.get_block_info(height)?.block_hash != *hash {
+                return Ok(Some(height));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Checks if the block at `height` may be popped or overwritten, refusing changes
+    /// at or below the highest checkpoint like monerod does. Every write path that
+    /// removes or replaces blocks calls this first.
+    ///
+    pub fn check_block_modifiable(&self, height: u64) -> Result<(), Error> {
+        match self.checkpoints.highest() {
+            Some(checkpoint) if height <= checkpoint => {
+                Err(Error::BelowCheckpoint { height, checkpoint })
+            }
+            _ => Ok(()),
+        }
+    }
+}
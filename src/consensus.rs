@@ -0,0 +1,186 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Consensus constants, from monerod's `cryptonote_config.h` and `hardforks.cpp`
+//!
+//! Rules that differ between networks, such as the hard fork heights, take the
+//! [`Network`] they apply to.
+
+use monero::{Hash, Network};
+
+use super::hard_fork::HardForkHeight;
+
+/// Unlock times below this are block heights, above are timestamps
+pub const MAX_BLOCK_NUMBER: u64 = 500_000_000;
+/// Number of blocks a normal output has to wait before it can be spent or used as
+/// a decoy
+pub const DEFAULT_TX_SPENDABLE_AGE: u64 = 10;
+/// Number of blocks a miner output has to wait before it can be spent
+pub const MINED_MONEY_UNLOCK_WINDOW: u64 = 60;
+/// Number of blocks an unlock height may be ahead of the chain
+pub const LOCKED_TX_ALLOWED_DELTA_BLOCKS: u64 = 1;
+/// Number of seconds an unlock timestamp may be ahead of the current time
+pub const LOCKED_TX_ALLOWED_DELTA_SECONDS: u64 = 120 * LOCKED_TX_ALLOWED_DELTA_BLOCKS;
+
+/// Total number of atomic units that will ever be emitted before tail emission
+pub const MONEY_SUPPLY: u64 = u64::MAX;
+/// Tail emission per minute of target block time
+pub const FINAL_SUBSIDY_PER_MINUTE: u64 = 300_000_000_000;
+/// Emission speed factor for one minute blocks, lowered by one per extra minute
+pub const EMISSION_SPEED_FACTOR_PER_MINUTE: u64 = 20;
+
+/// Number of blocks whose weights make up the short term median
+pub const REWARD_BLOCKS_WINDOW: u64 = 100;
+/// Number of blocks whose long term weights make up the long term median
+pub const LONG_TERM_BLOCK_WEIGHT_WINDOW: u64 = 100000;
+/// How far the short term median may exceed the long term median, from hard fork 10
+pub const SHORT_TERM_BLOCK_WEIGHT_SURGE_FACTOR: u64 = 50;
+/// Weight kept free for the miner transaction when filling a block
+pub const COINBASE_BLOB_RESERVED_SIZE: u64 = 600;
+
+/// Target block time, in seconds, from hard fork 2 onwards
+pub const DIFFICULTY_TARGET_V2: u64 = 120;
+/// Target block time, in seconds, before hard fork 2
+pub const DIFFICULTY_TARGET_V1: u64 = 60;
+/// Number of blocks the difficulty is computed from
+pub const DIFFICULTY_WINDOW: usize = 720;
+/// Number of most recent blocks left out of the difficulty window
+pub const DIFFICULTY_LAG: usize = 15;
+/// Number of outlying timestamps cut from each end of the difficulty window
+pub const DIFFICULTY_CUT: usize = 60;
+/// Number of blocks used to compute the median time past
+pub const TIMESTAMP_CHECK_WINDOW: u64 = 60;
+
+/// First major version hashed with RandomX
+pub const RANDOMX_MAJOR_VERSION: u64 = 12;
+/// Number of blocks between RandomX seed changes
+pub const SEEDHASH_EPOCH_BLOCKS: u64 = 2048;
+/// Number of blocks a new seed is delayed by
+pub const SEEDHASH_EPOCH_LAG: u64 = 64;
+
+/// Number of blocks the daemon counts hard fork votes over
+pub const HF_VOTING_WINDOW: u64 = 10080;
+
+/// Gets the target block time in seconds for a hard fork version
+///
+pub fn difficulty_target(hf_version: u8) -> u64 {
+    if hf_version >= 2 {
+        DIFFICULTY_TARGET_V2
+    } else {
+        DIFFICULTY_TARGET_V1
+    }
+}
+
+/// Gets the weight a block may have without being penalized, for a hard fork version
+///
+pub fn full_reward_zone(hf_version: u8) -> u64 {
+    match hf_version {
+        0 | 1 => 20_000,
+        2..=4 => 60_000,
+        _ => 300_000,
+    }
+}
+
+/// Gets the id of the genesis block of a network, as hardcoded in monerod
+///
+pub fn genesis_hash(network: Network) -> Hash {
+    let id = match network {
+        Network::Mainnet => "418015bb9ae982a1975da7d79277c2705727a56894ba0fb246adaabb1f4632e3",
+        Network::Testnet => "48ca7cd3c8de5b6a4d53d2861fbdaedca141553559f9be9520068053cda8430b",
+        Network::Stagenet => "76ee3cc98646292206cd3e86f74d88b4dcc1d937088645e9b0cbca84b7ce74eb",
+    };
+    Hash::from_slice(&hex::decode(id).unwrap())
+}
+
+/// Gets the nonce of the genesis block of a network
+///
+pub fn genesis_nonce(network: Network) -> u32 {
+    match network {
+        Network::Mainnet => 10000,
+        Network::Testnet => 10001,
+        Network::Stagenet => 10002,
+    }
+}
+
+const fn fork(version: u8, height: u64) -> HardForkHeight {
+    HardForkHeight { version, height }
+}
+
+/// Activation heights of the mainnet hard forks, from monerod's `hardforks.cpp`
+const MAINNET_HARD_FORKS: [HardForkHeight; 16] = [
+    fork(1, 0),
+    fork(2, 1009827),
+    fork(3, 1141317),
+    fork(4, 1220516),
+    fork(5, 1288616),
+    fork(6, 1400000),
+    fork(7, 1546000),
+    fork(8, 1685555),
+    fork(9, 1686275),
+    fork(10, 1788000),
+    fork(11, 1788720),
+    fork(12, 1978433),
+    fork(13, 2210000),
+    fork(14, 2210720),
+    fork(15, 2688888),
+    fork(16, 2689608),
+];
+
+/// Activation heights of the testnet hard forks
+const TESTNET_HARD_FORKS: [HardForkHeight; 16] = [
+    fork(1, 0),
+    fork(2, 624634),
+    fork(3, 800500),
+    fork(4, 801219),
+    fork(5, 802660),
+    fork(6, 971400),
+    fork(7, 1057027),
+    fork(8, 1057058),
+    fork(9, 1057778),
+    fork(10, 1154318),
+    fork(11, 1155038),
+    fork(12, 1308737),
+    fork(13, 1543939),
+    fork(14, 1544659),
+    fork(15, 1982800),
+    fork(16, 1983520),
+];
+
+/// Activation heights of the stagenet hard forks
+const STAGENET_HARD_FORKS: [HardForkHeight; 16] = [
+    fork(1, 0),
+    fork(2, 32000),
+    fork(3, 33000),
+    fork(4, 34000),
+    fork(5, 35000),
+    fork(6, 36000),
+    fork(7, 37000),
+    fork(8, 176456),
+    fork(9, 177176),
+    fork(10, 269000),
+    fork(11, 269720),
+    fork(12, 454721),
+    fork(13, 675405),
+    fork(14, 676125),
+    fork(15, 1151000),
+    fork(16, 1151720),
+];
+
+/// Gets the scheduled hard forks of a network, oldest first
+///
+pub fn hard_forks(network: Network) -> &'static [HardForkHeight] {
+    match network {
+        Network::Mainnet => &MAINNET_HARD_FORKS,
+        Network::Testnet => &TESTNET_HARD_FORKS,
+        Network::Stagenet => &STAGENET_HARD_FORKS,
+    }
+}
@@ -0,0 +1,150 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Decoy selection
+//!
+//! Selection of pre-RCT ring members the way wallet2 picked them before RingCT:
+//! half of the decoys come from outputs created in the last 1.8 days and the rest
+//! follow a triangular distribution favouring newer outputs. Randomness is supplied
+//! by the caller, it must come from a secure source when building transactions.
+
+use std::collections::BTreeSet;
+
+use super::consensus::DEFAULT_TX_SPENDABLE_AGE;
+use super::monero_db::read_item;
+use super::{Amount, Error, MoneroDB};
+
+/// How far back outputs count as recent, 1.8 days
+const RECENT_OUTPUT_ZONE: u64 = 18 * 86400 / 10;
+/// Share of the decoys picked from the recent outputs
+const RECENT_OUTPUT_RATIO: f64 = 0.5;
+
+/// Picks an index in `0..count` from a triangular distribution favouring higher indices
+///
+fn triangular(rng: &mut impl FnMut() -> u64, count: u64) -> u64 {
+    let r = rng() % (1 << 53);
+    let frac = (r as f64 / (1u64 << 53) as f64).sqrt();
+    ((frac * count as f64) as u64).min(count - 1)
+}
+
+impl MoneroDB {
+    /// Selects `count` decoys for a pre-RCT input spending the output at
+    /// `real_amount_index` among the outputs of `amount`, returning their sorted
+    /// amount indices without the real one.
+    ///
+    /// `rng` must return uniformly distributed `u64`s.
+    ///
+    pub fn select_legacy_decoys(
+        &self,
+        amount: impl Into<Amount>,
+        real_amount_index: u64,
+        count: usize,
+        mut rng: impl FnMut() -> u64,
+    ) -> Result<Vec<u64>, Error> {
+        let amount = amount.into();
+        let height = self.get_blockchain_height()?;
+        let top = height.saturating_sub(1);
+
+        // only outputs old enough to be spent can be used
+        let total = self.get_num_outputs(amount)?;
+        let num_outs = self.first_output_at(
+            amount,
+            height.saturating_sub(DEFAULT_TX_SPENDABLE_AGE),
+            total,
+        )?;
+        if num_outs <= count as u64 {
+            return Err(Error::NotEnoughOutputs {
+                amount: amount.0,
+                available: num_outs.saturating_sub(1),
+                requested: count,
+            });
+        }
+
+        let recent_cutoff = self
+            .get_block_info(top)?
+            .timestamp
+            .saturating_sub(RECENT_OUTPUT_ZONE);
+        let mut recent_height = top;
+        while recent_height > 0
+            && self.get_block_info(recent_height - 1)?.timestamp >= recent_cutoff
+        {
+            recent_height -= 1;
+        }
+        let num_recent_outs = num_outs - self.first_output_at(amount, recent_height, num_outs)?;
+        // as wallet2 does, never ask for more recent decoys than there are recent outputs
+        let usable_recent_outs = if real_amount_index >= num_outs - num_recent_outs {
+            num_recent_outs.saturating_sub(1)
+        } else {
+            num_recent_outs
+        };
+        let recent_count =
+            ((count as f64 * RECENT_OUTPUT_RATIO) as u64).min(usable_recent_outs) as usize;
+
+        let mut decoys = BTreeSet::new();
+        while decoys.len() < count {
+            let index = if decoys.len() < recent_count && num_recent_outs > 0 {
+                num_outs - num_recent_outs + triangular(&mut rng, num_recent_outs)
+            } else {
+                triangular(&mut rng, num_outs)
+            };
+            if index != real_amount_index {
+                decoys.insert(index);
+            }
+        }
+        Ok(decoys.into_iter().collect())
+    }
+
+    /// Finds the lowest amount index below `num_outs` whose output is at or above `height`
+    fn first_output_at(&self, amount: Amount, height: u64, num_outs: u64) -> Result<u64, Error> {
+        let (mut low, mut high) = (0, num_outs);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let output_height = read_item(
+                self,
+                self.sub_dbs.output_amounts,
+                &amount.to_le_bytes(),
+                &mid.to_le_bytes(),
+                2,
+                |outkey| {
+                    outkey
+                        .get(56..64)
+                        .map(|h| u64::from_le_bytes(h.try_into().unwrap()))
+                },
+            )?
+            .ok_or(Error::InvalidOutkey {
+                amount: amount.0,
+                amount_index: mid,
+            })?;
+            if output_height < height {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        Ok(low)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::triangular;
+
+    #[test]
+    fn triangular_picks() {
+        // wallet2 maps r to sqrt(r / 2^53) * count
+        assert_eq!(triangular(&mut || 0, 100), 0);
+        assert_eq!(triangular(&mut || 1 << 51, 100), 50);
+        assert_eq!(triangular(&mut || (1 << 53) - 1, 100), 99);
+        // only the low 53 bits are used
+        assert_eq!(triangular(&mut || (1 << 53) | (1 << 51), 100), 50);
+    }
+}
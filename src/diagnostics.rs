@@ -0,0 +1,104 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Explaining why a database could not be opened
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Why a database could not be opened, see [`Error::OpenError`](crate::Error::OpenError)
+///
+#[derive(Error, Debug)]
+pub enum OpenError {
+    /// The directory does not exist
+    #[error("{} does not exist", .0.display())]
+    DirectoryNotFound(PathBuf),
+    /// The directory has no `data.mdb`, monerod keeps it in the `lmdb` subdirectory
+    /// of its data directory
+    #[error("{} has no data.mdb", .0.display())]
+    MissingDataFile(PathBuf),
+    /// The path is a wallet file or a directory of wallets rather than a blockchain
+    /// database
+    #[error("{} looks like a wallet cache, not a blockchain database", .0.display())]
+    WalletCache(PathBuf),
+    /// The database files can not be read, or when opening read-write the lock file
+    /// can not be written
+    #[error("Permission denied opening {}", .path.display())]
+    PermissionDenied {
+        /// Directory of the database
+        path: PathBuf,
+        /// If the database was opened read only, which does not use the lock file
+        read_only: bool,
+    },
+    /// The lock file is held in a way that prevents opening the database
+    #[error("Lock file of {} is held by another process", .0.display())]
+    Locked(PathBuf),
+    /// `data.mdb` is not an LMDB environment or was written by an incompatible
+    /// LMDB version
+    #[error("{} is not a usable LMDB environment: {1:?}", .0.display())]
+    NotLmdb(PathBuf, lmdb::Error),
+    /// A table of the monerod schema is missing, the database is incomplete or
+    /// belongs to another program
+    #[error("Table {0} is missing")]
+    MissingTable(&'static str),
+}
+
+/// Checks `dir` holds an LMDB environment before LMDB is asked to open it, as
+/// opening read-write would create an empty one
+pub(crate) fn check_data_dir(dir: &Path) -> Result<(), OpenError> {
+    if !dir.exists() {
+        return Err(OpenError::DirectoryNotFound(dir.to_path_buf()));
+    }
+    if dir.join("data.mdb").is_file() {
+        return Ok(());
+    }
+    if is_wallet(dir) {
+        return Err(OpenError::WalletCache(dir.to_path_buf()));
+    }
+    Err(OpenError::MissingDataFile(dir.to_path_buf()))
+}
+
+/// Explains an error LMDB returned opening the environment in `dir`
+pub(crate) fn env_error(dir: &Path, read_only: bool, e: lmdb::Error) -> crate::Error {
+    let path = dir.to_path_buf();
+    let open_error = match e {
+        lmdb::Error::Invalid | lmdb::Error::VersionMismatch | lmdb::Error::Corrupted => {
+            OpenError::NotLmdb(path, e)
+        }
+        lmdb::Error::Other(code) => match io::Error::from_raw_os_error(code).kind() {
+            io::ErrorKind::PermissionDenied => OpenError::PermissionDenied { path, read_only },
+            io::ErrorKind::WouldBlock => OpenError::Locked(path),
+            io::ErrorKind::NotFound => OpenError::MissingDataFile(path),
+            _ => return e.into(),
+        },
+        e => return e.into(),
+    };
+    open_error.into()
+}
+
+/// Returns if `path` is a wallet file, one with a matching `.keys` file, or a
+/// directory holding wallet key files
+fn is_wallet(path: &Path) -> bool {
+    let has_keys_extension = |path: &Path| path.extension().map_or(false, |ext| ext == "keys");
+    if path.is_file() {
+        let mut keys = path.as_os_str().to_owned();
+        keys.push(".keys");
+        return has_keys_extension(path) || Path::new(&keys).is_file();
+    }
+    fs::read_dir(path).map_or(false, |entries| {
+        entries
+            .flatten()
+            .any(|entry| has_keys_extension(&entry.path()))
+    })
+}
@@ -0,0 +1,103 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Comparing two databases
+
+use monero::Hash;
+use std::ops::Range;
+
+use super::monero_db::get_raw_item;
+use super::{Error, MoneroDB, ZERO_KEY};
+
+/// Differences found between two databases by [`MoneroDB::diff`]
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DbDiff {
+    /// Blockchain heights of this and the other database
+    pub blockchain_heights: (u64, u64),
+    /// Transaction counts of this and the other database
+    pub tx_counts: (u64, u64),
+    /// Number of heights compared
+    pub compared_blocks: u64,
+    /// Number of heights where the block or its block info differ
+    pub differing_blocks: u64,
+    /// Number of transactions that differ or are missing from the other database
+    pub differing_txs: u64,
+    /// Lowest height at which the databases differ
+    pub first_divergent_height: Option<u64>,
+    /// First transaction that differs or is missing from the other database
+    pub first_divergent_tx: Option<Hash>,
+}
+
+impl DbDiff {
+    /// Returns if no differences were found
+    ///
+    pub fn is_identical(&self) -> bool {
+        self.differing_blocks == 0
+            && self.differing_txs == 0
+            && self.blockchain_heights.0 == self.blockchain_heights.1
+    }
+}
+
+impl MoneroDB {
+    /// Compares the blocks, block info and transactions in `range` with `other`,
+    /// reporting the first divergent height and transaction plus summary counts.
+    ///
+    /// Heights above the top of either chain are not compared.
+    ///
+    pub fn diff(&self, other: &MoneroDB, range: Range<u64>) -> Result<DbDiff, Error> {
+        let mut diff = DbDiff {
+            blockchain_heights: (
+                self.get_blockchain_height()?,
+                other.get_blockchain_height()?,
+            ),
+            tx_counts: (self.get_tx_count()?, other.get_tx_count()?),
+            ..Default::default()
+        };
+        let end = range
+            .end
+            .min(diff.blockchain_heights.0)
+            .min(diff.blockchain_heights.1);
+
+        for height in range.start..end {
+            diff.compared_blocks += 1;
+            let key = height.to_le_bytes();
+            let block_differs = get_raw_item(self, self.sub_dbs.blocks, &key, &[0], 15)?
+                != get_raw_item(other, other.sub_dbs.blocks, &key, &[0], 15)?
+                || get_raw_item(self, self.sub_dbs.block_info, &ZERO_KEY, &key, 2)?
+                    != get_raw_item(other, other.sub_dbs.block_info, &ZERO_KEY, &key, 2)?;
+            if block_differs {
+                diff.differing_blocks += 1;
+                diff.first_divergent_height.get_or_insert(height);
+            }
+
+            for tx_hash in &self.get_block(height)?.tx_hashes {
+                let ours = raw_tx_pruned(self, tx_hash)?;
+                let theirs = match raw_tx_pruned(other, tx_hash) {
+                    Err(e) if e.is_not_found() => None,
+                    theirs => Some(theirs?),
+                };
+                if theirs.as_ref() != Some(&ours) {
+                    diff.differing_txs += 1;
+                    diff.first_divergent_height.get_or_insert(height);
+                    diff.first_divergent_tx.get_or_insert(*tx_hash);
+                }
+            }
+        }
+        Ok(diff)
+    }
+}
+
+fn raw_tx_pruned(db: &MoneroDB, tx_hash: &Hash) -> Result<Vec<u8>, Error> {
+    let tx_id = db.get_tx_indices(tx_hash)?.data.tx_id;
+    get_raw_item(db, db.sub_dbs.txs_pruned, &tx_id.to_le_bytes(), &[0], 15)
+}
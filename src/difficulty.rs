@@ -0,0 +1,190 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Difficulty calculation and verification
+
+use std::ops::Range;
+
+use super::block_info::{short_entry, BlockMetadata};
+use super::consensus::{difficulty_target, DIFFICULTY_CUT, DIFFICULTY_LAG, DIFFICULTY_WINDOW};
+use super::{Error, MoneroDB};
+
+/// Computes the difficulty of the next block from the timestamps and cumulative
+/// difficulties of the blocks before it, oldest first, as monerod's
+/// `next_difficulty`. Only the first 720 blocks given are used.
+///
+pub fn next_difficulty(
+    timestamps: &[u64],
+    cumulative_difficulties: &[u128],
+    target_seconds: u64,
+) -> u128 {
+    let length = timestamps
+        .len()
+        .min(cumulative_difficulties.len())
+        .min(DIFFICULTY_WINDOW);
+    if length <= 1 {
+        return 1;
+    }
+    let mut timestamps = timestamps[..length].to_vec();
+    timestamps.sort_unstable();
+
+    let (cut_begin, cut_end) = if length <= DIFFICULTY_WINDOW - 2 * DIFFICULTY_CUT {
+        (0, length)
+    } else {
+        let cut_begin = (length - (DIFFICULTY_WINDOW - 2 * DIFFICULTY_CUT) + 1) / 2;
+        (
+            cut_begin,
+            cut_begin + DIFFICULTY_WINDOW - 2 * DIFFICULTY_CUT,
+        )
+    };
+    let time_span = (timestamps[cut_end - 1] - timestamps[cut_begin]).max(1) as u128;
+    let total_work =
+        cumulative_difficulties[cut_end - 1].saturating_sub(cumulative_difficulties[cut_begin]);
+    match total_work.checked_mul(target_seconds as u128) {
+        Some(work) => (work + time_span - 1) / time_span,
+        None => 0,
+    }
+}
+
+/// A block whose difficulty does not follow from the blocks before it
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DifficultyMismatch {
+    /// Height of the block
+    pub height: u64,
+    /// Difficulty implied by the stored cumulative difficulties, `None` if the
+    /// cumulative difficulty decreased
+    pub stored: Option<u128>,
+    /// Difficulty computed from the previous blocks
+    pub expected: u128,
+}
+
+/// Gets the height of the first block whose timestamp and cumulative difficulty are
+/// used for the difficulty of the block at `height`. As in monerod the genesis block
+/// is never part of the window.
+fn window_start(height: u64) -> u64 {
+    height
+        .saturating_sub((DIFFICULTY_WINDOW + DIFFICULTY_LAG) as u64)
+        .max(1)
+        .min(height)
+}
+
+impl MoneroDB {
+    /// Computes the difficulty the next block on top of the chain must meet
+    ///
+    pub fn get_next_difficulty(&self) -> Result<u128, Error> {
+        let height = self.get_blockchain_height()?;
+        if height == 0 {
+            return Ok(1);
+        }
+        let first = window_start(height);
+        let mut timestamps = Vec::new();
+        let mut cumulative_difficulties = Vec::new();
+        self.for_each_raw_block_info(first..height, |value| {
+            let info = BlockMetadata::from_bytes(value)
+                .ok_or_else(|| short_entry(&(first + timestamps.len() as u64).to_le_bytes()))?;
+            timestamps.push(info.timestamp);
+            cumulative_difficulties.push(info.cumulative_difficulty);
+            Ok(())
+        })?;
+        let target = difficulty_target(self.get_hf_version(height - 1)?);
+        Ok(next_difficulty(
+            &timestamps,
+            &cumulative_difficulties,
+            target,
+        ))
+    }
+
+    /// Recomputes the difficulty of every block in `range` from the timestamps and
+    /// cumulative difficulties of the blocks before it and checks it against the
+    /// stored cumulative difficulty, returning the blocks that do not match.
+    ///
+    /// The difficulty target is taken from the hard fork version of each block.
+    ///
+    pub fn verify_cumulative_difficulty(
+        &self,
+        range: Range<u64>,
+    ) -> Result<Vec<DifficultyMismatch>, Error> {
+        let range = range.start..range.end.min(self.get_blockchain_height()?);
+        let first = range
+            .start
+            .saturating_sub((DIFFICULTY_WINDOW + DIFFICULTY_LAG) as u64);
+        let mut timestamps = Vec::new();
+        let mut cumulative_difficulties = Vec::new();
+        self.for_each_raw_block_info(first..range.end, |value| {
+            let height = first + timestamps.len() as u64;
+            let info = BlockMetadata::from_bytes(value)
+                .ok_or_else(|| short_entry(&height.to_le_bytes()))?;
+            timestamps.push(info.timestamp);
+            cumulative_difficulties.push(info.cumulative_difficulty);
+            Ok(())
+        })?;
+
+        let mut mismatches = Vec::new();
+        for height in range {
+            self.check_cancelled(height)?;
+            let index = (height - first) as usize;
+            let window = (window_start(height) - first) as usize..index;
+            let target = difficulty_target(self.get_hf_version(height)?);
+            let expected = next_difficulty(
+                &timestamps[window.clone()],
+                &cumulative_difficulties[window],
+                target,
+            );
+            let stored = match index {
+                0 => Some(cumulative_difficulties[0]),
+                _ => cumulative_difficulties[index].checked_sub(cumulative_difficulties[index - 1]),
+            };
+            if stored != Some(expected) {
+                mismatches.push(DifficultyMismatch {
+                    height,
+                    stored,
+                    expected,
+                });
+            }
+        }
+        Ok(mismatches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{next_difficulty, window_start};
+
+    #[test]
+    fn steady_chain_keeps_difficulty() {
+        let difficulty = 300_000_000_000u128;
+        let timestamps: Vec<u64> = (0..735).map(|i| 1_600_000_000 + i * 120).collect();
+        let cumulative: Vec<u128> = (1..=735).map(|i| i * difficulty).collect();
+        assert_eq!(next_difficulty(&timestamps, &cumulative, 120), difficulty);
+        assert_eq!(
+            next_difficulty(&timestamps[..10], &cumulative[..10], 120),
+            difficulty
+        );
+    }
+
+    #[test]
+    fn short_windows() {
+        assert_eq!(next_difficulty(&[], &[], 120), 1);
+        assert_eq!(next_difficulty(&[5], &[1], 120), 1);
+    }
+
+    #[test]
+    fn window_skips_genesis() {
+        assert_eq!(window_start(0), 0);
+        assert_eq!(window_start(1), 1);
+        assert_eq!(window_start(735), 1);
+        assert_eq!(window_start(736), 1);
+        assert_eq!(window_start(737), 2);
+    }
+}
@@ -0,0 +1,362 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Block rewards and emission
+
+use std::ops::Range;
+
+use super::consensus::{
+    difficulty_target, full_reward_zone, EMISSION_SPEED_FACTOR_PER_MINUTE,
+    FINAL_SUBSIDY_PER_MINUTE, MONEY_SUPPLY, REWARD_BLOCKS_WINDOW,
+    SHORT_TERM_BLOCK_WEIGHT_SURGE_FACTOR,
+};
+use super::parallel::{scan_parallel, DEFAULT_CHUNK_SIZE};
+use super::{Error, MoneroDB};
+
+/// Gets the target block time in minutes for a hard fork version
+///
+fn target_minutes(hf_version: u8) -> u64 {
+    difficulty_target(hf_version) / 60
+}
+
+/// Gets the reward a block would get before penalties, given the coins emitted
+/// before it
+///
+pub fn base_block_reward(already_generated_coins: u64, hf_version: u8) -> u64 {
+    let target_minutes = target_minutes(hf_version);
+    let emission_speed_factor = EMISSION_SPEED_FACTOR_PER_MINUTE - (target_minutes - 1);
+    let base_reward = (MONEY_SUPPLY - already_generated_coins) >> emission_speed_factor;
+    base_reward.max(tail_emission_reward(hf_version))
+}
+
+/// Gets the fixed reward of blocks in tail emission
+///
+fn tail_emission_reward(hf_version: u8) -> u64 {
+    FINAL_SUBSIDY_PER_MINUTE * target_minutes(hf_version)
+}
+
+/// Applies the oversize block penalty to `base_reward`, returns `None` if the
+/// block is more than twice the median weight and so invalid.
+///
+/// `median_weight` must already be raised to the full reward zone of the hard fork.
+///
+pub fn calculate_penalized_reward(
+    base_reward: u64,
+    block_weight: u64,
+    median_weight: u64,
+) -> Option<u64> {
+    if block_weight <= median_weight {
+        return Some(base_reward);
+    }
+    if block_weight > 2 * median_weight {
+        return None;
+    }
+    let (weight, median) = (block_weight as u128, median_weight as u128);
+    Some((base_reward as u128 * (2 * median - weight) * weight / (median * median)) as u64)
+}
+
+/// The reward of a block and the part of it burned by the oversize block penalty
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BlockPenalty {
+    /// Height of the block
+    pub height: u64,
+    /// Reward before the penalty, excluding fees
+    pub base_reward: u64,
+    /// Reward after the penalty, excluding fees
+    pub reward: u64,
+    /// Amount burned, `base_reward - reward`
+    pub penalty: u64,
+    /// Weight of the block
+    pub block_weight: u64,
+    /// Median weight the block was checked against
+    pub median_weight: u64,
+}
+
+/// Coins emitted by a period of blocks
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct EmissionPeriod {
+    /// First block of the period
+    pub start_height: u64,
+    /// Block after the last block of the period
+    pub end_height: u64,
+    /// Coins emitted in the period, excluding fees
+    pub emitted: u64,
+    /// Coins emitted up to the end of the period
+    pub total: u64,
+}
+
+/// A block whose recorded emission does not match its miner transaction
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SupplyDivergence {
+    /// Height of the block
+    pub height: u64,
+    /// Emission recorded in the block info, the increase of the generated coins
+    pub recorded: u64,
+    /// Sum of the miner transaction outputs
+    pub coinbase: u64,
+    /// Sum of the fees of the block's transactions
+    pub fees: u64,
+}
+
+/// Results of [`MoneroDB::audit_supply`]
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SupplyAudit {
+    /// Number of blocks audited
+    pub blocks: u64,
+    /// Generated coins recorded in the block info of the last audited block
+    pub recorded_supply: u64,
+    /// Sum of all miner transaction outputs
+    pub coinbase_total: u64,
+    /// Sum of all transaction fees
+    pub fee_total: u64,
+    /// Blocks whose emission does not match
+    pub divergences: Vec<SupplyDivergence>,
+}
+
+impl SupplyAudit {
+    /// Returns if the supply computed from the transactions matches the recorded supply
+    ///
+    pub fn is_consistent(&self) -> bool {
+        self.divergences.is_empty()
+            && self.coinbase_total.checked_sub(self.fee_total) == Some(self.recorded_supply)
+    }
+}
+
+impl MoneroDB {
+    /// Recomputes the coin supply up to and including `up_to_height` from the miner
+    /// transaction outputs minus the fees of every block and compares it with the
+    /// generated coins recorded in the block info.
+    ///
+    /// Blocks are scanned in parallel. Before hard fork 2 the full base reward was
+    /// recorded even when a miner claimed less, so such blocks are reported as
+    /// divergences with a recorded emission above their coinbase.
+    ///
+    pub fn audit_supply(&self, up_to_height: u64) -> Result<SupplyAudit, Error> {
+        let end = (up_to_height + 1).min(self.get_blockchain_height()?);
+        let mut audit = SupplyAudit {
+            blocks: end,
+            ..Default::default()
+        };
+        scan_parallel(
+            0..end,
+            &self.options.scan,
+            "audit_supply",
+            DEFAULT_CHUNK_SIZE,
+            |range| self.audit_blocks(range),
+            |_, blocks| {
+                for block in blocks {
+                    audit.coinbase_total += block.coinbase;
+                    audit.fee_total += block.fees;
+                    if Some(block.recorded) != block.coinbase.checked_sub(block.fees) {
+                        audit.divergences.push(block);
+                    }
+                }
+                Ok(())
+            },
+        )?;
+        if end > 0 {
+            audit.recorded_supply = self.get_block_already_generated_coins(end - 1)?;
+        }
+        Ok(audit)
+    }
+
+    fn audit_blocks(&self, range: Range<u64>) -> Result<Vec<SupplyDivergence>, Error> {
+        let mut previous_coins = match range.start {
+            0 => 0,
+            start => self.get_block_already_generated_coins(start - 1)?,
+        };
+        let mut blocks = Vec::new();
+        for height in range {
+            let block = self.get_block(height)?;
+            let coins = self.get_block_already_generated_coins(height)?;
+            let fees = self.block_fees(&block)?;
+            blocks.push(SupplyDivergence {
+                height,
+                recorded: coins - previous_coins,
+                coinbase: block
+                    .miner_tx
+                    .prefix
+                    .outputs
+                    .iter()
+                    .map(|output| output.amount.0)
+                    .sum(),
+                fees,
+            });
+            previous_coins = coins;
+        }
+        Ok(blocks)
+    }
+
+    /// Gets the height of the first block rewarded with the fixed tail emission, or
+    /// `None` if the chain has not reached tail emission yet
+    ///
+    pub fn tail_emission_start_height(&self) -> Result<Option<u64>, Error> {
+        let in_tail = |height: u64| -> Result<bool, Error> {
+            let hf_version = self.get_hf_version(height)?;
+            let already_generated_coins = match height {
+                0 => 0,
+                _ => self.get_block_already_generated_coins(height - 1)?,
+            };
+            Ok(base_block_reward(already_generated_coins, hf_version)
+                == tail_emission_reward(hf_version))
+        };
+
+        // emission only decreases, so the blocks in tail emission are a suffix of the chain
+        let (mut low, mut high) = (0, self.get_blockchain_height()?);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if in_tail(mid)? {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+        Ok((low < self.get_blockchain_height()?).then_some(low))
+    }
+
+    /// Splits `range` into periods of `period` blocks and gets the coins emitted in
+    /// each, the last period may be shorter
+    ///
+    pub fn emission_schedule(
+        &self,
+        range: Range<u64>,
+        period: u64,
+    ) -> Result<Vec<EmissionPeriod>, Error> {
+        let period = period.max(1);
+        let mut previous_total = match range.start {
+            0 => 0,
+            start => self.get_block_already_generated_coins(start - 1)?,
+        };
+        let mut schedule = Vec::new();
+        let mut start_height = range.start;
+        while start_height < range.end {
+            let end_height = (start_height + period).min(range.end);
+            let total = self.get_block_already_generated_coins(end_height - 1)?;
+            schedule.push(EmissionPeriod {
+                start_height,
+                end_height,
+                emitted: total - previous_total,
+                total,
+            });
+            previous_total = total;
+            start_height = end_height;
+        }
+        Ok(schedule)
+    }
+
+    /// Gets the median weight the block at `height` is checked against.
+    ///
+    /// This is the median weight of the previous 100 blocks raised to the full reward
+    /// zone, from hard fork 10 it is capped at 50 times the median long term weight of
+    /// the previous [`LONG_TERM_BLOCK_WEIGHT_WINDOW`] blocks.
+    ///
+    /// [`LONG_TERM_BLOCK_WEIGHT_WINDOW`]: crate::consensus::LONG_TERM_BLOCK_WEIGHT_WINDOW
+    ///
+    pub fn get_effective_median_weight(&self, height: u64) -> Result<u64, Error> {
+        self.effective_median_weight(height, self.get_hf_version(height)?)
+    }
+
+    /// Gets the effective median weight for a block at `height` with the hard fork
+    /// version `hf_version`, which also works for the next block to be mined
+    pub(crate) fn effective_median_weight(
+        &self,
+        height: u64,
+        hf_version: u8,
+    ) -> Result<u64, Error> {
+        let zone = full_reward_zone(hf_version);
+
+        let start = height.saturating_sub(REWARD_BLOCKS_WINDOW);
+        let mut weights = Vec::with_capacity((height - start) as usize);
+        for block_height in start..height {
+            weights.push(self.get_block_weight(block_height)?);
+        }
+        weights.sort_unstable();
+        let short_term_median = match weights.len() {
+            0 => 0,
+            len if len % 2 == 1 => weights[len / 2],
+            len => (weights[len / 2 - 1] + weights[len / 2]) / 2,
+        };
+
+        if hf_version < 10 || height == 0 {
+            return Ok(short_term_median.max(zone));
+        }
+        let long_term_median = self.get_long_term_weight_median(height - 1)?.max(zone);
+        Ok(short_term_median
+            .max(zone)
+            .min(SHORT_TERM_BLOCK_WEIGHT_SURGE_FACTOR * long_term_median))
+    }
+
+    /// Gets how much of the reward of the block at `height` was burned by the oversize
+    /// block penalty
+    ///
+    pub fn get_block_penalty(&self, height: u64) -> Result<BlockPenalty, Error> {
+        let hf_version = self.get_hf_version(height)?;
+        let already_generated_coins = match height {
+            0 => 0,
+            _ => self.get_block_already_generated_coins(height - 1)?,
+        };
+        let base_reward = base_block_reward(already_generated_coins, hf_version);
+        let block_weight = self.get_block_weight(height)?;
+        let median_weight = self.get_effective_median_weight(height)?;
+        let reward = calculate_penalized_reward(base_reward, block_weight, median_weight)
+            .ok_or_else(|| {
+                Error::InvalidBlock(format!(
+                    "block {} weighs {}, more than twice the median of {}",
+                    height, block_weight, median_weight
+                ))
+            })?;
+
+        Ok(BlockPenalty {
+            height,
+            base_reward,
+            reward,
+            penalty: base_reward - reward,
+            block_weight,
+            median_weight,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{base_block_reward, calculate_penalized_reward};
+
+    #[test]
+    fn base_rewards() {
+        // the genesis block and block 1 on mainnet
+        assert_eq!(base_block_reward(0, 1), 17_592_186_044_415);
+        assert_eq!(base_block_reward(17_592_186_044_415, 1), 17_592_169_267_200);
+        // tail emission, 0.6 XMR per two minute block
+        assert_eq!(base_block_reward(u64::MAX - 1, 16), 600_000_000_000);
+        assert_eq!(base_block_reward(u64::MAX - 1, 1), 300_000_000_000);
+    }
+
+    #[test]
+    fn penalized_rewards() {
+        assert_eq!(calculate_penalized_reward(10, 100, 100), Some(10));
+        assert_eq!(calculate_penalized_reward(10, 150, 100), Some(7));
+        assert_eq!(calculate_penalized_reward(10, 200, 100), Some(0));
+        assert_eq!(calculate_penalized_reward(10, 201, 100), None);
+        assert_eq!(
+            calculate_penalized_reward(600_000_000_000, 450_000, 300_000),
+            Some(450_000_000_000)
+        );
+    }
+}
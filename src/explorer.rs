@@ -0,0 +1,196 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Lookups serving block explorers and RPC endpoints
+
+use lmdb::{Cursor, Transaction};
+use monero::consensus::{deserialize, Decodable};
+use monero::database::block::BlockHeight;
+use monero::database::transaction::TxIndex;
+use monero::{Block, Hash};
+
+use super::block_info::{short_entry, BlockMetadata};
+use super::{Error, MoneroDB, ZERO_KEY};
+
+/// Maximum number of matches returned by a hash prefix search
+pub const MAX_PREFIX_MATCHES: usize = 20;
+
+/// The fields of a block shown in an explorer block list
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BlockSummary {
+    /// Height of the block
+    pub height: u64,
+    /// Hash of the block
+    #[cfg_attr(
+        feature = "serde",
+        serde(serialize_with = "crate::json::serialize_hash")
+    )]
+    pub hash: Hash,
+    /// Block timestamp
+    pub timestamp: u64,
+    /// Major version of the block
+    pub major_version: u64,
+    /// Block weight
+    pub weight: u64,
+    /// Number of transactions excluding the miner transaction
+    pub num_txes: u64,
+    /// Sum of the miner transaction outputs, including fees
+    pub reward: u64,
+}
+
+impl MoneroDB {
+    /// Gets the height and block of each hash in `block_hashes` under a single read
+    /// transaction, as monerod's `get_blocks_by_hash.bin`. Hashes not in the main
+    /// chain give `None`.
+    ///
+    pub fn get_blocks_by_hashes(
+        &self,
+        block_hashes: &[Hash],
+    ) -> Result<Vec<Option<(u64, Block)>>, Error> {
+        self.check_table_version(self.sub_dbs.blocks)?;
+        let transaction = self.begin_read()?;
+        let heights = transaction.open_ro_cursor(self.sub_dbs.block_heights)?;
+        let blocks = transaction.open_ro_cursor(self.sub_dbs.blocks)?;
+
+        block_hashes
+            .iter()
+            .map(|block_hash| {
+                let height = match heights.get(Some(&ZERO_KEY), Some(block_hash.as_bytes()), 2) {
+                    Ok((_, value)) if value.len() >= 40 => {
+                        u64::from_le_bytes(value[32..40].try_into().unwrap())
+                    }
+                    Ok(_) | Err(lmdb::Error::NotFound) => return Ok(None),
+                    Err(e) => return Err(e.into()),
+                };
+                let key = height.to_le_bytes();
+                let blob = blocks
+                    .get(Some(&key), None, 15)
+                    .map_err(|e| Error::from_lookup(e, "blocks", &key))?
+                    .1;
+                let block = deserialize(blob).map_err(|source| Error::Corrupt {
+                    db: "blocks",
+                    key: key.to_vec(),
+                    source,
+                })?;
+                Ok(Some((height, block)))
+            })
+            .collect()
+    }
+
+    /// Gets page `page` of the main chain blocks from the tip backwards, each page
+    /// holding `page_size` blocks ordered by descending height. Page 0 starts at
+    /// the tip, pages past the genesis block are empty.
+    ///
+    pub fn recent_blocks(&self, page: u64, page_size: u64) -> Result<Vec<BlockSummary>, Error> {
+        self.check_table_version(self.sub_dbs.block_info)?;
+        let transaction = self.begin_read()?;
+        let chain_height = transaction.stat(self.sub_dbs.block_heights)?.entries() as u64;
+        let top = match page.checked_mul(page_size) {
+            Some(skip) if skip < chain_height => chain_height - skip,
+            _ => return Ok(Vec::new()),
+        };
+        let bottom = top.saturating_sub(page_size);
+
+        let infos = transaction.open_ro_cursor(self.sub_dbs.block_info)?;
+        let blocks = transaction.open_ro_cursor(self.sub_dbs.blocks)?;
+        (bottom..top)
+            .rev()
+            .map(|height| {
+                let key = height.to_le_bytes();
+                let info = infos
+                    .get(Some(&ZERO_KEY), Some(&key), 2)
+                    .map_err(|e| Error::from_lookup(e, "block_info", &key))?
+                    .1;
+                let info = BlockMetadata::from_bytes(info).ok_or_else(|| short_entry(&key))?;
+                let blob = blocks
+                    .get(Some(&key), None, 15)
+                    .map_err(|e| Error::from_lookup(e, "blocks", &key))?
+                    .1;
+                let block: Block = deserialize(blob).map_err(|source| Error::Corrupt {
+                    db: "blocks",
+                    key: key.to_vec(),
+                    source,
+                })?;
+                Ok(BlockSummary {
+                    height,
+                    hash: info.hash,
+                    timestamp: info.timestamp,
+                    major_version: block.header.major_version.0,
+                    weight: info.weight,
+                    num_txes: block.tx_hashes.len() as u64,
+                    reward: block
+                        .miner_tx
+                        .prefix
+                        .outputs
+                        .iter()
+                        .map(|out| out.amount.0)
+                        .sum(),
+                })
+            })
+            .collect()
+    }
+
+    /// Finds the main chain blocks whose hash starts with `prefix`, returning at
+    /// most [`MAX_PREFIX_MATCHES`] in table order. This walks the whole table.
+    ///
+    pub fn find_block_by_hash_prefix(&self, prefix: &[u8]) -> Result<Vec<BlockHeight>, Error> {
+        find_by_hash_prefix(self, self.sub_dbs.block_heights, prefix)
+    }
+
+    /// Finds the transactions whose hash starts with `prefix`, returning at most
+    /// [`MAX_PREFIX_MATCHES`] in table order. This walks the whole table.
+    ///
+    pub fn find_tx_by_hash_prefix(&self, prefix: &[u8]) -> Result<Vec<TxIndex>, Error> {
+        find_by_hash_prefix(self, self.sub_dbs.tx_indices, prefix)
+    }
+}
+
+/// Walks every entry of a hash keyed dup sorted table collecting those whose hash
+/// starts with `prefix`
+///
+fn find_by_hash_prefix<T: Decodable>(
+    db: &MoneroDB,
+    sub_db: lmdb::Database,
+    prefix: &[u8],
+) -> Result<Vec<T>, Error> {
+    db.check_table_version(sub_db)?;
+    if prefix.len() > 32 {
+        return Ok(Vec::new());
+    }
+    // the tables are sorted by monerod's hash32 comparator, which compares the
+    // hashes as u32 words from the last one, so hashes sharing a prefix are spread
+    // over the whole table
+    let name = db.sub_dbs.name(sub_db);
+    let transaction = db.begin_read()?;
+    let curser = transaction.open_ro_cursor(sub_db)?;
+    let mut matches = Vec::new();
+    let mut op = 15;
+    while matches.len() < MAX_PREFIX_MATCHES {
+        let value = match curser.get(Some(&ZERO_KEY), None, op) {
+            Ok((_, value)) => value,
+            Err(lmdb::Error::NotFound) => break,
+            Err(e) => return Err(e.into()),
+        };
+        op = 9;
+        if !value.starts_with(prefix) {
+            continue;
+        }
+        matches.push(deserialize(value).map_err(|source| Error::Corrupt {
+            db: name,
+            key: value[..value.len().min(32)].to_vec(),
+            source,
+        })?);
+    }
+    Ok(matches)
+}
@@ -0,0 +1,147 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Transaction fee statistics
+
+use monero::consensus::serialize;
+use monero::Block;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use super::analysis::{tx_fee, tx_weight};
+use super::monero_db::read_item;
+use super::{Error, MoneroDB};
+
+const SECONDS_PER_DAY: u64 = 86400;
+
+/// Quantiles of the fee per byte, in atomic units, of a set of transactions
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FeePercentiles {
+    /// Number of transactions
+    pub count: u64,
+    /// Lowest fee per byte
+    pub min: u64,
+    /// 10th percentile
+    pub p10: u64,
+    /// 25th percentile
+    pub p25: u64,
+    /// Median fee per byte
+    pub median: u64,
+    /// 75th percentile
+    pub p75: u64,
+    /// 90th percentile
+    pub p90: u64,
+    /// Highest fee per byte
+    pub max: u64,
+}
+
+impl FeePercentiles {
+    fn from_fees(mut fees: Vec<u64>) -> Self {
+        if fees.is_empty() {
+            return FeePercentiles::default();
+        }
+        fees.sort_unstable();
+        let percentile = |p: usize| fees[(fees.len() - 1) * p / 100];
+        FeePercentiles {
+            count: fees.len() as u64,
+            min: fees[0],
+            p10: percentile(10),
+            p25: percentile(25),
+            median: percentile(50),
+            p75: percentile(75),
+            p90: percentile(90),
+            max: fees[fees.len() - 1],
+        }
+    }
+}
+
+impl MoneroDB {
+    /// Sums the fees of the transactions of `block`
+    ///
+    pub(crate) fn block_fees(&self, block: &Block) -> Result<u64, Error> {
+        let mut fees = 0;
+        for tx_hash in &block.tx_hashes {
+            let tx = self.get_tx_pruned_by_hash(tx_hash)?;
+            fees += tx_fee(&tx.prefix, &tx.rct_signatures);
+        }
+        Ok(fees)
+    }
+
+    /// Sums the fees paid by the transactions of the blocks in `range`.
+    ///
+    /// RCT fees are read from the signatures, pre-RCT fees are the difference
+    /// between the input and output amounts.
+    ///
+    pub fn total_fees(&self, range: Range<u64>) -> Result<u64, Error> {
+        let mut total = 0;
+        for height in range {
+            total += self.block_fees(&self.get_block(height)?)?;
+        }
+        Ok(total)
+    }
+
+    /// Sums the fees paid by the transactions of the blocks in `range` per UTC day,
+    /// keyed by the timestamp of the start of the day. Blocks are assigned to days by
+    /// their timestamp.
+    ///
+    pub fn daily_fees(&self, range: Range<u64>) -> Result<BTreeMap<u64, u64>, Error> {
+        let mut days = BTreeMap::new();
+        for height in range {
+            let block = self.get_block(height)?;
+            let day = block.header.timestamp.0 / SECONDS_PER_DAY * SECONDS_PER_DAY;
+            *days.entry(day).or_default() += self.block_fees(&block)?;
+        }
+        Ok(days)
+    }
+
+    /// Gets the [`FeePercentiles`] of the transactions mined in the last
+    /// `window_blocks` blocks.
+    ///
+    /// The fee per byte is taken over the weight of the transactions, as used by
+    /// monerod for fees, rather than their serialized size. Transactions whose
+    /// prunable part was pruned are skipped.
+    ///
+    pub fn fee_percentiles(&self, window_blocks: u64) -> Result<FeePercentiles, Error> {
+        let height = self.get_blockchain_height()?;
+        let mut fees_per_byte = Vec::new();
+        for block_height in height.saturating_sub(window_blocks)..height {
+            for tx_hash in self.get_block(block_height)?.tx_hashes {
+                let tx_id = self.get_tx_indices(&tx_hash)?.data.tx_id;
+                let key = tx_id.to_le_bytes();
+                let prunable_size =
+                    match read_item(self, self.sub_dbs.txs_prunable, &key, &[0], 15, <[u8]>::len) {
+                        Ok(size) => size,
+                        Err(e) if e.is_not_found() => continue,
+                        Err(e) => return Err(e),
+                    };
+                let tx = self.get_tx_pruned(tx_id)?;
+                // the RCT type follows the prefix in the pruned blob
+                let prefix_size = serialize(&tx.prefix).len();
+                let (pruned_size, rct_type) =
+                    read_item(self, self.sub_dbs.txs_pruned, &key, &[0], 15, |blob| {
+                        (blob.len(), blob.get(prefix_size).copied().unwrap_or(0))
+                    })?;
+                let weight = tx_weight(
+                    tx.prefix.version.0,
+                    rct_type,
+                    tx.prefix.outputs.len(),
+                    (pruned_size + prunable_size) as u64,
+                );
+                let fee = tx_fee(&tx.prefix, &tx.rct_signatures);
+                fees_per_byte.push(fee / weight.max(1));
+            }
+        }
+        Ok(FeePercentiles::from_fees(fees_per_byte))
+    }
+}
@@ -0,0 +1,184 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! C ABI bindings
+//!
+//! Enabled with the `ffi` feature, build a shared library with
+//! `cargo rustc --release --features ffi --crate-type cdylib`. The matching
+//! declarations are in `include/monero_db.h`.
+//!
+//! Functions returning `c_int` return [`MONERO_DB_OK`], [`MONERO_DB_NOT_FOUND`]
+//! or [`MONERO_DB_ERROR`], and only write to their out parameters on success.
+#![allow(unsafe_code)]
+
+use monero::Hash;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int};
+use std::path::Path;
+use std::ptr;
+
+use super::{Error, MoneroDB};
+
+/// The call succeeded
+pub const MONERO_DB_OK: c_int = 0;
+/// The requested item is not in the database
+pub const MONERO_DB_NOT_FOUND: c_int = 1;
+/// The call failed
+pub const MONERO_DB_ERROR: c_int = -1;
+
+/// Bytes returned to C, must be freed with [`monero_db_buffer_free`]
+///
+#[repr(C)]
+pub struct MoneroDbBuffer {
+    /// Pointer to the bytes
+    pub data: *mut u8,
+    /// Number of bytes
+    pub len: usize,
+}
+
+impl MoneroDbBuffer {
+    fn from_vec(bytes: Vec<u8>) -> Self {
+        let len = bytes.len();
+        let data = Box::into_raw(bytes.into_boxed_slice()) as *mut u8;
+        MoneroDbBuffer { data, len }
+    }
+}
+
+fn status<T>(result: Result<T, Error>, out: impl FnOnce(T)) -> c_int {
+    match result {
+        Ok(value) => {
+            out(value);
+            MONERO_DB_OK
+        }
+        Err(e) if e.is_not_found() => MONERO_DB_NOT_FOUND,
+        Err(_) => MONERO_DB_ERROR,
+    }
+}
+
+/// Opens the database in the null terminated directory `path`, returns null on failure.
+///
+/// # Safety
+///
+/// `path` must be a valid null terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn monero_db_open(path: *const c_char, read_only: bool) -> *mut MoneroDB {
+    if path.is_null() {
+        return ptr::null_mut();
+    }
+    let path = match CStr::from_ptr(path).to_str() {
+        Ok(path) => path,
+        Err(_) => return ptr::null_mut(),
+    };
+    match MoneroDB::open(Path::new(path), read_only) {
+        Ok(db) => Box::into_raw(Box::new(db)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Closes a database opened with [`monero_db_open`].
+///
+/// # Safety
+///
+/// `db` must be null or returned by [`monero_db_open`] and not already closed.
+#[no_mangle]
+pub unsafe extern "C" fn monero_db_close(db: *mut MoneroDB) {
+    if !db.is_null() {
+        drop(Box::from_raw(db));
+    }
+}
+
+/// Writes the blockchain height to `height`.
+///
+/// # Safety
+///
+/// `db` must be an open database and `height` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn monero_db_height(db: *const MoneroDB, height: *mut u64) -> c_int {
+    if db.is_null() || height.is_null() {
+        return MONERO_DB_ERROR;
+    }
+    status((*db).get_blockchain_height(), |value| *height = value)
+}
+
+/// Writes the serialized block at `height` to `out`.
+///
+/// # Safety
+///
+/// `db` must be an open database and `out` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn monero_db_get_block_blob(
+    db: *const MoneroDB,
+    height: u64,
+    out: *mut MoneroDbBuffer,
+) -> c_int {
+    if db.is_null() || out.is_null() {
+        return MONERO_DB_ERROR;
+    }
+    status((*db).get_block_blob(height), |blob| {
+        *out = MoneroDbBuffer::from_vec(blob)
+    })
+}
+
+/// Writes the full serialized transaction with the 32 byte hash `tx_hash` to `out`.
+///
+/// # Safety
+///
+/// `db` must be an open database, `tx_hash` must point to 32 bytes and `out`
+/// must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn monero_db_get_tx_blob(
+    db: *const MoneroDB,
+    tx_hash: *const u8,
+    out: *mut MoneroDbBuffer,
+) -> c_int {
+    if db.is_null() || tx_hash.is_null() || out.is_null() {
+        return MONERO_DB_ERROR;
+    }
+    let tx_hash = Hash::from_slice(std::slice::from_raw_parts(tx_hash, 32));
+    status((*db).get_tx_blob(&tx_hash), |blob| {
+        *out = MoneroDbBuffer::from_vec(blob)
+    })
+}
+
+/// Writes if the 32 byte key image has been spent to `spent`.
+///
+/// # Safety
+///
+/// `db` must be an open database, `key_image` must point to 32 bytes and
+/// `spent` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn monero_db_is_key_image_spent(
+    db: *const MoneroDB,
+    key_image: *const u8,
+    spent: *mut bool,
+) -> c_int {
+    if db.is_null() || key_image.is_null() || spent.is_null() {
+        return MONERO_DB_ERROR;
+    }
+    let key_image = std::slice::from_raw_parts(key_image, 32);
+    status((*db).is_key_image_spent(key_image), |value| *spent = value)
+}
+
+/// Frees a buffer returned by this library.
+///
+/// # Safety
+///
+/// `buffer` must have been returned by this library and not already freed.
+#[no_mangle]
+pub unsafe extern "C" fn monero_db_buffer_free(buffer: MoneroDbBuffer) {
+    if !buffer.data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            buffer.data,
+            buffer.len,
+        )));
+    }
+}
@@ -0,0 +1,148 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Transaction graph export
+//!
+//! Builds a graph with an edge from every transaction to the transactions that
+//! created the outputs referenced by its rings.
+
+use monero::blockdata::transaction::TxIn;
+use monero::Hash;
+use std::collections::HashMap;
+use std::io::Write;
+use std::ops::Range;
+
+use super::{Error, MoneroDB};
+
+/// File formats a [`TxGraph`] can be written as
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// Graphviz DOT
+    Dot,
+    /// GraphML, as read by Gephi and NetworkX
+    GraphMl,
+}
+
+/// Directed graph of transactions referencing each others outputs
+///
+#[derive(Debug, Clone, Default)]
+pub struct TxGraph {
+    nodes: Vec<Hash>,
+    node_ids: HashMap<Hash, usize>,
+    edges: Vec<(usize, usize)>,
+}
+
+impl TxGraph {
+    /// Returns the hashes of all the transactions in the graph
+    ///
+    pub fn nodes(&self) -> &[Hash] {
+        &self.nodes
+    }
+
+    /// Returns the edges of the graph as (spending tx, ring member tx) pairs
+    ///
+    pub fn edges(&self) -> impl Iterator<Item = (&Hash, &Hash)> + '_ {
+        self.edges
+            .iter()
+            .map(|(from, to)| (&self.nodes[*from], &self.nodes[*to]))
+    }
+
+    fn node(&mut self, tx_hash: Hash) -> usize {
+        let nodes = &mut self.nodes;
+        *self.node_ids.entry(tx_hash).or_insert_with(|| {
+            nodes.push(tx_hash);
+            nodes.len() - 1
+        })
+    }
+
+    /// Writes the graph to `writer` in the given format
+    ///
+    pub fn write<W: Write>(&self, writer: &mut W, format: GraphFormat) -> Result<(), Error> {
+        match format {
+            GraphFormat::Dot => self.write_dot(writer),
+            GraphFormat::GraphMl => self.write_graphml(writer),
+        }
+    }
+
+    fn write_dot<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writeln!(writer, "digraph txs {{")?;
+        for (from, to) in self.edges() {
+            writeln!(
+                writer,
+                "  \"{}\" -> \"{}\";",
+                hex::encode(from.as_bytes()),
+                hex::encode(to.as_bytes())
+            )?;
+        }
+        writeln!(writer, "}}")?;
+        Ok(())
+    }
+
+    fn write_graphml<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            writer,
+            r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+        )?;
+        writeln!(writer, r#"  <graph id="txs" edgedefault="directed">"#)?;
+        for node in &self.nodes {
+            writeln!(
+                writer,
+                r#"    <node id="{}"/>"#,
+                hex::encode(node.as_bytes())
+            )?;
+        }
+        for (from, to) in self.edges() {
+            writeln!(
+                writer,
+                r#"    <edge source="{}" target="{}"/>"#,
+                hex::encode(from.as_bytes()),
+                hex::encode(to.as_bytes())
+            )?;
+        }
+        writeln!(writer, "  </graph>")?;
+        writeln!(writer, "</graphml>")?;
+        Ok(())
+    }
+}
+
+impl MoneroDB {
+    /// Builds the output reference graph of the transactions in the blocks of `range`
+    ///
+    pub fn build_tx_graph(&self, range: Range<u64>) -> Result<TxGraph, Error> {
+        let mut graph = TxGraph::default();
+        for height in range {
+            self.check_cancelled(height)?;
+            let block = self.get_block(height)?;
+            for tx_hash in &block.tx_hashes {
+                let tx = self.get_tx_pruned_by_hash(tx_hash)?;
+                let from = graph.node(*tx_hash);
+                for input in &tx.prefix.inputs {
+                    if let TxIn::ToKey {
+                        amount,
+                        key_offsets,
+                        ..
+                    } = input
+                    {
+                        for output_id in self.get_ring_output_ids(amount.0, key_offsets)? {
+                            let out_tx = self.get_output_tx(output_id)?;
+                            let to = graph.node(out_tx.tx_hash);
+                            graph.edges.push((from, to));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(graph)
+    }
+}
@@ -0,0 +1,108 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Sharing one database handle between threads
+
+use std::ops::Deref;
+use std::sync::Arc;
+
+use super::{MoneroDB, ReadWriteDb};
+
+/// A cheaply cloneable, `Send + Sync` handle to a [`MoneroDB`] or [`ReadWriteDb`],
+/// for example to share one database across the request handlers of a web server.
+///
+/// Clones point at the same open database, which is closed when the last clone is
+/// dropped. Every read opens its own LMDB read transaction, so reads from several
+/// clones run concurrently and each sees a consistent view of the database. Writes
+/// through a `DbHandle<ReadWriteDb>` are serialized by LMDB, one write transaction
+/// runs at a time and the others wait for it.
+///
+/// Settings changed through `&mut self`, such as [`MoneroDB::set_scan_options`],
+/// have to be made before the handle is cloned or through [`DbHandle::get_mut`].
+///
+pub struct DbHandle<D = MoneroDB> {
+    db: Arc<D>,
+}
+
+// the handle is meant to be shared between threads, keep it that way
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<DbHandle>();
+    assert_send_sync::<DbHandle<ReadWriteDb>>();
+};
+
+impl<D> DbHandle<D> {
+    /// Wraps `db` in a handle
+    ///
+    pub fn new(db: D) -> Self {
+        DbHandle { db: Arc::new(db) }
+    }
+
+    /// Gets mutable access to the database if this is the only handle to it
+    ///
+    pub fn get_mut(&mut self) -> Option<&mut D> {
+        Arc::get_mut(&mut self.db)
+    }
+
+    /// Returns the database if this is the only handle to it, otherwise returns
+    /// the handle back
+    ///
+    pub fn try_unwrap(self) -> Result<D, Self> {
+        Arc::try_unwrap(self.db).map_err(|db| DbHandle { db })
+    }
+
+    /// Returns the number of handles to the database
+    ///
+    pub fn handle_count(&self) -> usize {
+        Arc::strong_count(&self.db)
+    }
+}
+
+impl<D> Clone for DbHandle<D> {
+    fn clone(&self) -> Self {
+        DbHandle {
+            db: Arc::clone(&self.db),
+        }
+    }
+}
+
+impl<D> Deref for DbHandle<D> {
+    type Target = D;
+
+    fn deref(&self) -> &D {
+        &self.db
+    }
+}
+
+impl<D> From<D> for DbHandle<D> {
+    fn from(db: D) -> Self {
+        DbHandle::new(db)
+    }
+}
+
+impl MoneroDB {
+    /// Turns the database into a [`DbHandle`] that can be cloned and shared
+    /// between threads
+    ///
+    pub fn into_handle(self) -> DbHandle {
+        DbHandle::new(self)
+    }
+}
+
+impl ReadWriteDb {
+    /// Turns the database into a [`DbHandle`] that can be cloned and shared
+    /// between threads, writes through it are serialized by LMDB
+    ///
+    pub fn into_handle(self) -> DbHandle<ReadWriteDb> {
+        DbHandle::new(self)
+    }
+}
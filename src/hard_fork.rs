@@ -0,0 +1,183 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Hard fork activation heights and voting
+
+use lmdb::{Cursor, Transaction};
+use monero::blockdata::block::BlockHeader;
+use monero::consensus::deserialize_partial;
+use monero::consensus::encode::Error::ParseFailed;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use super::consensus::hard_forks;
+use super::{Error, MoneroDB};
+
+/// First block of a hard fork version
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct HardForkHeight {
+    /// Hard fork version
+    pub version: u8,
+    /// Height of the first block with this version
+    pub height: u64,
+}
+
+/// Hard fork votes cast by the blocks in a window
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct HardForkVotes {
+    /// Heights of the blocks counted
+    pub heights: Range<u64>,
+    /// Number of blocks voting for each version
+    pub votes: BTreeMap<u8, u64>,
+}
+
+impl HardForkVotes {
+    /// Number of blocks supporting `version`, a vote for a version also counts
+    /// for every older one
+    pub fn support_for(&self, version: u8) -> u64 {
+        self.votes.range(version..).map(|(_, count)| count).sum()
+    }
+
+    /// Percentage of the window supporting `version`, rounded down
+    pub fn support_percent(&self, version: u8) -> u64 {
+        let blocks = self.heights.end - self.heights.start;
+        if blocks == 0 {
+            return 0;
+        }
+        self.support_for(version) * 100 / blocks
+    }
+}
+
+impl MoneroDB {
+    /// Reads the `hf_starting_heights` table of old databases, returns `None` if
+    /// the database does not have it. Newer daemons drop this table and derive the
+    /// heights from the block versions instead, see [`MoneroDB::hf_activation_heights`].
+    ///
+    pub fn get_hf_starting_heights(&self) -> Result<Option<Vec<HardForkHeight>>, Error> {
+        let sub_db = match self.sub_dbs.hf_starting_heights {
+            Some(sub_db) => sub_db,
+            None => return Ok(None),
+        };
+        let transaction = self.begin_read()?;
+        let curser = transaction.open_ro_cursor(sub_db)?;
+        let mut heights = Vec::new();
+        let mut op = 0;
+        loop {
+            match curser.get(None, None, op) {
+                Ok((Some(key), value)) => {
+                    let (version, height) = match (key.first(), value.get(..8)) {
+                        (Some(version), Some(height)) => (*version, height),
+                        _ => {
+                            return Err(Error::Corrupt {
+                                db: "hf_starting_heights",
+                                key: key.to_vec(),
+                                source: ParseFailed("hard fork height entry too short"),
+                            })
+                        }
+                    };
+                    heights.push(HardForkHeight {
+                        version,
+                        height: u64::from_le_bytes(height.try_into().unwrap()),
+                    });
+                }
+                Ok((None, _)) | Err(lmdb::Error::NotFound) => break,
+                Err(e) => return Err(e.into()),
+            }
+            op = 8;
+        }
+        heights.sort_by_key(|fork| fork.height);
+        Ok(Some(heights))
+    }
+
+    /// Gets the first block of every hard fork version seen on the chain, found by
+    /// binary searching the hard fork version of each block
+    ///
+    pub fn hf_activation_heights(&self) -> Result<Vec<HardForkHeight>, Error> {
+        let chain_height = self.get_blockchain_height()?;
+        let mut forks = Vec::new();
+        let mut start = 0;
+        while start < chain_height {
+            let version = self.get_hf_version(start)?;
+            forks.push(HardForkHeight {
+                version,
+                height: start,
+            });
+            // versions never decrease, find the first block of the next one
+            let (mut low, mut high) = (start + 1, chain_height);
+            while low < high {
+                let mid = low + (high - low) / 2;
+                if self.get_hf_version(mid)? > version {
+                    high = mid;
+                } else {
+                    low = mid + 1;
+                }
+            }
+            start = low;
+        }
+        Ok(forks)
+    }
+
+    /// Gets the hard fork version a block at `height` must have according to the
+    /// schedule of the database's network, see [`MoneroDB::network`]. Works for
+    /// heights above the chain, `None` if the network is unknown.
+    ///
+    pub fn scheduled_hf_version(&self, height: u64) -> Result<Option<u8>, Error> {
+        Ok(self.network()?.and_then(|network| {
+            hard_forks(network)
+                .iter()
+                .take_while(|fork| fork.height <= height)
+                .last()
+                .map(|fork| fork.version)
+        }))
+    }
+
+    /// Gets the hard fork version the block votes for, blocks from before voting
+    /// was introduced have a minor version of 0 which counts as a vote for version 1
+    ///
+    pub fn get_block_vote(&self, height: u64) -> Result<u8, Error> {
+        let mut blob = Vec::new();
+        self.block_vote(height, &mut blob)
+    }
+
+    /// Counts the hard fork votes of the `window` blocks ending at `height`, the
+    /// daemon uses a window of [`HF_VOTING_WINDOW`](crate::consensus::HF_VOTING_WINDOW)
+    /// blocks
+    ///
+    pub fn hf_votes(&self, height: u64, window: u64) -> Result<HardForkVotes, Error> {
+        let end = (height + 1).min(self.get_blockchain_height()?);
+        let mut votes = HardForkVotes {
+            heights: end.saturating_sub(window)..end,
+            ..Default::default()
+        };
+        let mut blob = Vec::new();
+        for height in votes.heights.clone() {
+            *votes
+                .votes
+                .entry(self.block_vote(height, &mut blob)?)
+                .or_default() += 1;
+        }
+        Ok(votes)
+    }
+
+    fn block_vote(&self, height: u64, blob: &mut Vec<u8>) -> Result<u8, Error> {
+        self.get_block_blob_into(height, blob)?;
+        let (header, _) = deserialize_partial::<BlockHeader>(blob)?;
+        Ok(match header.minor_version.0 {
+            0 => 1,
+            vote => vote.min(u8::MAX as u64) as u8,
+        })
+    }
+}
@@ -0,0 +1,75 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Hex string variants of the lookup functions
+//!
+//! Hashes and key images are taken as hex strings and blobs are returned hex
+//! encoded, for CLI tools and web handlers.
+
+use monero::database::transaction::TxIndex;
+use monero::{Block, Hash};
+
+use super::{Error, MoneroDB};
+
+/// Parses a 32 byte hex string, as used for hashes and key images
+///
+fn parse_32(hex: &str) -> Result<[u8; 32], Error> {
+    ::hex::decode(hex.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| Error::InvalidHex(hex.to_string()))
+}
+
+pub(crate) fn parse_hash(hex: &str) -> Result<Hash, Error> {
+    Ok(Hash::from_slice(&parse_32(hex)?))
+}
+
+impl MoneroDB {
+    /// Gets the block with the hex encoded hash
+    ///
+    pub fn get_block_by_hash_hex(&self, block_hash: &str) -> Result<Block, Error> {
+        let height = self.get_block_height(&parse_hash(block_hash)?)?.height;
+        self.get_block(height)
+    }
+
+    /// Gets the hex encoded hash of the block at `block_height`
+    ///
+    pub fn get_block_hash_hex(&self, block_height: u64) -> Result<String, Error> {
+        Ok(::hex::encode(
+            self.get_block_info(block_height)?.block_hash.as_bytes(),
+        ))
+    }
+
+    /// Gets the hex encoded serialized block at `block_height`
+    ///
+    pub fn get_block_blob_hex(&self, block_height: u64) -> Result<String, Error> {
+        Ok(::hex::encode(self.get_block_blob(block_height)?))
+    }
+
+    /// Gets the hex encoded full transaction with the hex encoded hash
+    ///
+    pub fn get_transaction_hex(&self, txn_hash: &str) -> Result<String, Error> {
+        Ok(::hex::encode(self.get_tx_blob(&parse_hash(txn_hash)?)?))
+    }
+
+    /// Gets the tx indices of the transaction with the hex encoded hash
+    ///
+    pub fn get_tx_indices_hex(&self, txn_hash: &str) -> Result<TxIndex, Error> {
+        self.get_tx_indices(&parse_hash(txn_hash)?)
+    }
+
+    /// Checks if the hex encoded key image has been spent
+    ///
+    pub fn is_key_image_spent_hex(&self, key_image: &str) -> Result<bool, Error> {
+        self.is_key_image_spent(&parse_32(key_image)?)
+    }
+}
@@ -0,0 +1,103 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! A small REST server for internal explorers and dashboards
+//!
+//! [`MoneroDB::serve_explorer`] answers these requests with JSON bodies:
+//!
+//! - `GET /block/{height}` or `GET /block/{hash}`, the block header
+//! - `GET /tx/{hash}`, the transaction index entry and the transaction as hex
+//! - `GET /stats`, the chain height, transaction count and top block hash
+
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Response, Server};
+
+use super::hex::parse_hash;
+use super::{Error, MoneroDB};
+
+impl MoneroDB {
+    /// Serves the explorer endpoints on `addr`, for example `127.0.0.1:8080`,
+    /// handling one request at a time. Only returns if the server can not be
+    /// started.
+    ///
+    pub fn serve_explorer(&self, addr: &str) -> Result<(), Error> {
+        let server = Server::http(addr).map_err(|e| {
+            Error::IoError(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                e.to_string(),
+            ))
+        })?;
+        for request in server.incoming_requests() {
+            let (status, body) = if *request.method() != Method::Get {
+                (405, json!({ "error": "method not allowed" }))
+            } else {
+                match self.explorer_response(request.url()) {
+                    Ok(Some(body)) => (200, body),
+                    Ok(None) => (404, json!({ "error": "not found" })),
+                    Err(e) => (error_status(&e), json!({ "error": e.to_string() })),
+                }
+            };
+            let response = Response::from_string(body.to_string())
+                .with_status_code(status)
+                .with_header(
+                    Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap(),
+                );
+            // a client hanging up must not stop the server
+            let _ = request.respond(response);
+        }
+        Ok(())
+    }
+
+    fn explorer_response(&self, url: &str) -> Result<Option<Value>, Error> {
+        let path = url.split('?').next().unwrap_or_default();
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        let body = match segments.as_slice() {
+            ["block", id] => {
+                let height = match id.parse::<u64>() {
+                    Ok(height) => height,
+                    Err(_) => self.get_block_height(&parse_hash(id)?)?.height,
+                };
+                serde_json::to_value(self.get_block_header_json(height)?)?
+            }
+            ["tx", hash] => {
+                let tx_hash = parse_hash(hash)?;
+                json!({
+                    "index": self.get_tx_index_json(&tx_hash)?,
+                    "as_hex": hex::encode(self.get_tx_blob(&tx_hash)?),
+                })
+            }
+            ["stats"] => {
+                let height = self.get_blockchain_height()?;
+                let top_hash = match height.checked_sub(1) {
+                    Some(top) => hex::encode(self.get_block_hash(top)?.as_bytes()),
+                    None => String::new(),
+                };
+                json!({
+                    "height": height,
+                    "tx_count": self.get_tx_count()?,
+                    "top_block_hash": top_hash,
+                })
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(body))
+    }
+}
+
+fn error_status(error: &Error) -> u16 {
+    match error {
+        Error::InvalidHex(_) => 400,
+        Error::Pruned { .. } => 410,
+        e if e.is_not_found() => 404,
+        _ => 500,
+    }
+}
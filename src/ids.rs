@@ -0,0 +1,76 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Typed identifiers
+//!
+//! The database keys blocks, transactions and outputs by plain integers. These
+//! wrappers keep the different kinds apart in the API, lookups take `impl Into<Id>`
+//! so raw `u64`s are still accepted while one kind of ID can't be passed as another.
+
+use std::fmt;
+
+macro_rules! id_type {
+    ($(#[$doc:meta])* $name:ident) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize), serde(transparent))]
+        pub struct $name(pub u64);
+
+        impl $name {
+            /// Returns the raw value
+            ///
+            pub fn get(self) -> u64 {
+                self.0
+            }
+
+            pub(crate) fn to_le_bytes(self) -> [u8; 8] {
+                self.0.to_le_bytes()
+            }
+        }
+
+        impl From<u64> for $name {
+            fn from(value: u64) -> Self {
+                $name(value)
+            }
+        }
+
+        impl From<$name> for u64 {
+            fn from(value: $name) -> Self {
+                value.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Display::fmt(&self.0, f)
+            }
+        }
+    };
+}
+
+id_type!(
+    /// Height of a block in the main chain
+    BlockHeight
+);
+id_type!(
+    /// Database ID of a transaction, its position in the order transactions were added
+    TxId
+);
+id_type!(
+    /// Global index of a RingCT output, or of any output in the `output_txs` table
+    OutputId
+);
+id_type!(
+    /// Amount of an output in atomic units, pre-RingCT outputs are indexed per amount
+    /// and all RingCT outputs use amount 0
+    Amount
+);
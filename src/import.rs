@@ -0,0 +1,252 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Block import pipeline
+//!
+//! Blocks are deserialized and checked on worker threads while the calling
+//! thread commits them in batches, the only thread writing to the database.
+
+use monero::consensus::deserialize;
+use monero::cryptonote::hash::Hashable;
+use monero::Block;
+use std::collections::BTreeMap;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Mutex;
+use std::thread;
+
+use super::parallel::{default_threads, ByteBudget};
+use super::progress::ProgressTracker;
+use super::{Error, NewBlock, ReadWriteDb};
+
+/// A serialized block and the data needed to import it
+///
+#[derive(Debug, Clone)]
+pub struct RawBlock {
+    /// The serialized block
+    pub block: Vec<u8>,
+    /// The serialized transactions of the block in the order of its transaction hashes
+    pub txs: Vec<Vec<u8>>,
+    /// Weight of the block
+    pub block_weight: u64,
+    /// Long term weight of the block
+    pub long_term_block_weight: u64,
+    /// Difficulty of the block
+    pub difficulty: u128,
+}
+
+impl RawBlock {
+    /// Size of the serialized block and its transactions
+    fn size(&self) -> u64 {
+        let txs: usize = self.txs.iter().map(Vec::len).sum();
+        (self.block.len() + txs) as u64
+    }
+
+    fn decode(self) -> Result<NewBlock, Error> {
+        let block: Block = deserialize(&self.block)?;
+        let txs = self
+            .txs
+            .iter()
+            .map(|tx| deserialize::<monero::Transaction>(tx))
+            .collect::<Result<Vec<_>, _>>()?;
+        if txs.len() != block.tx_hashes.len()
+            || txs
+                .iter()
+                .zip(&block.tx_hashes)
+                .any(|(tx, hash)| tx.hash() != *hash)
+        {
+            return Err(Error::InvalidBlock(format!(
+                "transactions do not match the transaction hashes of block {}",
+                hex::encode(block.id().as_bytes())
+            )));
+        }
+        Ok(NewBlock {
+            block,
+            txs,
+            block_weight: self.block_weight,
+            long_term_block_weight: self.long_term_block_weight,
+            difficulty: self.difficulty,
+        })
+    }
+}
+
+/// Imports blocks by decoding them on worker threads and committing them in
+/// batches from a single writer
+///
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    threads: usize,
+    max_in_flight: usize,
+    max_bytes_in_flight: u64,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline {
+            threads: default_threads(),
+            max_in_flight: 4096,
+            max_bytes_in_flight: u64::MAX,
+        }
+    }
+}
+
+impl Pipeline {
+    /// Creates a pipeline using all available cores
+    ///
+    pub fn new() -> Self {
+        Pipeline::default()
+    }
+
+    /// Sets the number of decoding threads
+    ///
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Sets how many blocks may be read from the source but not yet added to a
+    /// write batch, reading from the source pauses once this is reached
+    ///
+    pub fn max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = max_in_flight;
+        self
+    }
+
+    /// Sets how many bytes of serialized blocks and transactions may be read from
+    /// the source but not yet handed to the writer, reading from the source pauses
+    /// once this is reached. Batches are also committed early once they hold this
+    /// many bytes, so a range of huge blocks can't exhaust memory. By default only
+    /// [`Pipeline::max_in_flight`] limits memory use.
+    ///
+    pub fn max_bytes_in_flight(mut self, max_bytes_in_flight: u64) -> Self {
+        self.max_bytes_in_flight = max_bytes_in_flight;
+        self
+    }
+
+    /// Imports `blocks` into `db` in order, committing them in batches of the
+    /// batch size `db` was opened with. Returns the number of blocks imported.
+    ///
+    /// Blocks committed before an error stay in the database. Progress is reported
+    /// after each batch to the callback set with [`ScanOptions::on_progress`] on `db`.
+    ///
+    /// [`ScanOptions::on_progress`]: crate::ScanOptions::on_progress
+    pub fn run<I>(&self, db: &ReadWriteDb, blocks: I) -> Result<u64, Error>
+    where
+        I: IntoIterator<Item = RawBlock>,
+        I::IntoIter: Send,
+    {
+        let max_in_flight = self.max_in_flight.max(1);
+        let batch_size = db.options.batch_size.max(1);
+        let blocks = blocks.into_iter();
+        let start = db.get_blockchain_height()?;
+        let end = blocks.size_hint().1.map(|count| start + count as u64);
+        let progress = ProgressTracker::with_end(&db.options.scan, "Pipeline::run", start, end);
+        let mut imported = start;
+
+        let (raw_sender, raw_receiver) = mpsc::sync_channel::<(u64, RawBlock)>(max_in_flight);
+        let (decoded_sender, decoded_receiver) = mpsc::channel();
+        let (permit_sender, permit_receiver) = mpsc::sync_channel::<()>(max_in_flight);
+        for _ in 0..max_in_flight {
+            permit_sender
+                .send(())
+                .expect("channel has room for every permit");
+        }
+        let raw_receiver = Mutex::new(raw_receiver);
+        let budget = ByteBudget::new(self.max_bytes_in_flight);
+
+        thread::scope(|scope| {
+            // owned by this closure so the reader stops as soon as writing ends
+            let permit_sender = permit_sender;
+            let budget = &budget;
+            scope.spawn(move || {
+                for (index, block) in (0..).zip(blocks) {
+                    if permit_receiver.recv().is_err() {
+                        break;
+                    }
+                    budget.acquire(block.size());
+                    if raw_sender.send((index, block)).is_err() {
+                        break;
+                    }
+                }
+            });
+            for _ in 0..self.threads.max(1) {
+                let decoded_sender = decoded_sender.clone();
+                let raw_receiver = &raw_receiver;
+                scope.spawn(move || loop {
+                    let next = raw_receiver.lock().unwrap().recv();
+                    let (index, block) = match next {
+                        Ok(next) => next,
+                        Err(_) => break,
+                    };
+                    let size = block.size();
+                    if decoded_sender.send((index, size, block.decode())).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(decoded_sender);
+
+            let written = write_in_order(
+                db,
+                decoded_receiver,
+                budget,
+                &permit_sender,
+                batch_size,
+                self.max_bytes_in_flight,
+                |count| {
+                    imported += count as u64;
+                    progress.report(imported);
+                },
+            );
+            budget.close();
+            if let Ok(written) = written {
+                progress.report(start + written);
+            }
+            written
+        })
+    }
+}
+
+/// Writes the decoded blocks in order, handing back a permit and the budget of each
+/// block as it moves into the write batch so the batch size is not limited by
+/// [`Pipeline::max_in_flight`]. `on_commit` is called with the size of each batch.
+fn write_in_order<F: FnMut(usize)>(
+    db: &ReadWriteDb,
+    decoded: Receiver<(u64, u64, Result<NewBlock, Error>)>,
+    budget: &ByteBudget,
+    permits: &SyncSender<()>,
+    batch_size: usize,
+    max_batch_bytes: u64,
+    mut on_commit: F,
+) -> Result<u64, Error> {
+    let mut pending = BTreeMap::new();
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut batch_bytes = 0u64;
+    let mut next_index = 0;
+    for (index, size, block) in decoded {
+        pending.insert(index, (size, block));
+        while let Some((size, block)) = pending.remove(&next_index) {
+            budget.release(size);
+            let _ = permits.send(());
+            batch.push(block?);
+            batch_bytes = batch_bytes.saturating_add(size);
+            next_index += 1;
+            if batch.len() == batch_size || batch_bytes >= max_batch_bytes {
+                db.add_blocks(&batch)?;
+                on_commit(batch.len());
+                batch.clear();
+                batch_bytes = 0;
+            }
+        }
+    }
+    db.add_blocks(&batch)?;
+    Ok(next_index)
+}
@@ -0,0 +1,216 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Incremental indexing
+//!
+//! An [`Indexer`] follows the chain and passes every new block, every block
+//! removed by a reorganization and every new pool transaction to its registered
+//! handlers. The blocks indexed so far are saved to a state file after each
+//! block, so after a crash indexing resumes where it stopped. A block whose
+//! handlers ran but which was not saved yet is passed again, handlers must
+//! tolerate seeing a block twice.
+
+use monero::cryptonote::hash::Hashable;
+use monero::{Block, Hash};
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::scan::read_u64;
+use super::{Error, MoneroDB};
+
+/// Number of indexed block hashes kept to unwind reorganizations
+pub const MAX_REORG_DEPTH: usize = 100;
+
+/// Receives the changes to the chain and pool from an [`Indexer`]
+///
+pub trait IndexHandler {
+    /// Called for each block added to the main chain, in height order
+    ///
+    fn on_block_added(&mut self, height: u64, block: &Block) -> Result<(), Error>;
+
+    /// Called for each block removed from the main chain by a reorganization,
+    /// from the top down, before the blocks replacing them are added
+    ///
+    fn on_block_popped(&mut self, height: u64, block_hash: &Hash) -> Result<(), Error> {
+        let _ = (height, block_hash);
+        Ok(())
+    }
+
+    /// Called for each transaction added to the pool
+    ///
+    fn on_pool_tx(&mut self, tx_hash: &Hash, tx: &monero::Transaction) -> Result<(), Error> {
+        let _ = (tx_hash, tx);
+        Ok(())
+    }
+}
+
+/// Drives [`IndexHandler`]s from the chain, see the [module docs](self)
+///
+pub struct Indexer<'a> {
+    db: &'a MoneroDB,
+    handlers: Vec<Box<dyn IndexHandler + Send + 'a>>,
+    state_path: PathBuf,
+    /// Height and hash of the last indexed blocks, the highest last
+    indexed: VecDeque<(u64, Hash)>,
+    seen_pool_txs: HashSet<Hash>,
+}
+
+impl<'a> Indexer<'a> {
+    /// Creates an indexer saving its progress to `state_path`, resuming from the
+    /// progress saved there if the file exists
+    ///
+    pub fn new(db: &'a MoneroDB, state_path: impl AsRef<Path>) -> Result<Self, Error> {
+        let state_path = state_path.as_ref().to_path_buf();
+        let indexed = match fs::File::open(&state_path) {
+            Ok(mut file) => read_state(&mut file)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => VecDeque::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Indexer {
+            db,
+            handlers: Vec::new(),
+            state_path,
+            indexed,
+            seen_pool_txs: HashSet::new(),
+        })
+    }
+
+    /// Adds a handler, handlers are called in the order they were registered
+    ///
+    pub fn register(&mut self, handler: impl IndexHandler + Send + 'a) {
+        self.handlers.push(Box::new(handler));
+    }
+
+    /// Height of the highest indexed block, the high-water mark
+    ///
+    pub fn indexed_height(&self) -> Option<u64> {
+        self.indexed.back().map(|(height, _)| *height)
+    }
+
+    /// Unwinds blocks no longer in the main chain then indexes every new block
+    /// and pool transaction, returning the number of blocks added.
+    ///
+    /// Fails with [`Error::ReorgTooDeep`] before unwinding anything if even the
+    /// oldest of the last [`MAX_REORG_DEPTH`] indexed blocks left the main chain,
+    /// see [`Indexer::reindex_from`] to recover.
+    ///
+    pub fn run_once(&mut self) -> Result<u64, Error> {
+        let chain_height = self.db.get_blockchain_height()?;
+        if let Some(&(height, hash)) = self.indexed.front() {
+            if height > 0 && !self.in_main_chain(height, &hash, chain_height)? {
+                return Err(Error::ReorgTooDeep { height });
+            }
+        }
+        while let Some(&(height, hash)) = self.indexed.back() {
+            if self.in_main_chain(height, &hash, chain_height)? {
+                break;
+            }
+            for handler in &mut self.handlers {
+                handler.on_block_popped(height, &hash)?;
+            }
+            self.indexed.pop_back();
+            self.save()?;
+        }
+
+        let start = self.indexed_height().map_or(0, |height| height + 1);
+        for height in start..chain_height {
+            let block = self.db.get_block(height)?;
+            for handler in &mut self.handlers {
+                handler.on_block_added(height, &block)?;
+            }
+            self.indexed.push_back((height, block.id()));
+            if self.indexed.len() > MAX_REORG_DEPTH {
+                self.indexed.pop_front();
+            }
+            self.save()?;
+        }
+
+        let pool_txs: HashSet<Hash> = self.db.get_txpool_hashes()?.into_iter().collect();
+        for tx_hash in pool_txs.difference(&self.seen_pool_txs) {
+            let tx = match self.db.get_txpool_tx(tx_hash) {
+                Ok(tx) => tx,
+                // removed from the pool since it was listed
+                Err(e) if e.is_not_found() => continue,
+                Err(e) => return Err(e),
+            };
+            for handler in &mut self.handlers {
+                handler.on_pool_tx(tx_hash, &tx)?;
+            }
+        }
+        self.seen_pool_txs = pool_txs;
+
+        Ok(chain_height.saturating_sub(start))
+    }
+
+    /// Follows the chain, calling [`Indexer::run_once`] each time the chain grows
+    /// and at least every `poll_interval`. Only returns on error, including
+    /// [`Error::Cancelled`] once the cancel handle of the database is cancelled.
+    ///
+    pub fn follow(&mut self, poll_interval: Duration) -> Result<(), Error> {
+        loop {
+            self.run_once()?;
+            let next_height = self.indexed_height().map_or(0, |height| height + 1);
+            self.db.check_cancelled(next_height)?;
+            self.db.wait_for_height(next_height + 1, poll_interval)?;
+        }
+    }
+
+    /// Makes the next [`Indexer::run_once`] index from `height`, forgetting the
+    /// blocks indexed at and above it without calling any handler, and saves it.
+    ///
+    /// This is the way out of [`Error::ReorgTooDeep`]: the handlers drop what they
+    /// derived from the blocks at and above `height`, then indexing restarts there.
+    /// `reindex_from(0)` resets the indexer to index the whole chain again.
+    ///
+    pub fn reindex_from(&mut self, height: u64) -> Result<(), Error> {
+        self.indexed.clear();
+        if let Some(below) = height.checked_sub(1) {
+            self.indexed
+                .push_back((below, self.db.get_block_hash(below)?));
+        }
+        self.seen_pool_txs.clear();
+        self.save()
+    }
+
+    fn in_main_chain(&self, height: u64, hash: &Hash, chain_height: u64) -> Result<bool, Error> {
+        Ok(height < chain_height && self.db.get_block_hash(height)? == *hash)
+    }
+
+    /// Writes the indexed blocks to the state file, replacing it atomically
+    fn save(&self) -> Result<(), Error> {
+        let mut state = Vec::with_capacity(8 + self.indexed.len() * 40);
+        state.extend_from_slice(&(self.indexed.len() as u64).to_le_bytes());
+        for (height, hash) in &self.indexed {
+            state.extend_from_slice(&height.to_le_bytes());
+            state.extend_from_slice(hash.as_bytes());
+        }
+        let tmp_path = self.state_path.with_extension("tmp");
+        fs::write(&tmp_path, state)?;
+        fs::rename(&tmp_path, &self.state_path)?;
+        Ok(())
+    }
+}
+
+fn read_state<R: io::Read>(reader: &mut R) -> Result<VecDeque<(u64, Hash)>, Error> {
+    let count = read_u64(reader)?;
+    let mut indexed = VecDeque::new();
+    for _ in 0..count {
+        let height = read_u64(reader)?;
+        let mut hash = [0; 32];
+        reader.read_exact(&mut hash)?;
+        indexed.push_back((height, Hash::from_slice(&hash)));
+    }
+    Ok(indexed)
+}
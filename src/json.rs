@@ -0,0 +1,226 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! JSON views of database items, shaped like the monerod RPC responses
+//!
+//! Enabled with the `serde` feature. Hashes are hex encoded and field names follow
+//! monerod so the output can replace RPC responses in explorer backends.
+
+use monero::cryptonote::hash::Hashable;
+use monero::database::transaction::{OutTx, TxIndex, TxPoolMeta};
+use monero::Hash;
+use serde::{Serialize, Serializer};
+
+use super::{Error, MoneroDB};
+
+fn hash_hex(hash: &Hash) -> String {
+    hex::encode(hash.as_bytes())
+}
+
+/// Serializes a hash as a hex string, for use with `serialize_with`
+pub(crate) fn serialize_hash<S: Serializer>(hash: &Hash, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hash_hex(hash))
+}
+
+/// Serializes a key or key image as a hex string, for use with `serialize_with`
+pub(crate) fn serialize_key<S: Serializer>(
+    key: &[u8; 32],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hex::encode(key))
+}
+
+/// A block header as returned by monerod's `get_block_header_by_height`
+///
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BlockHeader {
+    /// Major version of the block
+    pub major_version: u64,
+    /// Minor version of the block
+    pub minor_version: u64,
+    /// Block timestamp
+    pub timestamp: u64,
+    /// Hash of the previous block
+    pub prev_hash: String,
+    /// Block nonce
+    pub nonce: u32,
+    /// Height of the block
+    pub height: u64,
+    /// Number of blocks on top of this one
+    pub depth: u64,
+    /// Hash of the block
+    pub hash: String,
+    /// Difficulty of the block, lower 64 bits
+    pub difficulty: u64,
+    /// Difficulty of the block as a hex string
+    pub wide_difficulty: String,
+    /// Cumulative difficulty of the chain up to the block, lower 64 bits
+    pub cumulative_difficulty: u64,
+    /// Cumulative difficulty of the chain up to the block as a hex string
+    pub wide_cumulative_difficulty: String,
+    /// Block weight
+    pub block_weight: u64,
+    /// Coins emitted up to and including the block
+    pub already_generated_coins: u64,
+    /// Number of non miner transactions in the block
+    pub num_txes: u64,
+    /// Hash of the miner transaction
+    pub miner_tx_hash: String,
+    /// Total amount of the miner transaction outputs, emission and fees
+    pub reward: u64,
+}
+
+/// A transaction index entry, with the hash of the transaction it describes
+///
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TxIndexEntry {
+    /// Hash of the transaction
+    pub tx_hash: String,
+    /// Database ID of the transaction
+    pub tx_id: u64,
+    /// Height of the block containing the transaction
+    pub block_height: u64,
+    /// Unlock time of the transaction
+    pub unlock_time: u64,
+}
+
+impl From<&TxIndex> for TxIndexEntry {
+    fn from(index: &TxIndex) -> Self {
+        TxIndexEntry {
+            tx_hash: hash_hex(&index.key),
+            tx_id: index.data.tx_id,
+            block_height: index.data.block_id,
+            unlock_time: index.data.unlock_time,
+        }
+    }
+}
+
+/// The transaction an output belongs to
+///
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OutputTx {
+    /// Global output ID
+    pub output_id: u64,
+    /// Hash of the transaction
+    pub tx_hash: String,
+    /// Index of the output in the transaction
+    pub local_index: u64,
+}
+
+impl From<&OutTx> for OutputTx {
+    fn from(out_tx: &OutTx) -> Self {
+        OutputTx {
+            output_id: out_tx.output_id,
+            tx_hash: hash_hex(&out_tx.tx_hash),
+            local_index: out_tx.local_index,
+        }
+    }
+}
+
+/// A transaction pool entry as returned by monerod's `get_transaction_pool`,
+/// without the transaction itself
+///
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PoolTransaction {
+    /// Hash of the transaction
+    pub id_hash: String,
+    /// Transaction weight
+    pub weight: u64,
+    /// Transaction fee
+    pub fee: u64,
+    /// When the transaction was added to the pool
+    pub receive_time: u64,
+    /// When the transaction was last relayed
+    pub last_relayed_time: u64,
+    /// Height of the highest block referenced by the inputs
+    pub max_used_block_height: u64,
+    /// Hash of the highest block referenced by the inputs
+    pub max_used_block_id_hash: String,
+    /// Height of the last block the transaction failed to be added at
+    pub last_failed_height: u64,
+    /// Hash of the last block the transaction failed to be added at
+    pub last_failed_id_hash: String,
+}
+
+impl PoolTransaction {
+    /// Creates the entry of the pool transaction `tx_hash`
+    ///
+    pub fn new(tx_hash: &Hash, meta: &TxPoolMeta) -> Self {
+        PoolTransaction {
+            id_hash: hash_hex(tx_hash),
+            weight: meta.weight,
+            fee: meta.fee,
+            receive_time: meta.receive_time,
+            last_relayed_time: meta.last_relayed_time,
+            max_used_block_height: meta.max_used_block_height,
+            max_used_block_id_hash: hash_hex(&meta.max_used_block_id),
+            last_failed_height: meta.last_failed_height,
+            last_failed_id_hash: hash_hex(&meta.last_failed_id),
+        }
+    }
+}
+
+impl MoneroDB {
+    /// Gets the header of the block at `height` in the monerod RPC shape
+    ///
+    pub fn get_block_header_json(&self, height: u64) -> Result<BlockHeader, Error> {
+        let block = self.get_block(height)?;
+        let info = self.get_block_info(height)?;
+        let prev_cumulative_difficulty = match height {
+            0 => 0,
+            _ => self.get_block_info(height - 1)?.cumulative_difficulty(),
+        };
+        let cumulative_difficulty = info.cumulative_difficulty();
+        let difficulty = cumulative_difficulty - prev_cumulative_difficulty;
+
+        Ok(BlockHeader {
+            major_version: block.header.major_version.0,
+            minor_version: block.header.minor_version.0,
+            timestamp: block.header.timestamp.0,
+            prev_hash: hash_hex(&block.header.prev_id),
+            nonce: block.header.nonce,
+            height,
+            depth: self.get_blockchain_height()? - height - 1,
+            hash: hash_hex(&info.block_hash),
+            difficulty: difficulty as u64,
+            wide_difficulty: format!("{:#x}", difficulty),
+            cumulative_difficulty: cumulative_difficulty as u64,
+            wide_cumulative_difficulty: format!("{:#x}", cumulative_difficulty),
+            block_weight: info.weight,
+            already_generated_coins: info.total_coins_generated,
+            num_txes: block.tx_hashes.len() as u64,
+            miner_tx_hash: hash_hex(&block.miner_tx.hash()),
+            reward: block
+                .miner_tx
+                .prefix
+                .outputs
+                .iter()
+                .map(|out| out.amount.0)
+                .sum(),
+        })
+    }
+
+    /// Gets the index entry of a transaction in the monerod RPC shape
+    ///
+    pub fn get_tx_index_json(&self, tx_hash: &Hash) -> Result<TxIndexEntry, Error> {
+        Ok(TxIndexEntry::from(&self.get_tx_indices(tx_hash)?))
+    }
+
+    /// Gets the pool entry of a transaction in the monerod RPC shape
+    ///
+    pub fn get_txpool_entry_json(&self, tx_hash: &Hash) -> Result<PoolTransaction, Error> {
+        Ok(PoolTransaction::new(
+            tx_hash,
+            &self.get_txpool_meta(tx_hash)?,
+        ))
+    }
+}
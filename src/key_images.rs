@@ -0,0 +1,102 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Locating where key images were spent
+
+use monero::blockdata::transaction::TxIn;
+use monero::Hash;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::parallel::{scan_parallel, DEFAULT_CHUNK_SIZE};
+use super::{Error, MoneroDB};
+
+/// The input that spent a key image
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyImageSpend {
+    /// Height of the block containing the spending transaction
+    pub height: u64,
+    /// Hash of the spending transaction
+    pub tx_hash: Hash,
+    /// Index of the input within the transaction
+    pub input_index: usize,
+}
+
+impl MoneroDB {
+    /// Finds the transaction that spent `key_image`.
+    ///
+    /// The database has no index from key images to transactions, so the blocks in
+    /// `range` are scanned in parallel, only when the key image is known to be spent.
+    /// Returns `None` if it is unspent or was spent outside of `range`.
+    ///
+    pub fn key_image_spent_height(
+        &self,
+        key_image: &[u8; 32],
+        range: Range<u64>,
+    ) -> Result<Option<KeyImageSpend>, Error> {
+        if !self.is_key_image_spent(key_image)? {
+            return Ok(None);
+        }
+        let range = range.start..range.end.min(self.get_blockchain_height()?);
+
+        // a key image is spent once, the chunks left after it is found are skipped
+        let found = AtomicBool::new(false);
+        let mut spend = None;
+        scan_parallel(
+            range,
+            &self.options.scan,
+            "key_image_spent_height",
+            DEFAULT_CHUNK_SIZE,
+            |chunk| {
+                if found.load(Ordering::Relaxed) {
+                    return Ok(None);
+                }
+                let spend = self.find_key_image(key_image, chunk)?;
+                if spend.is_some() {
+                    found.store(true, Ordering::Relaxed);
+                }
+                Ok(spend)
+            },
+            |_, found| {
+                spend = spend.or(found);
+                Ok(())
+            },
+        )?;
+        Ok(spend)
+    }
+
+    fn find_key_image(
+        &self,
+        key_image: &[u8; 32],
+        range: Range<u64>,
+    ) -> Result<Option<KeyImageSpend>, Error> {
+        for height in range {
+            for tx_hash in self.get_block(height)?.tx_hashes {
+                let tx = self.get_tx_pruned_by_hash(&tx_hash)?;
+                for (input_index, input) in tx.prefix.inputs.iter().enumerate() {
+                    match input {
+                        TxIn::ToKey { k_image, .. } if k_image.image.as_bytes() == key_image => {
+                            return Ok(Some(KeyImageSpend {
+                                height,
+                                tx_hash,
+                                input_index,
+                            }));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+}
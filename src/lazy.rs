@@ -0,0 +1,434 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Lazily decoded views of blocks and transactions
+//!
+//! Decoding a whole block decodes its miner transaction too, and decoding a whole
+//! transaction decodes all its signatures. The views here decode only the parts
+//! that are asked for, from the raw blob.
+
+use monero::blockdata::block::BlockHeader;
+use monero::blockdata::transaction::{TxIn, TxOut};
+use monero::consensus::encode::Error::ParseFailed;
+use monero::consensus::{deserialize, deserialize_partial, Decodable};
+use monero::{Block, Hash, Transaction, VarInt};
+use std::ops::Range;
+
+use super::monero_db::get_raw_item;
+use super::{BlockHeight, Error, MoneroDB};
+
+/// First RingCT type whose `ecdhInfo` holds only the 8 byte encrypted amount
+const RCT_TYPE_BULLETPROOF2: u8 = 4;
+/// RingCT type with the pseudo outputs in the base rather than the prunable part
+const RCT_TYPE_SIMPLE: u8 = 2;
+
+/// A block with its header decoded, the miner transaction and transaction hashes
+/// are decoded from the blob each time they are asked for. Created by
+/// [`MoneroDB::get_block_lazy`].
+///
+#[derive(Debug, Clone)]
+pub struct LazyBlock {
+    /// Header of the block
+    pub header: BlockHeader,
+    blob: Vec<u8>,
+    header_len: usize,
+}
+
+impl LazyBlock {
+    /// Decodes the header of a serialized block
+    ///
+    pub fn from_blob(blob: Vec<u8>) -> Result<Self, Error> {
+        let (header, header_len) = deserialize_partial(&blob)?;
+        Ok(LazyBlock {
+            header,
+            blob,
+            header_len,
+        })
+    }
+
+    /// Gets the serialized block
+    ///
+    pub fn blob(&self) -> &[u8] {
+        &self.blob
+    }
+
+    /// Decodes the miner transaction
+    ///
+    pub fn miner_tx(&self) -> Result<Transaction, Error> {
+        Ok(deserialize_partial(&self.blob[self.header_len..])?.0)
+    }
+
+    /// Decodes the hashes of the transactions of the block, skipping over the miner
+    /// transaction
+    ///
+    pub fn tx_hashes(&self) -> Result<Vec<Hash>, Error> {
+        let (_, miner_tx_len) = deserialize_partial::<Transaction>(&self.blob[self.header_len..])?;
+        Ok(deserialize(&self.blob[self.header_len + miner_tx_len..])?)
+    }
+
+    /// Decodes the whole block
+    ///
+    pub fn to_block(&self) -> Result<Block, Error> {
+        Ok(deserialize(&self.blob)?)
+    }
+}
+
+/// Offsets of the prefix fields of a serialized transaction
+struct Layout {
+    version: u64,
+    unlock_time: u64,
+    inputs: Range<usize>,
+    input_count: usize,
+    outputs: Range<usize>,
+    output_count: usize,
+    extra: Range<usize>,
+}
+
+fn truncated() -> Error {
+    Error::MoneroDecodingError(ParseFailed("transaction blob is truncated"))
+}
+
+/// Decodes a `T` at `at`, returning it and the offset after it
+fn decode_at<T: Decodable>(blob: &[u8], at: usize) -> Result<(T, usize), Error> {
+    let (value, len) = deserialize_partial(blob.get(at..).ok_or_else(truncated)?)?;
+    Ok((value, at + len))
+}
+
+/// A serialized transaction whose prefix fields are decoded when asked for, and
+/// whose signatures are only exposed as byte ranges. Created by
+/// [`MoneroDB::get_tx_view`].
+///
+/// Each call walks the prefix again, callers reading several fields of many
+/// transactions should decode the whole prefix with [`TxView::to_transaction`] if
+/// they need most of it.
+///
+#[derive(Debug, Clone)]
+pub struct TxView {
+    blob: Vec<u8>,
+}
+
+impl TxView {
+    /// Wraps a serialized transaction, either the full blob or its pruned part
+    ///
+    pub fn new(blob: Vec<u8>) -> Self {
+        TxView { blob }
+    }
+
+    /// Gets the serialized transaction
+    ///
+    pub fn blob(&self) -> &[u8] {
+        &self.blob
+    }
+
+    fn layout(&self) -> Result<Layout, Error> {
+        let (VarInt(version), at) = decode_at(&self.blob, 0)?;
+        let (VarInt(unlock_time), inputs_start) = decode_at(&self.blob, at)?;
+        let (inputs, outputs_start) = decode_at::<Vec<TxIn>>(&self.blob, inputs_start)?;
+        let (outputs, extra_start) = decode_at::<Vec<TxOut>>(&self.blob, outputs_start)?;
+        let (VarInt(extra_len), extra_data) = decode_at(&self.blob, extra_start)?;
+        let extra_end = usize::try_from(extra_len)
+            .ok()
+            .and_then(|extra_len| extra_data.checked_add(extra_len))
+            .filter(|extra_end| *extra_end <= self.blob.len())
+            .ok_or_else(truncated)?;
+        Ok(Layout {
+            version,
+            unlock_time,
+            inputs: inputs_start..outputs_start,
+            input_count: inputs.len(),
+            outputs: outputs_start..extra_start,
+            output_count: outputs.len(),
+            extra: extra_data..extra_end,
+        })
+    }
+
+    /// Decodes the transaction version
+    ///
+    pub fn version(&self) -> Result<u64, Error> {
+        Ok(decode_at::<VarInt>(&self.blob, 0)?.0 .0)
+    }
+
+    /// Decodes the unlock time
+    ///
+    pub fn unlock_time(&self) -> Result<u64, Error> {
+        let (_, at) = decode_at::<VarInt>(&self.blob, 0)?;
+        Ok(decode_at::<VarInt>(&self.blob, at)?.0 .0)
+    }
+
+    /// Decodes the inputs, without decoding the outputs
+    ///
+    pub fn inputs(&self) -> Result<Vec<TxIn>, Error> {
+        let (_, at) = decode_at::<VarInt>(&self.blob, 0)?;
+        let (_, at) = decode_at::<VarInt>(&self.blob, at)?;
+        Ok(decode_at(&self.blob, at)?.0)
+    }
+
+    /// Decodes the outputs
+    ///
+    pub fn outputs(&self) -> Result<Vec<TxOut>, Error> {
+        let layout = self.layout()?;
+        Ok(decode_at(&self.blob, layout.outputs.start)?.0)
+    }
+
+    /// Gets the raw extra field, without its length prefix
+    ///
+    pub fn extra(&self) -> Result<&[u8], Error> {
+        Ok(&self.blob[self.layout()?.extra])
+    }
+
+    /// Gets the range of the prefix in [`TxView::blob`], the part hashed for the
+    /// prefix hash
+    ///
+    pub fn prefix_range(&self) -> Result<Range<usize>, Error> {
+        Ok(0..self.layout()?.extra.end)
+    }
+
+    /// Gets the range of the ring signatures of a version 1 transaction, `None` for
+    /// RingCT transactions. The range is empty if the blob is pruned.
+    ///
+    pub fn signatures_range(&self) -> Result<Option<Range<usize>>, Error> {
+        let layout = self.layout()?;
+        Ok((layout.version == 1).then_some(layout.extra.end..self.blob.len()))
+    }
+
+    /// Gets the range of the RingCT base, the fee, encrypted amounts and output
+    /// commitments, `None` for version 1 transactions
+    ///
+    pub fn rct_base_range(&self) -> Result<Option<Range<usize>>, Error> {
+        let layout = self.layout()?;
+        if layout.version == 1 {
+            return Ok(None);
+        }
+        let start = layout.extra.end;
+        let rct_type = *self.blob.get(start).ok_or_else(truncated)?;
+        if rct_type == 0 {
+            return Ok(Some(start..start + 1));
+        }
+        let (_, fee_end) = decode_at::<VarInt>(&self.blob, start + 1)?;
+        let pseudo_outs = if rct_type == RCT_TYPE_SIMPLE {
+            32 * layout.input_count
+        } else {
+            0
+        };
+        let ecdh_info = if rct_type >= RCT_TYPE_BULLETPROOF2 {
+            8
+        } else {
+            64
+        };
+        let end = fee_end + pseudo_outs + layout.output_count * (ecdh_info + 32);
+        if end > self.blob.len() {
+            return Err(truncated());
+        }
+        Ok(Some(start..end))
+    }
+
+    /// Gets the range of the prunable RingCT data, the range proofs, ring
+    /// signatures and pseudo outputs, `None` for version 1 transactions. The range
+    /// is empty if the blob is pruned.
+    ///
+    pub fn rct_prunable_range(&self) -> Result<Option<Range<usize>>, Error> {
+        Ok(self.rct_base_range()?.map(|base| base.end..self.blob.len()))
+    }
+
+    /// Decodes the whole transaction
+    ///
+    pub fn to_transaction(&self) -> Result<Transaction, Error> {
+        Ok(deserialize(&self.blob)?)
+    }
+}
+
+impl MoneroDB {
+    /// Gets a block with only its header decoded, for workloads that don't need the
+    /// miner transaction or transaction hashes of every block
+    ///
+    pub fn get_block_lazy(&self, block_height: impl Into<BlockHeight>) -> Result<LazyBlock, Error> {
+        LazyBlock::from_blob(self.get_block_blob(block_height)?)
+    }
+
+    /// Gets a view of a transaction that decodes its fields when asked for. On a
+    /// pruned database the view holds only the pruned part if the prunable part
+    /// has been removed.
+    ///
+    pub fn get_tx_view(&self, tx_hash: &Hash) -> Result<TxView, Error> {
+        match self.get_tx_blob(tx_hash) {
+            Ok(blob) => Ok(TxView::new(blob)),
+            Err(Error::Pruned { .. }) => {
+                let tx_id = self.get_tx_indices(tx_hash)?.data.tx_id;
+                let pruned = get_raw_item(
+                    self,
+                    self.sub_dbs.txs_pruned,
+                    &tx_id.to_le_bytes(),
+                    &[0],
+                    15,
+                )?;
+                Ok(TxView::new(pruned))
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TxView;
+    use curve25519_dalek::constants::ED25519_BASEPOINT_COMPRESSED;
+    use monero::consensus::serialize;
+    use monero::database::transaction::TransactionPruned;
+    use monero::util::ringct::RctSig;
+
+    const INPUTS: usize = 2;
+    const OUTPUTS: usize = 2;
+    const RING_SIZE: usize = 3;
+    /// Rounds of the aggregated range proof of two outputs
+    const PROOF_ROUNDS: u8 = 7;
+
+    fn keys(blob: &mut Vec<u8>, count: usize) {
+        blob.extend((0..count).flat_map(|i| [i as u8; 32]));
+    }
+
+    /// Serializes a prefix spending `INPUTS` rings of `RING_SIZE` members into
+    /// `OUTPUTS` outputs, using the base point as output and transaction key
+    fn prefix(version: u8) -> Vec<u8> {
+        let amount = if version == 1 { 100 } else { 0 };
+        let mut blob = vec![version, 0, INPUTS as u8];
+        for input in 0..INPUTS {
+            blob.extend([0x02, amount, RING_SIZE as u8]);
+            blob.extend([1; RING_SIZE]);
+            blob.extend([0x10 + input as u8; 32]);
+        }
+        blob.push(OUTPUTS as u8);
+        for _ in 0..OUTPUTS {
+            blob.extend([amount / 2, 0x02]);
+            blob.extend(ED25519_BASEPOINT_COMPRESSED.as_bytes());
+        }
+        blob.extend([33, 0x01]);
+        blob.extend(ED25519_BASEPOINT_COMPRESSED.as_bytes());
+        blob
+    }
+
+    /// Serializes a RingCT base of `rct_type` with a fee of 100
+    fn rct_base(blob: &mut Vec<u8>, rct_type: u8) {
+        blob.extend([rct_type, 100]);
+        if rct_type == 2 {
+            keys(blob, INPUTS);
+        }
+        for _ in 0..OUTPUTS {
+            if rct_type >= 4 {
+                blob.extend([0xee; 8]);
+            } else {
+                keys(blob, 2);
+            }
+        }
+        keys(blob, OUTPUTS);
+    }
+
+    fn v1_tx() -> Vec<u8> {
+        let mut blob = prefix(1);
+        keys(&mut blob, INPUTS * RING_SIZE * 2);
+        blob
+    }
+
+    fn simple_tx() -> Vec<u8> {
+        let mut blob = prefix(2);
+        rct_base(&mut blob, 2);
+        // a Borromean range proof per output
+        keys(&mut blob, OUTPUTS * (64 * 3 + 1));
+        // an MLSAG of two columns per input
+        keys(&mut blob, INPUTS * (RING_SIZE * 2 + 1));
+        blob
+    }
+
+    fn clsag_tx(rct_type: u8) -> Vec<u8> {
+        let mut blob = prefix(2);
+        rct_base(&mut blob, rct_type);
+        // one aggregated Bulletproof or Bulletproof+, with 6 keys before L and R
+        blob.push(1);
+        keys(&mut blob, 6);
+        for _ in 0..2 {
+            blob.push(PROOF_ROUNDS);
+            keys(&mut blob, PROOF_ROUNDS.into());
+        }
+        // a, b and t of a Bulletproof
+        if rct_type == 5 {
+            keys(&mut blob, 3);
+        }
+        // a CLSAG per input
+        keys(&mut blob, INPUTS * (RING_SIZE + 2));
+        // pseudo outputs
+        keys(&mut blob, INPUTS);
+        blob
+    }
+
+    /// Checks the ranges of `blob` and of its pruned part against the sizes of
+    /// the fully decoded transaction
+    fn check_ranges(blob: Vec<u8>) {
+        let tx = TxView::new(blob.clone()).to_transaction().unwrap();
+        assert_eq!(serialize(&tx), blob);
+        let prefix_end = serialize(&tx.prefix).len();
+        let pruned_end = serialize(&TransactionPruned {
+            prefix: tx.prefix.clone(),
+            rct_signatures: RctSig {
+                sig: tx.rct_signatures.sig.clone(),
+                p: None,
+            },
+        })
+        .len();
+
+        let view = TxView::new(blob.clone());
+        assert_eq!(view.prefix_range().unwrap(), 0..prefix_end);
+        let pruned = TxView::new(blob[..pruned_end].to_vec());
+        if tx.prefix.version.0 == 1 {
+            assert_eq!(pruned_end, prefix_end);
+            assert_eq!(
+                view.signatures_range().unwrap(),
+                Some(prefix_end..blob.len())
+            );
+            assert_eq!(view.rct_base_range().unwrap(), None);
+            assert_eq!(view.rct_prunable_range().unwrap(), None);
+            assert_eq!(
+                pruned.signatures_range().unwrap(),
+                Some(prefix_end..prefix_end)
+            );
+        } else {
+            assert_eq!(view.signatures_range().unwrap(), None);
+            assert_eq!(view.rct_base_range().unwrap(), Some(prefix_end..pruned_end));
+            assert_eq!(
+                view.rct_prunable_range().unwrap(),
+                Some(pruned_end..blob.len())
+            );
+            assert_eq!(
+                pruned.rct_base_range().unwrap(),
+                Some(prefix_end..pruned_end)
+            );
+            assert_eq!(
+                pruned.rct_prunable_range().unwrap(),
+                Some(pruned_end..pruned_end)
+            );
+        }
+    }
+
+    #[test]
+    fn v1_ranges() {
+        check_ranges(v1_tx());
+    }
+
+    #[test]
+    fn simple_ranges() {
+        check_ranges(simple_tx());
+    }
+
+    #[test]
+    fn clsag_ranges() {
+        check_ranges(clsag_tx(5));
+        check_ranges(clsag_tx(6));
+    }
+}
@@ -15,10 +15,12 @@
 //! This is a library for interacting with a Monero database. Currently only reading
 //! from the database is supported. This library should support all current and
 //! previous Monero types, however only the current database version is supported (5).
+//! Newer databases can be partially read with [`OpenOptions::allow_newer`].
 //!
 
 // Coding conventions
-#![forbid(unsafe_code)]
+#![cfg_attr(not(feature = "ffi"), forbid(unsafe_code))]
+#![cfg_attr(feature = "ffi", deny(unsafe_code))]
 #![deny(non_upper_case_globals)]
 #![deny(non_camel_case_types)]
 #![deny(unused_mut)]
@@ -26,12 +28,124 @@
 
 use thiserror::Error;
 
+mod alt_blocks;
+mod analysis;
+mod audit;
+#[cfg(feature = "bench")]
+pub mod bench;
+mod block_info;
+#[cfg(feature = "broker")]
+mod broker;
+mod cancel;
+mod checkpoints;
+pub mod consensus;
+mod decoy;
+mod diagnostics;
+mod diff;
+mod difficulty;
+mod emission;
+mod explorer;
+mod fees;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod graph;
+mod handle;
+mod hard_fork;
+mod hex;
+#[cfg(feature = "http-explorer")]
+mod http;
+mod ids;
+pub mod import;
+mod indexer;
+#[cfg(feature = "serde")]
+mod json;
+mod key_images;
+mod lazy;
 mod monero_db;
+mod notify;
+mod options;
+mod orphans;
+mod outputs;
+mod parallel;
+mod payment_id;
+mod pow;
+mod prefetch;
+mod progress;
+mod properties;
+mod retry;
+mod rings;
+mod scan;
+#[cfg(feature = "serai")]
+mod serai;
+mod sink;
+mod snapshot;
+mod stats;
 mod sub_db;
+mod template;
+mod timing;
+mod txpool;
+mod validate;
+mod write;
 
+pub use alt_blocks::{AltBlocksIter, AltChainEvaluation};
+pub use analysis::{
+    Anomaly, AnomalyKind, BurnPeriod, BurnReason, BurnReport, BurnedOutput, DuplicateRing,
+    InputLocation, LockedOutput, OffsetPattern, RingAnalysis, UnlockPoint, UnusualOffsets,
+};
+pub use audit::{BlockHashMismatch, TxRootAudit, TxRootMismatch};
+pub use block_info::BlockMetadata;
+#[cfg(feature = "broker")]
+pub use broker::{BrokerPublisher, EventFormat};
+pub use cancel::CancelHandle;
+pub use checkpoints::Checkpoints;
+pub use diagnostics::OpenError;
+pub use diff::DbDiff;
+pub use difficulty::{next_difficulty, DifficultyMismatch};
+pub use emission::{
+    base_block_reward, calculate_penalized_reward, BlockPenalty, EmissionPeriod, SupplyAudit,
+    SupplyDivergence,
+};
+pub use explorer::{BlockSummary, MAX_PREFIX_MATCHES};
+pub use fees::FeePercentiles;
+pub use graph::{GraphFormat, TxGraph};
+pub use handle::DbHandle;
+pub use hard_fork::{HardForkHeight, HardForkVotes};
+pub use ids::{Amount, BlockHeight, OutputId, TxId};
+pub use indexer::{IndexHandler, Indexer, MAX_REORG_DEPTH};
+#[cfg(feature = "serde")]
+pub use json::{BlockHeader, OutputTx, PoolTransaction, TxIndexEntry};
+pub use key_images::KeyImageSpend;
+pub use lazy::{LazyBlock, TxView};
 pub use monero_db::MoneroDB;
+#[cfg(feature = "zmq")]
+pub use notify::ZmqPublisher;
+pub use options::OpenOptions;
+pub use orphans::OrphanedBlock;
+pub use outputs::CoinbaseOutput;
+pub use parallel::ScanOptions;
+pub use payment_id::PaymentId;
+pub use progress::Progress;
+pub use properties::{DbProperties, Property, PropertyValue};
+pub use retry::RetryPolicy;
+pub use rings::{resolve_key_offsets, RingMember};
+pub use scan::{Balance, OutputCache, OwnedOutput, ScanKeys, SubaddressLookahead};
+#[cfg(feature = "serai")]
+pub use serai::{block_from_serai, block_to_serai, transaction_from_serai, transaction_to_serai};
+pub use sink::{ChainSink, ChannelSink, FileSink, SinkHandler, SinkItem, SinkReceiver};
+pub use snapshot::{ReadSnapshot, ScanCursor, SnapshotBlocks, SnapshotTransactions};
+pub use stats::{
+    BlockIoCounts, Distribution, HardForkStats, IntervalStats, RingStats, TimeseriesField,
+    TimeseriesPoint, TimestampSource,
+};
+pub use template::BlockTemplate;
+pub use timing::{QueryStats, SlowQuery};
+pub use txpool::{DandelionState, PoolConflict, TxPoolFlags};
+pub use validate::{ChainValidation, ValidationDepth, ValidationOptions, ValidationStage};
+pub use write::{genesis_block, NewBlock, ReadWriteDb};
 
 const ZERO_KEY: [u8; 8] = [0; 8];
+/// The database version this library reads and writes
+const DB_VERSION: u32 = 5;
 
 /// Potential errors
 ///
@@ -39,11 +153,175 @@ const ZERO_KEY: [u8; 8] = [0; 8];
 pub enum Error {
     /// Errors relating to the database eg: retrieving value from database
     #[error("Retrieval error: {0:#?}")]
-    DatabaseError(#[from] lmdb::Error),
+    DatabaseError(lmdb::Error),
+    /// The requested item is not in the database
+    #[error("Key {} not found in {db}", ::hex::encode(key))]
+    NotFound {
+        /// Name of the sub-database
+        db: &'static str,
+        /// Key that was looked up
+        key: Vec<u8>,
+    },
+    /// The requested item was removed when the database was pruned
+    #[error("Key {} in {db} has been pruned", ::hex::encode(key))]
+    Pruned {
+        /// Name of the sub-database
+        db: &'static str,
+        /// Key that was looked up
+        key: Vec<u8>,
+    },
+    /// A stored item could not be decoded
+    #[error("Corrupt value for key {} in {db}: {source}", ::hex::encode(key))]
+    Corrupt {
+        /// Name of the sub-database
+        db: &'static str,
+        /// Key of the item
+        key: Vec<u8>,
+        /// Why decoding failed
+        #[source]
+        source: monero::consensus::encode::Error,
+    },
+    /// A stored value is larger than [`OpenOptions::max_value_size`]
+    #[error("Value for key {} in {db} is over {limit} bytes", ::hex::encode(key))]
+    ValueTooLarge {
+        /// Name of the sub-database
+        db: &'static str,
+        /// Key of the item
+        key: Vec<u8>,
+        /// Size of the value
+        size: usize,
+        /// Largest value size allowed
+        limit: usize,
+    },
+    /// All reader slots are in use, retry later or raise [`OpenOptions::max_readers`],
+    /// see also [`OpenOptions::retry_policy`]
+    #[error("Database is busy, all reader slots are in use")]
+    Busy,
+    /// Another process grew the memory map beyond the size this handle was opened
+    /// with, the database has to be reopened
+    #[error("Database map was resized by another process")]
+    MapResized,
     /// The database is readonly
     #[error("Database is read only")]
     ReadOnly,
+    /// A snapshot was taken through a handle opened without locking, see
+    /// [`OpenOptions::lock_readers`]
+    #[error("Snapshots need a handle opened with reader locking")]
+    UnlockedSnapshot,
     /// Error deserializing the retrieved data
     #[error("Failed to decode value from database: {0}")]
     MoneroDecodingError(#[from] monero::consensus::encode::Error),
+    /// Error reading from or writing to an external reader or writer
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+    /// Error parsing JSON input
+    #[cfg(feature = "serde")]
+    #[error("JSON error: {0}")]
+    JsonError(#[from] serde_json::Error),
+    /// A string is not a valid hex encoded hash or key image
+    #[error("Invalid hex string: {0}")]
+    InvalidHex(String),
+    /// The database could not be opened
+    #[error("Failed to open database: {0}")]
+    OpenError(#[from] OpenError),
+    /// The database version is newer than this library supports
+    #[error("Unsupported database version {found}, the newest supported version is {supported}")]
+    UnsupportedVersion {
+        /// Version of the database
+        found: u32,
+        /// Newest version this library supports
+        supported: u32,
+    },
+    /// An entry of the output amounts table is too short or holds an invalid key
+    #[error("Invalid output entry for amount {amount} at index {amount_index}")]
+    InvalidOutkey {
+        /// Amount of the output
+        amount: u64,
+        /// Index of the output among the outputs with the same amount
+        amount_index: u64,
+    },
+    /// There are too few outputs of an amount to pick the requested number of decoys
+    #[error("Only {available} outputs of amount {amount} available, {requested} decoys requested")]
+    NotEnoughOutputs {
+        /// Amount of the outputs
+        amount: u64,
+        /// Number of outputs that could be used as decoys
+        available: u64,
+        /// Number of decoys requested
+        requested: usize,
+    },
+    /// The database belongs to another network than expected
+    #[error("Database does not belong to {0:?}")]
+    WrongNetwork(monero::Network),
+    /// A checkpoint is malformed or conflicts with another checkpoint
+    #[error("Invalid checkpoint: {0}")]
+    InvalidCheckpoint(String),
+    /// Refused to modify a block protected by a checkpoint
+    #[error("Block {height} is at or below the checkpoint at height {checkpoint}")]
+    BelowCheckpoint {
+        /// Height of the block that would have been modified
+        height: u64,
+        /// Height of the highest checkpoint
+        checkpoint: u64,
+    },
+    /// The operation was cancelled with a [`CancelHandle`]
+    #[error("Cancelled, blocks below height {height} were processed")]
+    Cancelled {
+        /// Height below which every block was processed
+        height: u64,
+    },
+    /// A reorganization went deeper than the blocks an [`Indexer`] keeps to unwind
+    /// it, see [`Indexer::reindex_from`]
+    #[error("Reorganization below height {height} is deeper than the indexer can unwind")]
+    ReorgTooDeep {
+        /// Height of the oldest block the indexer kept, which left the main chain
+        height: u64,
+    },
+    /// Error setting up write notifications
+    #[error("Notification error: {0}")]
+    NotifyError(String),
+    /// A block can not be added to the chain
+    #[error("Invalid block: {0}")]
+    InvalidBlock(String),
+    /// A transaction can not be added to the pool
+    #[error("Invalid transaction: {0}")]
+    InvalidTransaction(String),
+    /// A proof of work hash could not be computed
+    #[error("Proof of work error: {0}")]
+    PowError(String),
+    /// Error checking transaction outputs for ownership
+    #[error("Failed to scan transaction outputs: {0}")]
+    OutputScanError(#[from] monero::blockdata::transaction::Error),
+}
+
+impl From<lmdb::Error> for Error {
+    fn from(e: lmdb::Error) -> Self {
+        match e {
+            lmdb::Error::ReadersFull => Error::Busy,
+            lmdb::Error::MapResized => Error::MapResized,
+            e => Error::DatabaseError(e),
+        }
+    }
+}
+
+impl Error {
+    /// Converts an LMDB error from a lookup of `key` in the sub-database `db`
+    pub(crate) fn from_lookup(e: lmdb::Error, db: &'static str, key: &[u8]) -> Self {
+        match e {
+            lmdb::Error::NotFound => Error::NotFound {
+                db,
+                key: key.to_vec(),
+            },
+            e => Error::from(e),
+        }
+    }
+
+    /// Returns if the error is the key not being found
+    pub(crate) fn is_not_found(&self) -> bool {
+        match self {
+            Error::NotFound { .. } => true,
+            Error::DatabaseError(e) => e.to_err_code() == -30798,
+            _ => false,
+        }
+    }
 }
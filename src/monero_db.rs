@@ -10,59 +10,125 @@
 // The above copyright notice and this permission notice shall be included in all
 // copies or substantial portions of the Software.
 
-use lmdb::{Cursor, Database, Environment, EnvironmentFlags, Transaction, WriteFlags};
+use lmdb::{Cursor, Database, Environment, Transaction, WriteFlags};
+use monero::consensus::encode::{self, Error::ParseFailed};
 use monero::consensus::{deserialize, serialize, Decodable, Encodable};
 use monero::cryptonote::hash::Hashable;
 use monero::database::block::{AltBlock, BlockHeight, BlockInfo};
 use monero::database::transaction::{
     OutTx, PreRctOutkey, RctOutkey, TransactionPruned, TxIndex, TxOutputIdx, TxPoolMeta,
 };
-use monero::{Block, Hash, PublicKey};
+use monero::{Block, Hash, Network, PublicKey};
 use std::fmt::Debug;
+use std::io::Read;
+use std::ops::Range;
 use std::path::Path;
-
+use std::time::Instant;
+
+use super::checkpoints::Checkpoints;
+use super::consensus;
+use super::ids::{self, Amount, OutputId, TxId};
+use super::notify::TipSubscribers;
+#[cfg(feature = "zmq")]
+use super::notify::ZmqPublisher;
+use super::options::OpenOptions;
 use super::sub_db::MoneroSubDB;
-use super::{Error, ZERO_KEY};
+use super::timing::QueryTimer;
+use super::txpool::{insert_pool_tx, read_pool_key_images};
+use super::write::ReadWriteDb;
+use super::{Error, DB_VERSION, ZERO_KEY};
 
 /// Struct containing the data needed to interact with a
 /// Monero database
 ///
+/// It is `Send + Sync`, use [`MoneroDB::into_handle`] to share it between threads.
+///
 pub struct MoneroDB {
     /// Internal LMDB environment
     pub env: Environment,
-    sub_dbs: MoneroSubDB,
-    read_only: bool,
+    pub(crate) sub_dbs: MoneroSubDB,
+    pub(crate) options: OpenOptions,
+    pub(crate) checkpoints: Checkpoints,
+    pub(crate) tip_subscribers: TipSubscribers,
+    pub(crate) query_timer: Option<QueryTimer>,
+    pub(crate) newer_version: Option<u32>,
+    #[cfg(feature = "zmq")]
+    pub(crate) zmq_publisher: Option<ZmqPublisher>,
 }
 
 impl MoneroDB {
+    pub(crate) fn from_parts(env: Environment, sub_dbs: MoneroSubDB, options: OpenOptions) -> Self {
+        MoneroDB {
+            env,
+            sub_dbs,
+            options,
+            checkpoints: Checkpoints::default(),
+            tip_subscribers: TipSubscribers::default(),
+            query_timer: None,
+            newer_version: None,
+            #[cfg(feature = "zmq")]
+            zmq_publisher: None,
+        }
+    }
+
     /// Opens the Monero the database
     ///
     pub fn open(dir: &Path, read_only: bool) -> Result<Self, Error> {
-        let mut env = Environment::new();
-        let mut flags = EnvironmentFlags::NO_READAHEAD;
-        if read_only {
-            flags |= EnvironmentFlags::READ_ONLY;
-            flags |= EnvironmentFlags::NO_LOCK;
+        OpenOptions::new().read_only(read_only).open(dir)
+    }
+
+    /// Creates a new Monero database containing only the genesis block of `network`
+    ///
+    pub fn create(dir: &Path, network: Network) -> Result<ReadWriteDb, Error> {
+        OpenOptions::new().create(dir, network)
+    }
+
+    /// Opens a mainnet database, `dir` can be a monerod data directory such as
+    /// `~/.bitmonero` or the `lmdb` directory in it. Fails with
+    /// [`Error::WrongNetwork`] if the genesis block is not the mainnet one.
+    ///
+    pub fn open_mainnet(dir: &Path) -> Result<Self, Error> {
+        open_network(dir, Network::Mainnet)
+    }
+
+    /// Opens a stagenet database, `dir` can be a monerod data directory such as
+    /// `~/.bitmonero/stagenet` or the `lmdb` directory in it. Fails with
+    /// [`Error::WrongNetwork`] if the genesis block is not the stagenet one.
+    ///
+    pub fn open_stagenet(dir: &Path) -> Result<Self, Error> {
+        open_network(dir, Network::Stagenet)
+    }
+
+    /// Opens a testnet database, `dir` can be a monerod data directory such as
+    /// `~/.bitmonero/testnet` or the `lmdb` directory in it. Fails with
+    /// [`Error::WrongNetwork`] if the genesis block is not the testnet one.
+    ///
+    pub fn open_testnet(dir: &Path) -> Result<Self, Error> {
+        open_network(dir, Network::Testnet)
+    }
+
+    /// Gets the network of the database, the one it was opened or created for or
+    /// else the one whose genesis block it holds. `None` for an empty database or
+    /// one with an unknown genesis block.
+    ///
+    pub fn network(&self) -> Result<Option<Network>, Error> {
+        if let Some(network) = self.options.network {
+            return Ok(Some(network));
         }
-        env.set_max_dbs(32)
-            .set_map_size(1 << 30)
-            .set_max_readers(126)
-            .set_flags(flags);
-        let env = env.open(dir)?;
-        env.check_do_resize()?;
-        let sub_dbs = MoneroSubDB::new(&env)?;
-        Ok(MoneroDB {
-            env,
-            sub_dbs,
-            read_only,
-        })
+        if self.get_blockchain_height()? == 0 {
+            return Ok(None);
+        }
+        let genesis_hash = self.get_block_hash(0)?;
+        Ok([Network::Mainnet, Network::Stagenet, Network::Testnet]
+            .into_iter()
+            .find(|network| consensus::genesis_hash(*network) == genesis_hash))
     }
 
     /// Gets alternative block from the database.
     ///
     pub fn get_alt_block(&self, block_hash: &Hash) -> Result<AltBlock, Error> {
         get_item(
-            &self.env,
+            self,
             self.sub_dbs.alt_blocks,
             block_hash.as_bytes(),
             &[0],
@@ -72,9 +138,10 @@ impl MoneroDB {
 
     /// Gets block from the database.
     ///
-    pub fn get_block(&self, block_height: u64) -> Result<Block, Error> {
+    pub fn get_block(&self, block_height: impl Into<ids::BlockHeight>) -> Result<Block, Error> {
+        let block_height = block_height.into();
         get_item(
-            &self.env,
+            self,
             self.sub_dbs.blocks,
             &block_height.to_le_bytes(),
             &[0],
@@ -82,11 +149,46 @@ impl MoneroDB {
         )
     }
 
+    /// Gets the serialized block from the database
+    ///
+    pub fn get_block_blob(
+        &self,
+        block_height: impl Into<ids::BlockHeight>,
+    ) -> Result<Vec<u8>, Error> {
+        let mut blob = Vec::new();
+        self.get_block_blob_into(block_height, &mut blob)?;
+        Ok(blob)
+    }
+
+    /// Gets the serialized block into `buf`, replacing its contents. Reusing the
+    /// buffer avoids an allocation per block when reading many blocks.
+    ///
+    pub fn get_block_blob_into(
+        &self,
+        block_height: impl Into<ids::BlockHeight>,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), Error> {
+        let block_height = block_height.into();
+        buf.clear();
+        get_raw_item_into(
+            self,
+            self.sub_dbs.blocks,
+            &block_height.to_le_bytes(),
+            &[0],
+            15,
+            buf,
+        )
+    }
+
     /// Gets block info from the database
     ///
-    pub fn get_block_info(&self, block_height: u64) -> Result<BlockInfo, Error> {
+    pub fn get_block_info(
+        &self,
+        block_height: impl Into<ids::BlockHeight>,
+    ) -> Result<BlockInfo, Error> {
+        let block_height = block_height.into();
         get_item(
-            &self.env,
+            self,
             self.sub_dbs.block_info,
             &ZERO_KEY,
             &block_height.to_le_bytes(),
@@ -96,16 +198,20 @@ impl MoneroDB {
 
     /// Gets the blocks difficulty from the database
     ///
-    pub fn get_block_difficulty(&self, block_height: u64) -> Result<u128, Error> {
+    pub fn get_block_difficulty(
+        &self,
+        block_height: impl Into<ids::BlockHeight>,
+    ) -> Result<u128, Error> {
+        let block_height = block_height.into();
         let prev_block = get_item::<BlockInfo>(
-            &self.env,
+            self,
             self.sub_dbs.block_info,
             &ZERO_KEY,
-            &(block_height - 1).to_le_bytes(),
+            &(block_height.0 - 1).to_le_bytes(),
             2,
         )?;
         let block = get_item::<BlockInfo>(
-            &self.env,
+            self,
             self.sub_dbs.block_info,
             &ZERO_KEY,
             &block_height.to_le_bytes(),
@@ -119,7 +225,7 @@ impl MoneroDB {
     ///
     pub fn get_block_height(&self, block_hash: &Hash) -> Result<BlockHeight, Error> {
         get_item(
-            &self.env,
+            self,
             self.sub_dbs.block_heights,
             &ZERO_KEY,
             block_hash.as_bytes(),
@@ -130,23 +236,24 @@ impl MoneroDB {
     /// Get the height of the blockchain (1 + height of max block)
     ///
     pub fn get_blockchain_height(&self) -> Result<u64, Error> {
-        let transaction = self.env.begin_ro_txn()?;
+        let transaction = self.begin_read()?;
         let stats = transaction.stat(self.sub_dbs.block_heights)?;
         Ok(stats.entries() as u64)
     }
 
     /// Get the transaction count of the blockchain
     pub fn get_tx_count(&self) -> Result<u64, Error> {
-        let transaction = self.env.begin_ro_txn()?;
+        let transaction = self.begin_read()?;
         let stats = transaction.stat(self.sub_dbs.txs_pruned)?;
         Ok(stats.entries() as u64)
     }
 
     /// Gets the blocks hard fork version
     ///
-    pub fn get_hf_version(&self, block_height: u64) -> Result<u8, Error> {
+    pub fn get_hf_version(&self, block_height: impl Into<ids::BlockHeight>) -> Result<u8, Error> {
+        let block_height = block_height.into();
         get_item::<u8>(
-            &self.env,
+            self,
             self.sub_dbs.hf_versions,
             &block_height.to_le_bytes(),
             &[0],
@@ -156,9 +263,10 @@ impl MoneroDB {
 
     /// Gets the pruned part of the transaction
     ///
-    pub fn get_tx_pruned(&self, txn_id: u64) -> Result<TransactionPruned, Error> {
+    pub fn get_tx_pruned(&self, txn_id: impl Into<TxId>) -> Result<TransactionPruned, Error> {
+        let txn_id = txn_id.into();
         get_item(
-            &self.env,
+            self,
             self.sub_dbs.txs_pruned,
             &txn_id.to_le_bytes(),
             &[0],
@@ -166,27 +274,82 @@ impl MoneroDB {
         )
     }
 
-    /// Gets the prunable part of the transaction
+    /// Gets the pruned part of the transaction with the given hash
     ///
-    pub fn get_tx_prunable(&self, txn_id: u64) -> Result<Vec<u8>, Error> {
-        get_raw_item(
-            &self.env,
+    pub fn get_tx_pruned_by_hash(&self, txn_hash: &Hash) -> Result<TransactionPruned, Error> {
+        let tx_index = self.get_tx_indices(txn_hash)?;
+        self.get_tx_pruned(tx_index.data.tx_id)
+    }
+
+    /// Gets the full serialized transaction, this fails if the prunable part
+    /// has been pruned
+    ///
+    pub fn get_tx_blob(&self, txn_hash: &Hash) -> Result<Vec<u8>, Error> {
+        let mut blob = Vec::new();
+        self.get_tx_blob_into(txn_hash, &mut blob)?;
+        Ok(blob)
+    }
+
+    /// Gets the full serialized transaction into `buf`, replacing its contents
+    ///
+    pub fn get_tx_blob_into(&self, txn_hash: &Hash, buf: &mut Vec<u8>) -> Result<(), Error> {
+        let txn_id = self.get_tx_indices(txn_hash)?.data.tx_id;
+        buf.clear();
+        get_raw_item_into(
+            self,
+            self.sub_dbs.txs_pruned,
+            &txn_id.to_le_bytes(),
+            &[0],
+            15,
+            buf,
+        )?;
+        self.tx_prunable_into(txn_id.into(), buf)
+    }
+
+    /// Gets the transaction with the given hash, decoded without first copying its
+    /// pruned and prunable parts into one blob. Fails with [`Error::Pruned`] if the
+    /// prunable part was removed when the database was pruned.
+    ///
+    pub fn get_tx(&self, txn_hash: &Hash) -> Result<monero::Transaction, Error> {
+        self.short_snapshot()?.get_tx(txn_hash)
+    }
+
+    /// Gets the prunable part of the transaction, fails with [`Error::Pruned`] if it
+    /// was removed when the database was pruned
+    ///
+    pub fn get_tx_prunable(&self, txn_id: impl Into<TxId>) -> Result<Vec<u8>, Error> {
+        let mut blob = Vec::new();
+        self.tx_prunable_into(txn_id.into(), &mut blob)?;
+        Ok(blob)
+    }
+
+    /// Appends the prunable part of the transaction to `buf`
+    fn tx_prunable_into(&self, txn_id: TxId, buf: &mut Vec<u8>) -> Result<(), Error> {
+        match get_raw_item_into(
+            self,
             self.sub_dbs.txs_prunable,
             &txn_id.to_le_bytes(),
             &[0],
             15,
-        )
+            buf,
+        ) {
+            Err(Error::NotFound { db, key }) if self.get_db_pruning_seed().unwrap_or(0) != 0 => {
+                Err(Error::Pruned { db, key })
+            }
+            result => result,
+        }
     }
 
     /// Gets the [`Outkey`] of a transactions output
     ///
     pub fn get_output_rct_outkey(
         &self,
-        amount: u64,
+        amount: impl Into<Amount>,
         amount_output_index: u64,
     ) -> Result<RctOutkey, Error> {
+        let amount = amount.into();
         get_item(
-            &self.env,
+            self,
             self.sub_dbs.output_amounts,
             &amount.to_le_bytes(),
             &amount_output_index.to_le_bytes(),
@@ -198,11 +361,12 @@ impl MoneroDB {
     ///
     pub fn get_output_pre_rct_outkey(
         &self,
-        amount: u64,
+        amount: impl Into<Amount>,
         amount_output_index: u64,
     ) -> Result<PreRctOutkey, Error> {
+        let amount = amount.into();
         get_item(
-            &self.env,
+            self,
             self.sub_dbs.output_amounts,
             &amount.to_le_bytes(),
             &amount_output_index.to_le_bytes(),
@@ -212,9 +376,10 @@ impl MoneroDB {
 
     /// Gets amount output indices of the transaction outputs
     ///
-    pub fn get_tx_output_idx(&self, txn_id: u64) -> Result<TxOutputIdx, Error> {
+    pub fn get_tx_output_idx(&self, txn_id: impl Into<TxId>) -> Result<TxOutputIdx, Error> {
+        let txn_id = txn_id.into();
         get_item(
-            &self.env,
+            self,
             self.sub_dbs.tx_outputs,
             &txn_id.to_le_bytes(),
             &[0],
@@ -224,9 +389,10 @@ impl MoneroDB {
 
     /// Gets the hash of the prunable part of the transaction
     ///
-    pub fn get_txs_prunable_hash(&self, txn_id: u64) -> Result<Hash, Error> {
+    pub fn get_txs_prunable_hash(&self, txn_id: impl Into<TxId>) -> Result<Hash, Error> {
+        let txn_id = txn_id.into();
         get_item(
-            &self.env,
+            self,
             self.sub_dbs.txs_prunable_hash,
             &txn_id.to_le_bytes(),
             &[0],
@@ -236,9 +402,10 @@ impl MoneroDB {
 
     /// Gets the height of the transaction if that transactions block height + 5500 is >= the blockchain height
     ///
-    pub fn get_txs_prunable_tip(&self, txn_id: u64) -> Result<u64, Error> {
+    pub fn get_txs_prunable_tip(&self, txn_id: impl Into<TxId>) -> Result<u64, Error> {
+        let txn_id = txn_id.into();
         get_item(
-            &self.env,
+            self,
             self.sub_dbs.txs_prunable_tip,
             &txn_id.to_le_bytes(),
             &[0],
@@ -249,14 +416,15 @@ impl MoneroDB {
     /// Gets the height of the first block where the blocks height + 5500 is = the blockchain height
     ///
     pub fn get_prunable_tip(&self) -> Result<u64, Error> {
-        get_item::<u64>(&self.env, self.sub_dbs.txs_prunable_tip, &[0], &[0], 0)
+        get_item::<u64>(self, self.sub_dbs.txs_prunable_tip, &[0], &[0], 0)
     }
 
     /// Gets the [`OutTx`] of an output
     ///
-    pub fn get_output_tx(&self, output_id: u64) -> Result<OutTx, Error> {
+    pub fn get_output_tx(&self, output_id: impl Into<OutputId>) -> Result<OutTx, Error> {
+        let output_id = output_id.into();
         get_item(
-            &self.env,
+            self,
             self.sub_dbs.output_txs,
             &ZERO_KEY,
             &output_id.to_le_bytes(),
@@ -264,11 +432,23 @@ impl MoneroDB {
         )
     }
 
+    /// Gets the [`OutTx`] of many outputs under a single read transaction, in
+    /// the order of `output_ids`
+    ///
+    pub fn get_output_txs(&self, output_ids: &[OutputId]) -> Result<Vec<OutTx>, Error> {
+        get_items(
+            self,
+            self.sub_dbs.output_txs,
+            output_ids.iter().map(|id| (ZERO_KEY, id.to_le_bytes())),
+            2,
+        )
+    }
+
     /// Get the [`TxIndex`] from a transaction  
     ///
     pub fn get_tx_indices(&self, txn_hash: &Hash) -> Result<TxIndex, Error> {
         get_item(
-            &self.env,
+            self,
             self.sub_dbs.tx_indices,
             &ZERO_KEY,
             txn_hash.as_bytes(),
@@ -279,23 +459,18 @@ impl MoneroDB {
     /// Returns if a key image has already been spent
     ///
     pub fn is_key_image_spent(&self, spent_key: &[u8]) -> Result<bool, Error> {
-        let data =
-            get_item::<PublicKey>(&self.env, self.sub_dbs.spent_keys, &ZERO_KEY, spent_key, 2);
-        if let Err(Error::DatabaseError(e)) = data {
-            // key not found
-            if e.to_err_code() == -30798 {
-                return Ok(false);
-            }
-            return Err(Error::DatabaseError(e));
+        match get_item::<PublicKey>(self, self.sub_dbs.spent_keys, &ZERO_KEY, spent_key, 2) {
+            Ok(_) => Ok(true),
+            Err(e) if e.is_not_found() => Ok(false),
+            Err(e) => Err(e),
         }
-        Ok(true)
     }
 
     /// Get the transaction from transaction pool
     ///
     pub fn get_txpool_tx(&self, txn_hash: &Hash) -> Result<monero::Transaction, Error> {
         get_item(
-            &self.env,
+            self,
             self.sub_dbs.txpool_blob,
             txn_hash.as_bytes(),
             &[0],
@@ -307,7 +482,7 @@ impl MoneroDB {
     ///
     pub fn get_txpool_meta(&self, txn_hash: &Hash) -> Result<TxPoolMeta, Error> {
         get_item(
-            &self.env,
+            self,
             self.sub_dbs.txpool_meta,
             txn_hash.as_bytes(),
             &[0],
@@ -319,37 +494,103 @@ impl MoneroDB {
     ///
     pub fn get_db_version(&self) -> Result<u32, Error> {
         let key = b"version\0";
-        get_item::<u32>(&self.env, self.sub_dbs.properties, key, &[0], 15)
+        get_item::<u32>(self, self.sub_dbs.properties, key, &[0], 15)
     }
 
     /// Gets the pruning seed of the database
     ///
     pub fn get_db_pruning_seed(&self) -> Result<u32, Error> {
         let key = b"pruning_seed\0";
-        get_item::<u32>(&self.env, self.sub_dbs.properties, key, &[0], 15)
+        get_item::<u32>(self, self.sub_dbs.properties, key, &[0], 15)
     }
 
     /// Gets the max block size
     ///
     pub fn get_max_block_size(&self) -> Result<u64, Error> {
         let key = b"max_block_size\0";
-        get_item::<u64>(&self.env, self.sub_dbs.properties, key, &[0], 15)
+        get_item::<u64>(self, self.sub_dbs.properties, key, &[0], 15)
     }
 
-    /// Returns if the database is readonly
+    /// Returns if the database is readonly. Writes need a [`ReadWriteDb`] whatever
+    /// this returns.
     ///
     pub fn is_readonly(&self) -> bool {
-        self.read_only
+        self.options.read_only
+    }
+
+    /// Fails if the database is newer than supported and the table format may have changed,
+    /// see [`OpenOptions::allow_newer`]
+    pub(crate) fn check_table_version(&self, sub_db: Database) -> Result<(), Error> {
+        match self.newer_version {
+            Some(version) if !self.sub_dbs.is_version_independent(sub_db) => {
+                Err(Error::UnsupportedVersion {
+                    found: version,
+                    supported: DB_VERSION,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails with [`Error::ValueTooLarge`] if a value of `size` bytes is over
+    /// [`OpenOptions::max_value_size`]
+    pub(crate) fn check_value_size(
+        &self,
+        db: &'static str,
+        key: &[u8],
+        size: usize,
+    ) -> Result<(), Error> {
+        match self.options.max_value_size {
+            Some(limit) if size > limit => Err(Error::ValueTooLarge {
+                db,
+                key: key.to_vec(),
+                size,
+                limit,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Calls `f` with the [`BlockInfo`] of every block in `range` in order, walking
+    /// `block_info` with a single cursor
+    ///
+    pub(crate) fn for_each_block_info<F>(&self, range: Range<u64>, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(BlockInfo) -> Result<(), Error>,
+    {
+        self.for_each_raw_block_info(range, |value| f(deserialize(value)?))
     }
 
-    // ##################### WRITE TRANSACTIONS #####################
+    /// Calls `f` with the serialized block info of every block in `range` in order
+    pub(crate) fn for_each_raw_block_info<F>(
+        &self,
+        range: Range<u64>,
+        mut f: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(&[u8]) -> Result<(), Error>,
+    {
+        if range.is_empty() {
+            return Ok(());
+        }
+        self.check_table_version(self.sub_dbs.block_info)?;
+        let transaction = self.begin_read()?;
+        let curser = transaction.open_ro_cursor(self.sub_dbs.block_info)?;
+        let (_, mut value) = curser.get(Some(&ZERO_KEY), Some(&range.start.to_le_bytes()), 2)?;
+        for height in range.clone() {
+            if height != range.start {
+                value = curser.get(None, None, 9)?.1;
+            }
+            f(value)?;
+        }
+        Ok(())
+    }
+}
 
+impl ReadWriteDb {
     /// Adds an alt block to the database
     ///
     pub fn add_alt_block(&self, alt_block: &AltBlock) -> Result<(), Error> {
-        if self.is_readonly() {
-            return Err(Error::ReadOnly);
-        }
         let block_id = alt_block.block.id().as_bytes().to_vec();
         put_item(
             &self.env,
@@ -360,62 +601,180 @@ impl MoneroDB {
         )
     }
 
-    /// Adds a transaction to the transaction pool
+    /// Adds a transaction to the transaction pool.
+    ///
+    /// The transaction is rejected if it is already in the pool or the chain, or
+    /// if one of its key images is spent in the chain or by a pool transaction. In
+    /// the last case the conflicting pool transactions are flagged as double spend
+    /// seen.
     ///
     pub fn add_txpool_tx(
         &self,
         tx: &monero::Transaction,
         tx_meta: &TxPoolMeta,
     ) -> Result<(), Error> {
-        if self.is_readonly() {
-            return Err(Error::ReadOnly);
+        let mut transaction = self.env.begin_rw_txn()?;
+        let mut pool_key_images = read_pool_key_images(self, &transaction)?;
+        let inserted = insert_pool_tx(self, &mut transaction, tx, tx_meta, &mut pool_key_images);
+        // conflicting pool transactions are flagged even if `tx` is rejected
+        if let Ok(()) | Err(Error::InvalidTransaction(_)) = inserted {
+            transaction.commit()?;
         }
-        let tx_hash = tx.hash().as_bytes().to_vec();
-        put_item(
-            &self.env,
-            self.sub_dbs.txpool_meta,
-            &tx_hash,
-            &serialize(tx_meta),
-            WriteFlags::NO_DUP_DATA,
-        )?;
-        put_item(
-            &self.env,
-            self.sub_dbs.txpool_blob,
-            &tx_hash,
-            &serialize(tx),
-            WriteFlags::NO_DUP_DATA,
-        )?;
+        inserted?;
+        self.notify_txpool_add(tx, tx_meta);
         Ok(())
     }
 }
 
-fn get_raw_item(
-    env: &Environment,
-    db: Database,
+/// Opens the database of `network` in `dir`, or in its `lmdb` subdirectory if there
+/// is one, as monerod lays out its data directory
+fn open_network(dir: &Path, network: Network) -> Result<MoneroDB, Error> {
+    let lmdb_dir = dir.join("lmdb");
+    let dir = if lmdb_dir.join("data.mdb").is_file() {
+        &lmdb_dir
+    } else {
+        dir
+    };
+    OpenOptions::new().network(network).open(dir)
+}
+
+/// Looks up an item and passes the value, borrowed from the memory map, to `f`
+///
+pub(crate) fn read_item<T>(
+    db: &MoneroDB,
+    sub_db: Database,
+    key: &[u8],
+    data: &[u8],
+    op: u32,
+    f: impl FnOnce(&[u8]) -> T,
+) -> Result<T, Error> {
+    db.check_table_version(sub_db)?;
+    let start = Instant::now();
+    let transaction = db.begin_read()?;
+    let curser = transaction.open_ro_cursor(sub_db)?;
+    let value = curser
+        .get(Some(key), Some(data), op)
+        .map_err(|e| Error::from_lookup(e, db.sub_dbs.name(sub_db), lookup_key(key, data, op)))?
+        .1;
+    db.check_value_size(
+        db.sub_dbs.name(sub_db),
+        lookup_key(key, data, op),
+        value.len(),
+    )?;
+
+    if let Some(query_timer) = &db.query_timer {
+        query_timer.record(db.sub_dbs.name(sub_db), start.elapsed(), value.len());
+    }
+    Ok(f(value))
+}
+
+pub(crate) fn get_raw_item(
+    db: &MoneroDB,
+    sub_db: Database,
     key: &[u8],
     data: &[u8],
     op: u32,
 ) -> Result<Vec<u8>, Error> {
-    let transaction = env.begin_ro_txn()?;
-    let curser = transaction.open_ro_cursor(db)?;
-    let value = curser.get(Some(key), Some(data), op)?;
+    read_item(db, sub_db, key, data, op, |value| value.to_vec())
+}
 
-    Ok(value.1.to_vec())
+/// Appends the value of an item to `buf`
+///
+pub(crate) fn get_raw_item_into(
+    db: &MoneroDB,
+    sub_db: Database,
+    key: &[u8],
+    data: &[u8],
+    op: u32,
+    buf: &mut Vec<u8>,
+) -> Result<(), Error> {
+    read_item(db, sub_db, key, data, op, |value| {
+        buf.extend_from_slice(value)
+    })
 }
 
-fn get_item<T: Decodable + Encodable + Debug>(
-    env: &Environment,
-    db: Database,
+pub(crate) fn get_item<T: Decodable + Encodable + Debug>(
+    db: &MoneroDB,
+    sub_db: Database,
     key: &[u8],
     data: &[u8],
     op: u32,
 ) -> Result<T, Error> {
-    let value = get_raw_item(env, db, key, data, op)?;
+    read_item(db, sub_db, key, data, op, |value| deserialize(value))?.map_err(|source| {
+        Error::Corrupt {
+            db: db.sub_dbs.name(sub_db),
+            key: lookup_key(key, data, op).to_vec(),
+            source,
+        }
+    })
+}
+
+/// Looks up many items with the same cursor operation under one read transaction
+///
+pub(crate) fn get_items<T: Decodable + Encodable + Debug>(
+    db: &MoneroDB,
+    sub_db: Database,
+    lookups: impl IntoIterator<Item = ([u8; 8], [u8; 8])>,
+    op: u32,
+) -> Result<Vec<T>, Error> {
+    db.check_table_version(sub_db)?;
+    let name = db.sub_dbs.name(sub_db);
+    let transaction = db.begin_read()?;
+    let curser = transaction.open_ro_cursor(sub_db)?;
+
+    lookups
+        .into_iter()
+        .map(|(key, data)| {
+            let start = Instant::now();
+            let lookup_key = lookup_key(&key, &data, op);
+            let value = curser
+                .get(Some(&key), Some(&data), op)
+                .map_err(|e| Error::from_lookup(e, name, lookup_key))?
+                .1;
+            db.check_value_size(name, lookup_key, value.len())?;
+            if let Some(query_timer) = &db.query_timer {
+                query_timer.record(name, start.elapsed(), value.len());
+            }
+            deserialize(value).map_err(|source| Error::Corrupt {
+                db: name,
+                key: lookup_key.to_vec(),
+                source,
+            })
+        })
+        .collect()
+}
+
+/// Decodes a `T` from `reader`, reading at most `limit` bytes. Fails if the value
+/// ends before the reader does, like [`deserialize`] does for slices.
+///
+/// Lets values split over several parts, such as the pruned and prunable parts of
+/// a transaction, be decoded straight from the memory map without joining them.
+///
+pub(crate) fn deserialize_from<T: Decodable, R: Read>(
+    reader: R,
+    limit: u64,
+) -> Result<T, encode::Error> {
+    let mut reader = reader.take(limit);
+    let value = T::consensus_decode(&mut reader)?;
+    if reader.read(&mut [0])? != 0 {
+        return Err(ParseFailed(
+            "data not consumed entirely when explicitly deserializing",
+        ));
+    }
+    Ok(value)
+}
 
-    Ok(deserialize(&value)?)
+/// The key identifying the item of a lookup, dup-sorted tables are looked up
+/// by their data under a shared key
+fn lookup_key<'a>(key: &'a [u8], data: &'a [u8], op: u32) -> &'a [u8] {
+    if op == 2 {
+        data
+    } else {
+        key
+    }
 }
 
-fn put_item(
+pub(crate) fn put_item(
     env: &Environment,
     db: Database,
     key: &Vec<u8>,
@@ -423,6 +782,24 @@ fn put_item(
     flags: WriteFlags,
 ) -> Result<(), Error> {
     let mut transaction = env.begin_rw_txn()?;
-    let mut curser = transaction.open_rw_cursor(db)?;
-    Ok(curser.put(key, data, flags)?)
+    {
+        let mut curser = transaction.open_rw_cursor(db)?;
+        curser.put(key, data, flags)?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+pub(crate) fn delete_item(env: &Environment, db: Database, key: &[u8]) -> Result<(), Error> {
+    let mut transaction = env.begin_rw_txn()?;
+    transaction.del(db, &key, None)?;
+    transaction.commit()?;
+    Ok(())
+}
+
+pub(crate) fn clear_db(env: &Environment, db: Database) -> Result<(), Error> {
+    let mut transaction = env.begin_rw_txn()?;
+    transaction.clear_db(db)?;
+    transaction.commit()?;
+    Ok(())
 }
@@ -0,0 +1,275 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Notifications about new blocks
+//!
+//! Subscribers created with [`MoneroDB::subscribe`] are told about new tip
+//! heights, whether the blocks were written through this library or found by
+//! polling a database another process writes to. With the `zmq` feature, blocks
+//! and pool transactions written by this crate are published in the same
+//! `json-minimal` format monerod uses, so existing ZMQ consumers can follow
+//! databases written from Rust.
+
+use monero::database::transaction::TxPoolMeta;
+use monero::Block;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::{Error, MoneroDB};
+
+/// Senders of the tip subscribers of a database handle
+#[derive(Debug, Default)]
+pub(crate) struct TipSubscribers {
+    senders: Mutex<Vec<(u64, Sender<u64>)>>,
+    /// Highest tip sent so far, plus one so zero means nothing was sent
+    last_sent: AtomicU64,
+    next_id: AtomicU64,
+}
+
+impl TipSubscribers {
+    fn subscribe(&self) -> (u64, Receiver<u64>) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = mpsc::channel();
+        self.senders.lock().unwrap().push((id, sender));
+        (id, receiver)
+    }
+
+    /// Subscribes until the returned guard is dropped
+    fn subscribe_scoped(&self) -> Subscription<'_> {
+        let (id, receiver) = self.subscribe();
+        Subscription {
+            subscribers: self,
+            id,
+            receiver,
+        }
+    }
+
+    fn unsubscribe(&self, id: u64) {
+        self.senders
+            .lock()
+            .unwrap()
+            .retain(|(sender_id, _)| *sender_id != id);
+    }
+
+    /// Sends `tip` to every subscriber if it is higher than the last tip sent,
+    /// dropping subscribers whose receiver is gone
+    fn publish(&self, tip: u64) {
+        if self.last_sent.fetch_max(tip + 1, Ordering::SeqCst) > tip {
+            return;
+        }
+        self.senders
+            .lock()
+            .unwrap()
+            .retain(|(_, sender)| sender.send(tip).is_ok());
+    }
+
+    /// Lowers the last tip sent after blocks were popped, so the next blocks
+    /// added are sent again
+    fn rewind(&self, tip: u64) {
+        self.last_sent.fetch_min(tip + 1, Ordering::SeqCst);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.senders.lock().unwrap().is_empty()
+    }
+}
+
+/// A subscription removed from the subscribers when dropped, so short lived
+/// waiters don't pile up while no tip is published
+struct Subscription<'a> {
+    subscribers: &'a TipSubscribers,
+    id: u64,
+    receiver: Receiver<u64>,
+}
+
+impl Drop for Subscription<'_> {
+    fn drop(&mut self) {
+        self.subscribers.unsubscribe(self.id);
+    }
+}
+
+#[cfg(feature = "zmq")]
+pub use self::zmq_publisher::ZmqPublisher;
+
+#[cfg(feature = "zmq")]
+mod zmq_publisher {
+    use monero::consensus::serialize;
+    use monero::cryptonote::hash::Hashable;
+    use monero::database::transaction::TxPoolMeta;
+    use monero::Block;
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    use crate::{Error, MoneroDB};
+
+    /// Publishes monerod style `json-minimal` notifications on a ZMQ PUB socket
+    ///
+    pub struct ZmqPublisher {
+        socket: Mutex<zmq::Socket>,
+    }
+
+    impl ZmqPublisher {
+        /// Creates a publisher bound to `endpoint`, for example `tcp://127.0.0.1:18083`
+        ///
+        pub fn bind(endpoint: &str) -> Result<Self, Error> {
+            let socket = zmq::Context::new()
+                .socket(zmq::PUB)
+                .map_err(|e| Error::NotifyError(e.to_string()))?;
+            socket
+                .bind(endpoint)
+                .map_err(|e| Error::NotifyError(e.to_string()))?;
+            Ok(ZmqPublisher {
+                socket: Mutex::new(socket),
+            })
+        }
+
+        fn publish(&self, topic: &str, body: serde_json::Value) {
+            let message = format!("{}:{}", topic, body);
+            if let Ok(socket) = self.socket.lock() {
+                // notifications are best effort, a failed send must not fail the write
+                let _ = socket.send(message.as_bytes(), 0);
+            }
+        }
+
+        pub(crate) fn chain_main(&self, first_height: u64, blocks: &[&Block]) {
+            let first_prev_id = match blocks.first() {
+                Some(block) => hex::encode(block.header.prev_id.as_bytes()),
+                None => return,
+            };
+            let ids: Vec<String> = blocks
+                .iter()
+                .map(|block| hex::encode(block.id().as_bytes()))
+                .collect();
+            self.publish(
+                "json-minimal-chain_main",
+                json!({
+                    "first_height": first_height,
+                    "first_prev_id": first_prev_id,
+                    "ids": ids,
+                }),
+            );
+        }
+
+        pub(crate) fn txpool_add(&self, tx: &monero::Transaction, tx_meta: &TxPoolMeta) {
+            self.publish(
+                "json-minimal-txpool_add",
+                json!([{
+                    "id": hex::encode(tx.hash().as_bytes()),
+                    "blob_size": serialize(tx).len(),
+                    "weight": tx_meta.weight,
+                    "fee": tx_meta.fee,
+                }]),
+            );
+        }
+    }
+
+    impl MoneroDB {
+        /// Publishes notifications for every block and pool transaction written
+        /// through this handle from now on
+        ///
+        pub fn set_zmq_publisher(&mut self, publisher: ZmqPublisher) {
+            self.zmq_publisher = Some(publisher);
+        }
+    }
+}
+
+impl MoneroDB {
+    /// Subscribes to new tip heights, the height of the top block is sent each
+    /// time the chain grows through a write on this handle or [`MoneroDB::poll_tip`]
+    ///
+    pub fn subscribe(&self) -> Receiver<u64> {
+        self.tip_subscribers.subscribe().1
+    }
+
+    /// Checks the blockchain height and notifies subscribers if the chain grew,
+    /// for following a database written to by another process. Returns the
+    /// current tip height, or `None` if the chain is empty.
+    ///
+    pub fn poll_tip(&self) -> Result<Option<u64>, Error> {
+        let tip = self.get_blockchain_height()?.checked_sub(1);
+        if let Some(tip) = tip {
+            self.tip_subscribers.publish(tip);
+        }
+        Ok(tip)
+    }
+
+    /// Spawns a thread calling [`MoneroDB::poll_tip`] every `interval`, it stops
+    /// once polling fails or no subscriber is left.
+    ///
+    /// Dropped subscribers are only noticed when a new tip is sent to them, so the
+    /// thread keeps running while the chain does not grow.
+    ///
+    pub fn spawn_tip_poller(self: Arc<Self>, interval: Duration) -> JoinHandle<Result<(), Error>> {
+        thread::spawn(move || {
+            while !self.tip_subscribers.is_empty() {
+                self.poll_tip()?;
+                thread::sleep(interval);
+            }
+            Ok(())
+        })
+    }
+
+    /// Blocks until the blockchain height reaches `height` or `timeout` passes,
+    /// returning if the height was reached.
+    ///
+    /// Blocks written through this handle wake the caller straight away, blocks
+    /// written by another process, such as a syncing monerod, are noticed by
+    /// polling once a second.
+    ///
+    pub fn wait_for_height(&self, height: u64, timeout: Duration) -> Result<bool, Error> {
+        const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+        let deadline = Instant::now() + timeout;
+        let subscription = self.tip_subscribers.subscribe_scoped();
+        loop {
+            if self.get_blockchain_height()? >= height {
+                return Ok(true);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(false);
+            }
+            // a tip or a timeout both mean the height needs checking again
+            let _ = subscription
+                .receiver
+                .recv_timeout(remaining.min(POLL_INTERVAL));
+        }
+    }
+
+    pub(crate) fn notify_chain_main(&self, first_height: u64, blocks: &[&Block]) {
+        if blocks.is_empty() {
+            return;
+        }
+        self.tip_subscribers
+            .publish(first_height + blocks.len() as u64 - 1);
+
+        #[cfg(feature = "zmq")]
+        if let Some(publisher) = &self.zmq_publisher {
+            publisher.chain_main(first_height, blocks);
+        }
+    }
+
+    pub(crate) fn notify_chain_pop(&self, new_height: u64) {
+        self.tip_subscribers.rewind(new_height.saturating_sub(1));
+    }
+
+    #[cfg_attr(not(feature = "zmq"), allow(unused_variables))]
+    pub(crate) fn notify_txpool_add(&self, tx: &monero::Transaction, tx_meta: &TxPoolMeta) {
+        #[cfg(feature = "zmq")]
+        if let Some(publisher) = &self.zmq_publisher {
+            publisher.txpool_add(tx, tx_meta);
+        }
+    }
+}
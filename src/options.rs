@@ -0,0 +1,326 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Options for opening a database
+
+use lmdb::{Environment, EnvironmentFlags};
+use monero::consensus::serialize;
+use monero::Network;
+use std::path::Path;
+
+use super::consensus::genesis_hash;
+use super::diagnostics::{check_data_dir, env_error};
+use super::parallel::ScanOptions;
+use super::retry::RetryPolicy;
+use super::sub_db::MoneroSubDB;
+use super::write::genesis_block;
+use super::{Error, MoneroDB, ReadWriteDb, DB_VERSION};
+
+/// Options used to open or create a [`MoneroDB`]
+///
+#[derive(Debug, Clone)]
+pub struct OpenOptions {
+    pub(crate) read_only: bool,
+    pub(crate) lock_readers: bool,
+    pub(crate) map_size: usize,
+    pub(crate) map_growth: usize,
+    pub(crate) max_readers: u32,
+    pub(crate) write_map: bool,
+    pub(crate) no_sync: bool,
+    pub(crate) batch_size: usize,
+    pub(crate) allow_newer: bool,
+    pub(crate) archive_orphans: bool,
+    pub(crate) index_payment_ids: bool,
+    pub(crate) network: Option<Network>,
+    pub(crate) max_value_size: Option<usize>,
+    pub(crate) scan: ScanOptions,
+    pub(crate) retry_policy: Option<RetryPolicy>,
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        OpenOptions {
+            read_only: false,
+            lock_readers: false,
+            map_size: 1 << 30,
+            map_growth: 1 << 30,
+            max_readers: 126,
+            write_map: false,
+            no_sync: false,
+            batch_size: 1,
+            allow_newer: false,
+            archive_orphans: false,
+            index_payment_ids: false,
+            network: None,
+            max_value_size: None,
+            scan: ScanOptions::default(),
+            retry_policy: None,
+        }
+    }
+}
+
+impl OpenOptions {
+    /// Creates the default options, these are the same as used by [`MoneroDB::open`]
+    ///
+    pub fn new() -> Self {
+        OpenOptions::default()
+    }
+
+    /// Options tuned for importing large numbers of blocks with [`ReadWriteDb::add_blocks`].
+    ///
+    /// Writes go through a writable memory map without syncing to disk, the map
+    /// grows in large steps and blocks are committed in batches of 1000. The
+    /// database is synced once all blocks passed to `add_blocks` are written, a
+    /// crash before then can corrupt the database.
+    ///
+    pub fn bulk_import() -> Self {
+        OpenOptions {
+            map_growth: 1 << 33,
+            write_map: true,
+            no_sync: true,
+            batch_size: 1000,
+            ..OpenOptions::default()
+        }
+    }
+
+    /// Sets if the database is opened read only
+    ///
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Sets if a read only database registers its readers in the lock file.
+    ///
+    /// Read only handles skip locking by default, so they work on a database
+    /// they can not write the lock file of, but a writer in another process such
+    /// as monerod does not see their read transactions and may reuse pages they
+    /// still read. [`MoneroDB::snapshot`] needs this set on read only handles.
+    /// Handles opened for writing always lock.
+    ///
+    pub fn lock_readers(mut self, lock_readers: bool) -> Self {
+        self.lock_readers = lock_readers;
+        self
+    }
+
+    /// If the environment is opened without registering readers in the lock file
+    pub(crate) fn no_lock(&self) -> bool {
+        self.read_only && !self.lock_readers
+    }
+
+    /// Sets the initial size of the memory map
+    ///
+    pub fn map_size(mut self, map_size: usize) -> Self {
+        self.map_size = map_size;
+        self
+    }
+
+    /// Sets how much the memory map grows by when it is full
+    ///
+    pub fn map_growth(mut self, map_growth: usize) -> Self {
+        self.map_growth = map_growth;
+        self
+    }
+
+    /// Sets the maximum number of concurrent readers
+    ///
+    pub fn max_readers(mut self, max_readers: u32) -> Self {
+        self.max_readers = max_readers;
+        self
+    }
+
+    /// Sets if writes skip syncing to disk on commit, see [`ReadWriteDb::sync`]
+    ///
+    pub fn no_sync(mut self, no_sync: bool) -> Self {
+        self.no_sync = no_sync;
+        self
+    }
+
+    /// Sets the number of blocks committed together by [`ReadWriteDb::add_blocks`]
+    ///
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Sets if databases with a newer version than this library supports can be opened.
+    ///
+    /// Such databases are opened read only and only the tables holding consensus
+    /// serialized data, whose format does not depend on the database version, can
+    /// be read. Queries on other tables fail with [`Error::UnsupportedVersion`].
+    ///
+    pub fn allow_newer(mut self, allow_newer: bool) -> Self {
+        self.allow_newer = allow_newer;
+        self
+    }
+
+    /// Sets if blocks removed by [`ReadWriteDb::pop_blocks`] are kept in an archive
+    /// table, see [`MoneroDB::orphans`]
+    ///
+    pub fn archive_orphans(mut self, archive_orphans: bool) -> Self {
+        self.archive_orphans = archive_orphans;
+        self
+    }
+
+    /// Sets if the payment IDs of added blocks are written to the payment ID index,
+    /// and those of popped blocks removed, see [`MoneroDB::find_payment_id`].
+    /// Blocks already in the database are indexed with
+    /// [`crate::ReadWriteDb::index_payment_ids`].
+    ///
+    pub fn index_payment_ids(mut self, index_payment_ids: bool) -> Self {
+        self.index_payment_ids = index_payment_ids;
+        self
+    }
+
+    /// Sets the network the database must belong to, checked against its genesis
+    /// block when opening
+    ///
+    pub fn network(mut self, network: Network) -> Self {
+        self.network = Some(network);
+        self
+    }
+
+    /// Sets the largest value, in bytes, that lookups read. Larger values fail with
+    /// [`Error::ValueTooLarge`] before anything is decoded or copied, so corrupt or
+    /// hostile entries can't cause huge allocations. By default there is no limit.
+    ///
+    pub fn max_value_size(mut self, max_value_size: usize) -> Self {
+        self.max_value_size = Some(max_value_size);
+        self
+    }
+
+    /// Sets the threads and cancel handle used by parallel scans, audits and
+    /// exports, by default one thread per available core and no cancel handle
+    ///
+    pub fn scan_options(mut self, scan_options: ScanOptions) -> Self {
+        self.scan = scan_options;
+        self
+    }
+
+    /// Sets how reads retry transient failures such as [`Error::Busy`], by default
+    /// they are returned straight away
+    ///
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(retry_policy);
+        self
+    }
+
+    /// Opens the database in `dir`, fails with [`Error::UnsupportedVersion`] if the
+    /// database is newer than this library supports, see [`OpenOptions::allow_newer`],
+    /// and with [`Error::WrongNetwork`] if it belongs to another network than set
+    /// with [`OpenOptions::network`]. Other failures are explained by an
+    /// [`Error::OpenError`] where possible.
+    ///
+    pub fn open(&self, dir: &Path) -> Result<MoneroDB, Error> {
+        check_data_dir(dir)?;
+        let env = self.open_env(dir)?;
+        let sub_dbs = MoneroSubDB::new(&env)?;
+        let mut db = MoneroDB::from_parts(env, sub_dbs, self.clone());
+
+        let version = match db.get_db_version() {
+            Ok(version) => version,
+            Err(e) if e.is_not_found() => DB_VERSION,
+            Err(e) => return Err(e),
+        };
+        if version > DB_VERSION {
+            if !self.allow_newer {
+                return Err(Error::UnsupportedVersion {
+                    found: version,
+                    supported: DB_VERSION,
+                });
+            }
+            // the environment was opened with locking unless read only was asked for
+            db.options.lock_readers |= !self.read_only;
+            db.options.read_only = true;
+            db.newer_version = Some(version);
+        }
+        if let Some(network) = self.network {
+            if db.get_blockchain_height()? > 0 && db.get_block_hash(0)? != genesis_hash(network) {
+                return Err(Error::WrongNetwork(network));
+            }
+        }
+        Ok(db)
+    }
+
+    /// Opens the database in `dir` for writing as [`OpenOptions::open`] does. Fails
+    /// with [`Error::ReadOnly`] if the options are read only or the database is
+    /// newer than this library supports and [`OpenOptions::allow_newer`] is set.
+    ///
+    pub fn open_rw(&self, dir: &Path) -> Result<ReadWriteDb, Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        let db = self.open(dir)?;
+        if db.is_readonly() {
+            return Err(Error::ReadOnly);
+        }
+        Ok(ReadWriteDb { db })
+    }
+
+    /// Creates a new database in `dir` containing only the genesis block of `network`
+    ///
+    pub fn create(&self, dir: &Path, network: Network) -> Result<ReadWriteDb, Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        std::fs::create_dir_all(dir)?;
+        let env = self.open_env(dir)?;
+        let sub_dbs = MoneroSubDB::create(&env)?;
+        let options = OpenOptions {
+            network: Some(network),
+            ..self.clone()
+        };
+        let db = ReadWriteDb {
+            db: MoneroDB::from_parts(env, sub_dbs, options),
+        };
+        if db.get_blockchain_height()? != 0 {
+            return Err(Error::InvalidBlock(
+                "database already contains blocks".to_string(),
+            ));
+        }
+
+        db.set_db_version(DB_VERSION)?;
+        let genesis = genesis_block(network);
+        let weight = serialize(&genesis.miner_tx).len() as u64;
+        db.add_block(&genesis, &[], weight, weight, 1)?;
+        if self.no_sync {
+            db.sync(true)?;
+        }
+        Ok(db)
+    }
+
+    fn open_env(&self, dir: &Path) -> Result<Environment, Error> {
+        let mut env = Environment::new();
+        let mut flags = EnvironmentFlags::NO_READAHEAD;
+        if self.read_only {
+            flags |= EnvironmentFlags::READ_ONLY;
+        }
+        if self.no_lock() {
+            flags |= EnvironmentFlags::NO_LOCK;
+        }
+        if self.write_map {
+            flags |= EnvironmentFlags::WRITE_MAP | EnvironmentFlags::MAP_ASYNC;
+        }
+        if self.no_sync {
+            flags |= EnvironmentFlags::NO_SYNC | EnvironmentFlags::NO_META_SYNC;
+        }
+        env.set_max_dbs(32)
+            .set_map_size(self.map_size)
+            .set_max_readers(self.max_readers)
+            .set_flags(flags);
+        let env = env
+            .open(dir)
+            .map_err(|e| env_error(dir, self.read_only, e))?;
+        env.check_do_resize()?;
+        Ok(env)
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Archive of blocks popped from the main chain
+//!
+//! monerod discards popped blocks, with [`crate::OpenOptions::archive_orphans`] they
+//! are kept in an extra table so past reorgs can be studied.
+
+use lmdb::{Cursor, Database, DatabaseFlags, RwTransaction, Transaction, WriteFlags};
+use monero::consensus::{deserialize, serialize};
+use monero::cryptonote::hash::Hashable;
+use monero::{Block, Hash};
+use std::io::Cursor as Reader;
+
+use super::scan::read_u64;
+use super::sub_db::open_optional_subdb;
+use super::txpool::read_item;
+use super::write::NewBlock;
+use super::{Error, MoneroDB};
+
+/// Name of the table holding popped blocks, keyed by block hash
+pub(crate) const ORPHAN_BLOCKS: &str = "orphan_blocks";
+
+/// A block popped from the main chain
+///
+#[derive(Debug, Clone)]
+pub struct OrphanedBlock {
+    /// Hash of the block
+    pub hash: Hash,
+    /// Height the block had in the main chain
+    pub height: u64,
+    /// Unix time the block was popped
+    pub popped_at: u64,
+    /// The block with its transactions, weights and difficulty
+    pub block: NewBlock,
+}
+
+/// Writes a popped block to the archive, replacing an earlier entry if the same
+/// block was popped before
+///
+pub(crate) fn archive_orphan(
+    transaction: &mut RwTransaction,
+    archive: Database,
+    height: u64,
+    popped_at: u64,
+    new_block: &NewBlock,
+) -> Result<(), Error> {
+    let mut value = Vec::new();
+    for field in [
+        height,
+        popped_at,
+        new_block.block_weight,
+        new_block.long_term_block_weight,
+        new_block.difficulty as u64,
+        (new_block.difficulty >> 64) as u64,
+    ] {
+        value.extend_from_slice(&field.to_le_bytes());
+    }
+    let mut push_item = |item: &[u8]| {
+        value.extend_from_slice(&(item.len() as u64).to_le_bytes());
+        value.extend_from_slice(item);
+    };
+    push_item(&serialize(&new_block.block));
+    for tx in &new_block.txs {
+        push_item(&serialize(tx));
+    }
+    transaction.put(
+        archive,
+        &new_block.block.id().as_bytes(),
+        &value,
+        WriteFlags::empty(),
+    )?;
+    Ok(())
+}
+
+fn read_orphan(hash: &[u8], value: &[u8]) -> Result<OrphanedBlock, Error> {
+    let mut reader = Reader::new(value);
+    let mut fields = [0; 6];
+    for field in &mut fields {
+        *field = read_u64(&mut reader)?;
+    }
+    let [height, popped_at, block_weight, long_term_block_weight, diff_lo, diff_hi] = fields;
+    let block: Block = deserialize(&read_item(&mut reader)?)?;
+    let mut txs = Vec::with_capacity(block.tx_hashes.len());
+    for _ in 0..block.tx_hashes.len() {
+        txs.push(deserialize(&read_item(&mut reader)?)?);
+    }
+    Ok(OrphanedBlock {
+        hash: Hash::from_slice(hash),
+        height,
+        popped_at,
+        block: NewBlock {
+            block,
+            txs,
+            block_weight,
+            long_term_block_weight,
+            difficulty: u128::from(diff_lo) | u128::from(diff_hi) << 64,
+        },
+    })
+}
+
+impl MoneroDB {
+    /// Gets the blocks archived when they were popped from the main chain, oldest
+    /// pop first. Blocks popped together are ordered by height.
+    ///
+    /// The archive is only written by handles opened with
+    /// [`crate::OpenOptions::archive_orphans`], it is empty if none was.
+    ///
+    pub fn orphans(&self) -> Result<Vec<OrphanedBlock>, Error> {
+        let archive =
+            match open_optional_subdb(&self.env, ORPHAN_BLOCKS, DatabaseFlags::empty(), false)? {
+                Some(archive) => archive,
+                None => return Ok(Vec::new()),
+            };
+        let transaction = self.begin_read()?;
+        let curser = transaction.open_ro_cursor(archive)?;
+        let mut orphans = Vec::new();
+        let mut op = 0;
+        loop {
+            match curser.get(None, None, op) {
+                Ok((Some(key), value)) => orphans.push(read_orphan(key, value)?),
+                Ok((None, _)) | Err(lmdb::Error::NotFound) => break,
+                Err(e) => return Err(e.into()),
+            }
+            op = 8;
+        }
+        orphans.sort_by_key(|orphan| (orphan.popped_at, orphan.height));
+        Ok(orphans)
+    }
+}
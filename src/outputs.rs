@@ -0,0 +1,215 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Output index conversions and output listings
+
+use lmdb::{Cursor, Transaction};
+use monero::blockdata::transaction::TxOutTarget;
+use monero::cryptonote::hash::Hashable;
+use monero::{Hash, PublicKey};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use super::monero_db::{get_raw_item, read_item};
+use super::{Amount, Error, MoneroDB, OutputId};
+
+/// An output of a miner transaction
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoinbaseOutput {
+    /// Height of the block
+    pub height: u64,
+    /// Hash of the miner transaction
+    pub tx_hash: Hash,
+    /// Index of the output in the miner transaction
+    pub local_index: usize,
+    /// One time public key of the output
+    pub public_key: PublicKey,
+    /// Amount of the output, miner outputs always have a visible amount
+    pub amount: u64,
+    /// Index among the outputs with the same indexed amount, RCT miner outputs
+    /// are indexed under amount 0 with all other RCT outputs
+    pub amount_index: u64,
+    /// Global output ID
+    pub output_id: u64,
+}
+
+impl MoneroDB {
+    /// Converts the index of an output among the outputs with `amount` into its
+    /// global output ID, all RCT outputs use amount 0
+    ///
+    pub fn get_output_global_index(
+        &self,
+        amount: impl Into<Amount>,
+        amount_index: u64,
+    ) -> Result<u64, Error> {
+        let amount = amount.into();
+        // the output ID directly follows the amount index in both outkey layouts
+        read_item(
+            self,
+            self.sub_dbs.output_amounts,
+            &amount.to_le_bytes(),
+            &amount_index.to_le_bytes(),
+            2,
+            |outkey| {
+                outkey
+                    .get(8..16)
+                    .map(|id| u64::from_le_bytes(id.try_into().unwrap()))
+            },
+        )?
+        .ok_or(Error::InvalidOutkey {
+            amount: amount.0,
+            amount_index,
+        })
+    }
+
+    /// Converts a global output ID into the amount the output is indexed under and
+    /// its index among the outputs with that amount
+    ///
+    pub fn get_output_amount_index(
+        &self,
+        output_id: impl Into<OutputId>,
+    ) -> Result<(Amount, u64), Error> {
+        let out_tx = self.get_output_tx(output_id)?;
+        let tx_id = self.get_tx_indices(&out_tx.tx_hash)?.data.tx_id;
+        let tx = self.get_tx_pruned(tx_id)?;
+        let local_index = out_tx.local_index as usize;
+
+        let output = tx.prefix.outputs.get(local_index).ok_or(Error::NotFound {
+            db: "txs_pruned",
+            key: tx_id.to_le_bytes().to_vec(),
+        })?;
+        let amount = if tx.prefix.version.0 > 1 {
+            0
+        } else {
+            output.amount.0
+        };
+
+        let amount_indices = get_raw_item(
+            self,
+            self.sub_dbs.tx_outputs,
+            &tx_id.to_le_bytes(),
+            &[0],
+            15,
+        )?;
+        let amount_index = amount_indices
+            .get(local_index * 8..local_index * 8 + 8)
+            .map(|index| u64::from_le_bytes(index.try_into().unwrap()))
+            .ok_or(Error::NotFound {
+                db: "tx_outputs",
+                key: tx_id.to_le_bytes().to_vec(),
+            })?;
+        Ok((Amount(amount), amount_index))
+    }
+
+    /// Gets the number of outputs with `amount`, all RCT outputs use amount 0
+    ///
+    pub fn get_num_outputs(&self, amount: impl Into<Amount>) -> Result<u64, Error> {
+        let amount = amount.into();
+        self.check_table_version(self.sub_dbs.output_amounts)?;
+        let transaction = self.begin_read()?;
+        let curser = transaction.open_ro_cursor(self.sub_dbs.output_amounts)?;
+        match curser.get(Some(&amount.to_le_bytes()), None, 15) {
+            Ok(_) => {}
+            Err(lmdb::Error::NotFound) => return Ok(0),
+            Err(e) => return Err(e.into()),
+        }
+        let (_, last) = curser.get(None, None, 7)?;
+        Ok(last_amount_index(last) + 1)
+    }
+
+    /// Lists every pre-RCT denomination in the chain with the number of outputs of it
+    ///
+    pub fn list_pre_rct_amounts(&self) -> Result<BTreeMap<u64, u64>, Error> {
+        self.check_table_version(self.sub_dbs.output_amounts)?;
+        let transaction = self.begin_read()?;
+        let curser = transaction.open_ro_cursor(self.sub_dbs.output_amounts)?;
+
+        let mut amounts = BTreeMap::new();
+        // first entry, then the first entry of each following amount
+        let mut op = 0;
+        loop {
+            let amount = match curser.get(None, None, op) {
+                Ok((Some(key), _)) => u64::from_le_bytes(key.try_into().unwrap()),
+                Ok((None, _)) | Err(lmdb::Error::NotFound) => break,
+                Err(e) => return Err(e.into()),
+            };
+            let (_, last) = curser.get(None, None, 7)?;
+            if amount != 0 {
+                amounts.insert(amount, last_amount_index(last) + 1);
+            }
+            op = 11;
+        }
+        Ok(amounts)
+    }
+
+    /// Calls `f` with every output of the miner transactions of the blocks in
+    /// `range`, without reading any other transaction.
+    ///
+    /// View tagged miner outputs, used from hard fork 15, are not supported and
+    /// fail with [`Error::InvalidBlock`].
+    ///
+    pub fn coinbase_outputs<F: FnMut(CoinbaseOutput)>(
+        &self,
+        range: Range<u64>,
+        mut f: F,
+    ) -> Result<(), Error> {
+        for height in range {
+            let miner_tx = self.get_block(height)?.miner_tx;
+            let tx_hash = miner_tx.hash();
+            let tx_id = self.get_tx_indices(&tx_hash)?.data.tx_id;
+            let amount_indices = get_raw_item(
+                self,
+                self.sub_dbs.tx_outputs,
+                &tx_id.to_le_bytes(),
+                &[0],
+                15,
+            )?;
+            let rct = miner_tx.prefix.version.0 > 1;
+
+            for (local_index, output) in miner_tx.prefix.outputs.iter().enumerate() {
+                let public_key = match &output.target {
+                    TxOutTarget::ToKey { key } => *key,
+                    _ => {
+                        return Err(Error::InvalidBlock(format!(
+                            "miner output {} at height {} is not to an untagged key",
+                            local_index, height
+                        )))
+                    }
+                };
+                let amount_index = amount_indices
+                    .get(local_index * 8..local_index * 8 + 8)
+                    .map(|index| u64::from_le_bytes(index.try_into().unwrap()))
+                    .ok_or(Error::NotFound {
+                        db: "tx_outputs",
+                        key: tx_id.to_le_bytes().to_vec(),
+                    })?;
+                let indexed_amount = if rct { 0 } else { output.amount.0 };
+                f(CoinbaseOutput {
+                    height,
+                    tx_hash,
+                    local_index,
+                    public_key,
+                    amount: output.amount.0,
+                    amount_index,
+                    output_id: self.get_output_global_index(indexed_amount, amount_index)?,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the amount index at the start of an outkey
+fn last_amount_index(outkey: &[u8]) -> u64 {
+    u64::from_le_bytes(outkey[..8].try_into().unwrap())
+}
@@ -0,0 +1,402 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Parallel scanning over block height ranges
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use super::cancel::CancelHandle;
+use super::progress::{Progress, ProgressCallback, ProgressTracker};
+use super::{Error, MoneroDB};
+
+/// Default number of blocks handed to a worker at a time
+pub(crate) const DEFAULT_CHUNK_SIZE: u64 = 1000;
+/// Number of chunks per thread that may be scanned ahead of the next chunk
+/// handed out in order
+const CHUNKS_AHEAD_PER_THREAD: u64 = 2;
+
+/// Returns the number of worker threads to use when none is configured
+pub(crate) fn default_threads() -> usize {
+    thread::available_parallelism()
+        .map(|threads| threads.get())
+        .unwrap_or(1)
+}
+
+/// Threads, cancellation and progress reporting of the scans, audits, exports and
+/// imports of a [`MoneroDB`], set with [`OpenOptions::scan_options`] or
+/// [`MoneroDB::set_scan_options`]
+///
+/// [`OpenOptions::scan_options`]: crate::OpenOptions::scan_options
+#[derive(Clone)]
+pub struct ScanOptions {
+    pub(crate) threads: usize,
+    pub(crate) cancel: Option<CancelHandle>,
+    pub(crate) on_progress: Option<ProgressCallback>,
+}
+
+impl fmt::Debug for ScanOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScanOptions")
+            .field("threads", &self.threads)
+            .field("cancel", &self.cancel)
+            .field("on_progress", &self.on_progress.is_some())
+            .finish()
+    }
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            threads: default_threads(),
+            cancel: None,
+            on_progress: None,
+        }
+    }
+}
+
+impl ScanOptions {
+    /// Creates the default options, using one thread per available core
+    ///
+    pub fn new() -> Self {
+        ScanOptions::default()
+    }
+
+    /// Sets the number of worker threads, with a single thread the work runs on
+    /// the calling thread without spawning any
+    ///
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Runs everything on the calling thread, for environments where spawning
+    /// threads is not wanted. The same as `threads(1)`.
+    ///
+    pub fn single_threaded(self) -> Self {
+        self.threads(1)
+    }
+
+    /// Sets the handle cancelling the operations, see [`CancelHandle`]
+    ///
+    pub fn cancel_handle(mut self, cancel: CancelHandle) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Sets a callback receiving the [`Progress`] of each operation, called from
+    /// the thread that started the operation
+    ///
+    pub fn on_progress<F>(mut self, on_progress: F) -> Self
+    where
+        F: Fn(&Progress) + Send + Sync + 'static,
+    {
+        self.on_progress = Some(Arc::new(on_progress));
+        self
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .map_or(false, CancelHandle::is_cancelled)
+    }
+}
+
+/// Limits the total size of the items passed between threads.
+///
+/// [`ByteBudget::acquire`] blocks until enough of the bytes acquired before have
+/// been released. An item larger than the whole budget is let through once
+/// nothing else is in flight, so it can't block forever.
+#[derive(Debug)]
+pub(crate) struct ByteBudget {
+    limit: u64,
+    /// Bytes in flight and if the budget was closed
+    state: Mutex<(u64, bool)>,
+    released: Condvar,
+}
+
+impl ByteBudget {
+    pub(crate) fn new(limit: u64) -> Self {
+        ByteBudget {
+            limit,
+            state: Mutex::new((0, false)),
+            released: Condvar::new(),
+        }
+    }
+
+    pub(crate) fn acquire(&self, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        while !state.1 && state.0 != 0 && state.0.saturating_add(bytes) > self.limit {
+            state = self.released.wait(state).unwrap();
+        }
+        state.0 = state.0.saturating_add(bytes);
+    }
+
+    pub(crate) fn release(&self, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.0 = state.0.saturating_sub(bytes);
+        self.released.notify_all();
+    }
+
+    /// Stops limiting, called once the consumer is gone so producers waiting for
+    /// room find out
+    pub(crate) fn close(&self) {
+        self.state.lock().unwrap().1 = true;
+        self.released.notify_all();
+    }
+}
+
+/// Limits how far workers run ahead of the results handed out in order, so the
+/// results waiting to be reordered stay bounded.
+///
+/// [`ReorderWindow::wait_for`] blocks a worker that took item `index` until it
+/// is less than the window size ahead of the next item to hand out. The next
+/// item itself is always let through, so the window can't block forever.
+#[derive(Debug)]
+struct ReorderWindow {
+    size: u64,
+    /// Index of the next item handed out and if the window was closed
+    state: Mutex<(u64, bool)>,
+    advanced: Condvar,
+}
+
+impl ReorderWindow {
+    fn new(size: u64) -> Self {
+        ReorderWindow {
+            size: size.max(1),
+            state: Mutex::new((0, false)),
+            advanced: Condvar::new(),
+        }
+    }
+
+    /// Returns false if the window was closed while waiting
+    fn wait_for(&self, index: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        while !state.1 && index >= state.0.saturating_add(self.size) {
+            state = self.advanced.wait(state).unwrap();
+        }
+        !state.1
+    }
+
+    fn advance(&self, next: u64) {
+        self.state.lock().unwrap().0 = next;
+        self.advanced.notify_all();
+    }
+
+    /// Wakes every waiting worker, called once the consumer is gone
+    fn close(&self) {
+        self.state.lock().unwrap().1 = true;
+        self.advanced.notify_all();
+    }
+}
+
+impl MoneroDB {
+    /// Sets the threads and cancel handle used by parallel scans, audits and
+    /// exports made through this handle from now on
+    ///
+    pub fn set_scan_options(&mut self, scan_options: ScanOptions) {
+        self.options.scan = scan_options;
+    }
+
+    /// Gets the threads and cancel handle used by parallel scans, audits and exports
+    ///
+    pub fn scan_options(&self) -> ScanOptions {
+        self.options.scan.clone()
+    }
+}
+
+/// Splits `range` into chunks scanned by the threads of `options`, the results of
+/// each chunk are handed to `on_chunk` in height order on the calling thread,
+/// after which the progress of `operation` is reported.
+///
+/// Scanning stops at the first error returned by either closure, or with
+/// [`Error::Cancelled`] once the cancel handle of `options` is cancelled. With a
+/// single thread the chunks are scanned on the calling thread.
+pub(crate) fn scan_parallel<T, S, C>(
+    range: Range<u64>,
+    options: &ScanOptions,
+    operation: &'static str,
+    chunk_size: u64,
+    scan: S,
+    mut on_chunk: C,
+) -> Result<(), Error>
+where
+    T: Send,
+    S: Fn(Range<u64>) -> Result<T, Error> + Sync,
+    C: FnMut(Range<u64>, T) -> Result<(), Error>,
+{
+    if range.is_empty() {
+        return Ok(());
+    }
+    let chunk_size = chunk_size.max(1);
+    let chunks = (range.end - range.start + chunk_size - 1) / chunk_size;
+    let chunk_range = |chunk: u64| {
+        let start = range.start + chunk * chunk_size;
+        start..(start + chunk_size).min(range.end)
+    };
+    let progress = ProgressTracker::new(options, operation, range.clone());
+    let cancelled = |chunk: u64| Error::Cancelled {
+        height: chunk_range(chunk).start,
+    };
+    if options.threads <= 1 {
+        for chunk in 0..chunks {
+            if options.is_cancelled() {
+                return Err(cancelled(chunk));
+            }
+            let value = scan(chunk_range(chunk))?;
+            on_chunk(chunk_range(chunk), value)?;
+            progress.report(chunk_range(chunk).end);
+        }
+        return Ok(());
+    }
+
+    let next_chunk = AtomicU64::new(0);
+    let stop = AtomicBool::new(false);
+    let window = ReorderWindow::new(options.threads as u64 * CHUNKS_AHEAD_PER_THREAD);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..options.threads {
+            let sender = sender.clone();
+            let (next_chunk, stop, window, scan) = (&next_chunk, &stop, &window, &scan);
+            scope.spawn(move || loop {
+                let chunk = next_chunk.fetch_add(1, Ordering::Relaxed);
+                if chunk >= chunks || stop.load(Ordering::Relaxed) || options.is_cancelled() {
+                    break;
+                }
+                if !window.wait_for(chunk) || stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                let result = scan(chunk_range(chunk));
+                if result.is_err() {
+                    stop.store(true, Ordering::Relaxed);
+                }
+                if sender.send((chunk, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        let mut pending = BTreeMap::new();
+        let mut next_to_emit = 0;
+        for (chunk, result) in receiver {
+            pending.insert(chunk, result);
+            while let Some(result) = pending.remove(&next_to_emit) {
+                let handled = result.and_then(|value| on_chunk(chunk_range(next_to_emit), value));
+                if let Err(e) = handled {
+                    stop.store(true, Ordering::Relaxed);
+                    window.close();
+                    return Err(e);
+                }
+                progress.report(chunk_range(next_to_emit).end);
+                next_to_emit += 1;
+                window.advance(next_to_emit);
+            }
+        }
+        // the workers only stop early without an error when cancelled
+        if next_to_emit < chunks {
+            return Err(cancelled(next_to_emit));
+        }
+        Ok(())
+    })
+}
+
+/// Runs `read` on a reader thread, passing the raw items it reads in order to
+/// `send`, while `threads` workers decode them with `decode`. The decoded items
+/// are handed to `on_item` in order on the calling thread.
+///
+/// The reader copies data out of the memory map, taking the page faults, while
+/// the workers decode. It stays at most `max_in_flight` items ahead of the
+/// workers, which stay at most `max_in_flight` items ahead of the next item
+/// handed to `on_item`, and stops once `send` returns false. Processing stops at the first
+/// error returned by any of the closures. With a single thread everything runs
+/// on the calling thread, decoding each item as it is read.
+pub(crate) fn pipeline<R, T, F, D, C>(
+    threads: usize,
+    max_in_flight: usize,
+    read: F,
+    decode: D,
+    mut on_item: C,
+) -> Result<(), Error>
+where
+    R: Send,
+    T: Send,
+    F: FnOnce(&mut dyn FnMut(R) -> bool) -> Result<(), Error> + Send,
+    D: Fn(R) -> Result<T, Error> + Sync,
+    C: FnMut(T) -> Result<(), Error>,
+{
+    if threads <= 1 {
+        let mut result = Ok(());
+        read(&mut |item| {
+            result = decode(item).and_then(&mut on_item);
+            result.is_ok()
+        })?;
+        return result;
+    }
+    let max_in_flight = max_in_flight.max(1);
+    let (raw_sender, raw_receiver) = mpsc::sync_channel::<(u64, R)>(max_in_flight);
+    let (decoded_sender, decoded_receiver) = mpsc::sync_channel(max_in_flight);
+    // shared by the workers only, so the reader stops once they are all gone
+    let raw_receiver = Arc::new(Mutex::new(raw_receiver));
+    let window = ReorderWindow::new(max_in_flight as u64);
+
+    thread::scope(|scope| {
+        let reader = scope.spawn(move || {
+            let mut index = 0;
+            read(&mut |item| {
+                let sent = raw_sender.send((index, item)).is_ok();
+                index += 1;
+                sent
+            })
+        });
+        for _ in 0..threads.max(1) {
+            let decoded_sender = decoded_sender.clone();
+            let raw_receiver = raw_receiver.clone();
+            let (decode, window) = (&decode, &window);
+            scope.spawn(move || loop {
+                let next = raw_receiver.lock().unwrap().recv();
+                let (index, item) = match next {
+                    Ok(next) => next,
+                    Err(_) => break,
+                };
+                if !window.wait_for(index) {
+                    break;
+                }
+                if decoded_sender.send((index, decode(item))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop((decoded_sender, raw_receiver));
+
+        let mut pending = BTreeMap::new();
+        let mut next_to_emit = 0;
+        for (index, item) in decoded_receiver {
+            pending.insert(index, item);
+            while let Some(item) = pending.remove(&next_to_emit) {
+                if let Err(e) = item.and_then(&mut on_item) {
+                    window.close();
+                    return Err(e);
+                }
+                next_to_emit += 1;
+                window.advance(next_to_emit);
+            }
+        }
+        reader.join().expect("pipeline reader panicked")
+    })
+}
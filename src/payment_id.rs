@@ -0,0 +1,259 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//! Optional index from payment IDs to transaction hashes
+//!
+//! The index is an extra table outside the monerod schema, written by handles
+//! opened with [`crate::OpenOptions::index_payment_ids`] and filled for blocks
+//! already in the database with [`ReadWriteDb::index_payment_ids`]. Payment IDs
+//! are stored as they appear in the transactions, encrypted short IDs are only
+//! decrypted when looked up with a view key.
+
+use curve25519_dalek::scalar::Scalar;
+use lmdb::{Cursor, Database, DatabaseFlags, RwTransaction, Transaction, WriteFlags};
+use monero::blockdata::transaction::{SubField, TransactionPrefix};
+use monero::cryptonote::hash::keccak_256;
+use monero::{Hash, PrivateKey, PublicKey};
+use std::ops::Range;
+
+use super::sub_db::open_optional_subdb;
+use super::{Error, MoneroDB, ReadWriteDb};
+
+/// Name of the table holding the index, keyed by the raw payment ID with the hash
+/// and public key of each transaction carrying it as duplicates
+pub(crate) const PAYMENT_IDS: &str = "payment_ids";
+
+/// Number of blocks read before their payment IDs are written
+const INDEX_BATCH_BLOCKS: u64 = 1000;
+
+const TX_EXTRA_NONCE_PAYMENT_ID: u8 = 0x00;
+const TX_EXTRA_NONCE_ENCRYPTED_PAYMENT_ID: u8 = 0x01;
+const ENCRYPTED_PAYMENT_ID_TAIL: u8 = 0x8d;
+
+/// A payment ID found in the extra field of a transaction
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PaymentId {
+    /// Unencrypted 32 byte payment ID
+    Long([u8; 32]),
+    /// 8 byte payment ID, encrypted with the shared secret of the transaction
+    Short([u8; 8]),
+}
+
+impl PaymentId {
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            PaymentId::Long(id) => id,
+            PaymentId::Short(id) => id,
+        }
+    }
+}
+
+/// Opens the index table, creating it if `create` is set
+pub(crate) fn open_payment_ids(
+    env: &lmdb::Environment,
+    create: bool,
+) -> Result<Option<Database>, Error> {
+    open_optional_subdb(env, PAYMENT_IDS, DatabaseFlags::DUP_SORT, create)
+}
+
+/// Gets the raw payment IDs of a transaction with the entry stored for each, the
+/// transaction hash followed by its public key, zero if it has none
+fn index_entries(tx_hash: &Hash, prefix: &TransactionPrefix) -> Vec<(PaymentId, [u8; 64])> {
+    let fields = &prefix.extra.0;
+    let tx_pub_key = fields.iter().find_map(|field| match field {
+        SubField::TxPublicKey(key) => Some(*key),
+        _ => None,
+    });
+    let mut entry = [0; 64];
+    entry[..32].copy_from_slice(tx_hash.as_bytes());
+    if let Some(tx_pub_key) = tx_pub_key {
+        entry[32..].copy_from_slice(tx_pub_key.as_bytes());
+    }
+    fields
+        .iter()
+        .filter_map(|field| match field {
+            SubField::Nonce(nonce) => parse_payment_id(nonce),
+            _ => None,
+        })
+        .map(|payment_id| (payment_id, entry))
+        .collect()
+}
+
+/// Adds the payment IDs of a transaction to the index, adding it again is a no-op
+pub(crate) fn index_tx(
+    transaction: &mut RwTransaction,
+    index: Database,
+    tx_hash: &Hash,
+    prefix: &TransactionPrefix,
+) -> Result<(), Error> {
+    put_entries(transaction, index, &index_entries(tx_hash, prefix))
+}
+
+fn put_entries(
+    transaction: &mut RwTransaction,
+    index: Database,
+    entries: &[(PaymentId, [u8; 64])],
+) -> Result<(), Error> {
+    for (payment_id, entry) in entries {
+        match transaction.put(
+            index,
+            &payment_id.as_bytes(),
+            entry,
+            WriteFlags::NO_DUP_DATA,
+        ) {
+            Ok(()) | Err(lmdb::Error::KeyExist) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+/// Removes the payment IDs of a transaction from the index
+pub(crate) fn unindex_tx(
+    transaction: &mut RwTransaction,
+    index: Database,
+    tx_hash: &Hash,
+    prefix: &TransactionPrefix,
+) -> Result<(), Error> {
+    for (payment_id, entry) in index_entries(tx_hash, prefix) {
+        match transaction.del(index, &payment_id.as_bytes(), Some(&entry)) {
+            Ok(()) | Err(lmdb::Error::NotFound) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+impl MoneroDB {
+    /// Gets the hashes of the indexed transactions carrying `payment_id`, see the
+    /// [module docs](self). Fails with [`Error::NotFound`] if the database has no
+    /// payment ID index.
+    ///
+    /// Short payment IDs are looked up as stored, encrypted, unless a view key is
+    /// given. With a view key every indexed short ID is decrypted with the
+    /// transaction's shared secret and compared to `payment_id`, so the lookup
+    /// walks the short IDs of the whole index.
+    ///
+    pub fn find_payment_id(
+        &self,
+        payment_id: &PaymentId,
+        view_key: Option<&PrivateKey>,
+    ) -> Result<Vec<Hash>, Error> {
+        let index = open_payment_ids(&self.env, false)?.ok_or(Error::NotFound {
+            db: "payment_ids",
+            key: Vec::new(),
+        })?;
+        let transaction = self.begin_read()?;
+        let curser = transaction.open_ro_cursor(index)?;
+        let mut tx_hashes = Vec::new();
+
+        let (view_key, id) = match (payment_id, view_key) {
+            (PaymentId::Short(id), Some(view_key)) => (view_key, id),
+            _ => {
+                // every transaction stored under the raw ID
+                let mut key = Some(payment_id.as_bytes());
+                let mut op = 15;
+                loop {
+                    match curser.get(key, None, op) {
+                        Ok((_, entry)) => tx_hashes.push(Hash::from_slice(&entry[..32])),
+                        Err(lmdb::Error::NotFound) => break,
+                        Err(e) => return Err(e.into()),
+                    }
+                    key = None;
+                    op = 9;
+                }
+                return Ok(tx_hashes);
+            }
+        };
+
+        let mut op = 0;
+        loop {
+            let (key, entry) = match curser.get(None, None, op) {
+                Ok((Some(key), entry)) => (key, entry),
+                Ok((None, _)) | Err(lmdb::Error::NotFound) => break,
+                Err(e) => return Err(e.into()),
+            };
+            op = 8;
+            let encrypted: [u8; 8] = match key.try_into() {
+                Ok(encrypted) => encrypted,
+                Err(_) => continue,
+            };
+            let tx_pub_key = match PublicKey::from_slice(&entry[32..]) {
+                Ok(tx_pub_key) => tx_pub_key,
+                Err(_) => continue,
+            };
+            if decrypt_payment_id(encrypted, &tx_pub_key, view_key) == *id {
+                tx_hashes.push(Hash::from_slice(&entry[..32]));
+            }
+        }
+        Ok(tx_hashes)
+    }
+}
+
+impl ReadWriteDb {
+    /// Adds the payment IDs of the transactions of the blocks in `range` to the
+    /// index, creating it if needed, so blocks written before
+    /// [`crate::OpenOptions::index_payment_ids`] was set can be looked up.
+    /// Indexing a block twice is a no-op. Miner transactions are not indexed, and
+    /// the blocks are committed in batches of 1000.
+    ///
+    pub fn index_payment_ids(&self, range: Range<u64>) -> Result<(), Error> {
+        let index = open_payment_ids(&self.env, true)?.expect("created if missing");
+        let mut start = range.start;
+        while start < range.end {
+            let end = range.end.min(start.saturating_add(INDEX_BATCH_BLOCKS));
+            // read before writing, a thread can only have one transaction open
+            let mut entries = Vec::new();
+            for height in start..end {
+                self.check_cancelled(height)?;
+                for tx_hash in self.get_block(height)?.tx_hashes {
+                    let tx = self.get_tx_pruned_by_hash(&tx_hash)?;
+                    entries.extend(index_entries(&tx_hash, &tx.prefix));
+                }
+            }
+            let mut transaction = self.env.begin_rw_txn()?;
+            put_entries(&mut transaction, index, &entries)?;
+            transaction.commit()?;
+            start = end;
+        }
+        Ok(())
+    }
+}
+
+fn parse_payment_id(nonce: &[u8]) -> Option<PaymentId> {
+    match nonce.split_first()? {
+        (&TX_EXTRA_NONCE_PAYMENT_ID, id) if id.len() == 32 => {
+            Some(PaymentId::Long(id.try_into().ok()?))
+        }
+        (&TX_EXTRA_NONCE_ENCRYPTED_PAYMENT_ID, id) if id.len() == 8 => {
+            Some(PaymentId::Short(id.try_into().ok()?))
+        }
+        _ => None,
+    }
+}
+
+fn decrypt_payment_id(id: [u8; 8], tx_pub_key: &PublicKey, view_key: &PrivateKey) -> [u8; 8] {
+    let point = match tx_pub_key.point.decompress() {
+        Some(point) => point,
+        None => return id,
+    };
+    let derivation = (point * (view_key.scalar * Scalar::from(8u8))).compress();
+    let mut data = derivation.as_bytes().to_vec();
+    data.push(ENCRYPTED_PAYMENT_ID_TAIL);
+    let key = keccak_256(&data);
+
+    let mut decrypted = id;
+    for (byte, key) in decrypted.iter_mut().zip(key.iter()) {
+        *byte ^= key;
+    }
+    decrypted
+}
@@ -0,0 +1,278 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Proof of work hashing and verification
+//!
+//! The hashing blob of any block can be built. With the `randomx` feature the
+//! RandomX hash of blocks from hard fork 12 can be computed and checked against
+//! their difficulty. Older blocks use CryptoNight variants, which are not
+//! supported.
+
+use monero::consensus::serialize;
+use monero::cryptonote::hash::{keccak_256, Hashable};
+#[cfg(feature = "randomx")]
+use monero::Hash;
+use monero::{Block, VarInt};
+#[cfg(feature = "randomx")]
+use randomx_rs::{RandomXCache, RandomXFlag, RandomXVM};
+#[cfg(feature = "randomx")]
+use std::ops::Range;
+
+#[cfg(feature = "randomx")]
+use super::consensus::RANDOMX_MAJOR_VERSION;
+use super::consensus::{SEEDHASH_EPOCH_BLOCKS, SEEDHASH_EPOCH_LAG};
+use super::{BlockHeight, Error, MoneroDB};
+
+/// Computes the Merkle root of `hashes` as cryptonote's `tree_hash`
+pub(crate) fn tree_hash(hashes: &[[u8; 32]]) -> [u8; 32] {
+    let hash_pair = |a: &[u8; 32], b: &[u8; 32]| {
+        let mut pair = [0; 64];
+        pair[..32].copy_from_slice(a);
+        pair[32..].copy_from_slice(b);
+        keccak_256(&pair)
+    };
+    match hashes.len() {
+        0 => [0; 32],
+        1 => hashes[0],
+        2 => hash_pair(&hashes[0], &hashes[1]),
+        count => {
+            let mut cnt = count.next_power_of_two() / 2;
+            let direct = 2 * cnt - count;
+            let mut ints = hashes[..direct].to_vec();
+            ints.extend(
+                hashes[direct..]
+                    .chunks(2)
+                    .map(|pair| hash_pair(&pair[0], &pair[1])),
+            );
+            while cnt > 2 {
+                cnt /= 2;
+                ints = ints
+                    .chunks(2)
+                    .map(|pair| hash_pair(&pair[0], &pair[1]))
+                    .collect();
+            }
+            hash_pair(&ints[0], &ints[1])
+        }
+    }
+}
+
+/// Builds the blob hashed for the proof of work of `block`: its header, the Merkle
+/// root of its transactions and the number of transactions
+///
+pub(crate) fn hashing_blob(block: &Block) -> Vec<u8> {
+    let mut tx_hashes = vec![*block.miner_tx.hash().as_fixed_bytes()];
+    tx_hashes.extend(
+        block
+            .tx_hashes
+            .iter()
+            .map(|tx_hash| *tx_hash.as_fixed_bytes()),
+    );
+
+    let mut blob = serialize(&block.header);
+    blob.extend_from_slice(&tree_hash(&tx_hashes));
+    blob.extend_from_slice(&serialize(&VarInt(tx_hashes.len() as u64)));
+    blob
+}
+
+/// Height of the block whose hash seeds RandomX for the block at `height`
+pub(crate) fn seed_height(height: u64) -> u64 {
+    if height <= SEEDHASH_EPOCH_BLOCKS + SEEDHASH_EPOCH_LAG {
+        0
+    } else {
+        (height - SEEDHASH_EPOCH_LAG - 1) & !(SEEDHASH_EPOCH_BLOCKS - 1)
+    }
+}
+
+/// Returns if `hash`, read as a little endian 256 bit number, times `difficulty`
+/// does not overflow 256 bits
+///
+#[cfg(feature = "randomx")]
+fn check_hash(hash: &[u8; 32], difficulty: u128) -> bool {
+    let limbs: Vec<u128> = hash
+        .chunks(8)
+        .map(|limb| u64::from_le_bytes(limb.try_into().unwrap()) as u128)
+        .collect();
+    let factors = [difficulty as u64 as u128, difficulty >> 64];
+    let mut product = [0u128; 6];
+    for (i, limb) in limbs.iter().enumerate() {
+        let mut carry = 0;
+        for (j, factor) in factors.iter().enumerate() {
+            let sum = limb * factor + product[i + j] + carry;
+            product[i + j] = sum as u64 as u128;
+            carry = sum >> 64;
+        }
+        product[i + 2] += carry;
+    }
+    product[4] == 0 && product[5] == 0
+}
+
+impl MoneroDB {
+    /// Gets the blob hashed for the proof of work of the block at `block_height`
+    ///
+    pub fn get_block_hashing_blob(
+        &self,
+        block_height: impl Into<BlockHeight>,
+    ) -> Result<Vec<u8>, Error> {
+        Ok(hashing_blob(&self.get_block(block_height)?))
+    }
+
+    /// Computes the proof of work hash of the block at `height`, the "long hash" of
+    /// monerod. The RandomX VM is built for each call, which takes around a second.
+    ///
+    /// Fails with [`Error::PowError`] for blocks before hard fork 12, which are
+    /// hashed with CryptoNight.
+    ///
+    #[cfg(feature = "randomx")]
+    pub fn get_block_longhash(&self, height: impl Into<BlockHeight>) -> Result<[u8; 32], Error> {
+        let height = height.into().get();
+        let block = self.get_block(height)?;
+        if block.header.major_version.0 < RANDOMX_MAJOR_VERSION {
+            return Err(Error::PowError(format!(
+                "block {} has major version {}, only RandomX blocks can be hashed",
+                height, block.header.major_version.0
+            )));
+        }
+        PowHasher::default().hash(self, height, &block)
+    }
+
+    /// Computes the proof of work hash of the block at `height` and checks it meets
+    /// the block's difficulty, see [`MoneroDB::get_block_longhash`].
+    ///
+    #[cfg(feature = "randomx")]
+    pub fn verify_block_pow(&self, height: impl Into<BlockHeight>) -> Result<bool, Error> {
+        let height = height.into().get();
+        let pow_hash = self.get_block_longhash(height)?;
+        Ok(check_hash(&pow_hash, self.block_difficulty(height)?))
+    }
+
+    /// Checks the proof of work of the RandomX blocks in `range`, reusing the VM
+    /// while the seed stays the same. Returns the heights of the blocks failing the
+    /// check and the number of older blocks skipped. `progress` is called with each
+    /// height checked.
+    ///
+    #[cfg(feature = "randomx")]
+    pub(crate) fn find_invalid_pow<F: FnMut(u64)>(
+        &self,
+        range: Range<u64>,
+        mut progress: F,
+    ) -> Result<(Vec<u64>, u64), Error> {
+        let mut hasher = PowHasher::default();
+        let (mut invalid, mut skipped) = (Vec::new(), 0);
+        for height in range {
+            self.check_cancelled(height)?;
+            let block = self.get_block(height)?;
+            if block.header.major_version.0 < RANDOMX_MAJOR_VERSION {
+                skipped += 1;
+            } else if !check_hash(
+                &hasher.hash(self, height, &block)?,
+                self.block_difficulty(height)?,
+            ) {
+                invalid.push(height);
+            }
+            progress(height);
+        }
+        Ok((invalid, skipped))
+    }
+
+    #[cfg(feature = "randomx")]
+    fn block_difficulty(&self, height: u64) -> Result<u128, Error> {
+        let cumulative_difficulty = self.get_block_cumulative_difficulty(height)?;
+        Ok(match height {
+            0 => cumulative_difficulty,
+            _ => cumulative_difficulty - self.get_block_cumulative_difficulty(height - 1)?,
+        })
+    }
+}
+
+/// Hashes blocks with a RandomX VM in light mode, kept until the seed changes
+#[cfg(feature = "randomx")]
+#[derive(Default)]
+struct PowHasher {
+    vm: Option<(Hash, RandomXVM)>,
+}
+
+#[cfg(feature = "randomx")]
+impl PowHasher {
+    fn hash(&mut self, db: &MoneroDB, height: u64, block: &Block) -> Result<[u8; 32], Error> {
+        let pow_error = |e: randomx_rs::RandomXError| Error::PowError(e.to_string());
+        let seed_hash = db.get_block_hash(seed_height(height))?;
+        if !matches!(&self.vm, Some((vm_seed, _)) if *vm_seed == seed_hash) {
+            let flags = RandomXFlag::get_recommended_flags();
+            let cache = RandomXCache::new(flags, seed_hash.as_bytes()).map_err(pow_error)?;
+            let vm = RandomXVM::new(flags, Some(cache), None).map_err(pow_error)?;
+            self.vm = Some((seed_hash, vm));
+        }
+        let (_, vm) = self.vm.as_ref().unwrap();
+        let hash = vm.calculate_hash(&hashing_blob(block)).map_err(pow_error)?;
+        hash.try_into()
+            .map_err(|_| Error::PowError("RandomX hash is not 32 bytes".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hashing_blob, seed_height, tree_hash};
+    use crate::consensus::genesis_hash;
+    use crate::write::genesis_block;
+    use monero::consensus::serialize;
+    use monero::cryptonote::hash::keccak_256;
+    use monero::{Network, VarInt};
+
+    fn hash_pair(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+        keccak_256(&[&a[..], &b[..]].concat())
+    }
+
+    #[test]
+    fn tree_hash_shapes() {
+        let h: Vec<[u8; 32]> = (0..5).map(|i| [i as u8; 32]).collect();
+        assert_eq!(tree_hash(&[]), [0; 32]);
+        assert_eq!(tree_hash(&h[..1]), h[0]);
+        assert_eq!(tree_hash(&h[..2]), hash_pair(&h[0], &h[1]));
+        assert_eq!(
+            tree_hash(&h[..3]),
+            hash_pair(&h[0], &hash_pair(&h[1], &h[2]))
+        );
+        assert_eq!(
+            tree_hash(&h[..4]),
+            hash_pair(&hash_pair(&h[0], &h[1]), &hash_pair(&h[2], &h[3]))
+        );
+        assert_eq!(
+            tree_hash(&h[..5]),
+            hash_pair(
+                &hash_pair(&h[0], &h[1]),
+                &hash_pair(&h[2], &hash_pair(&h[3], &h[4]))
+            )
+        );
+    }
+
+    #[test]
+    fn genesis_hashing_blob() {
+        for network in [Network::Mainnet, Network::Testnet, Network::Stagenet] {
+            let blob = hashing_blob(&genesis_block(network));
+            let mut prefixed = serialize(&VarInt(blob.len() as u64));
+            prefixed.extend_from_slice(&blob);
+            assert_eq!(
+                keccak_256(&prefixed),
+                genesis_hash(network).to_fixed_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn seed_heights() {
+        assert_eq!(seed_height(0), 0);
+        assert_eq!(seed_height(2112), 0);
+        assert_eq!(seed_height(2113), 2048);
+        assert_eq!(seed_height(4160), 2048);
+        assert_eq!(seed_height(4161), 4096);
+    }
+}
@@ -0,0 +1,69 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Warming up the page cache before random access workloads
+
+use monero::cryptonote::hash::Hashable;
+use std::hint::black_box;
+use std::ops::Range;
+
+use super::monero_db::read_item;
+use super::{Error, MoneroDB, ZERO_KEY};
+
+const PAGE_SIZE: usize = 4096;
+
+/// Reads one byte of every page of `value` so the OS maps it in
+///
+fn touch(value: &[u8]) -> u64 {
+    let mut sum = 0u8;
+    for page in value.chunks(PAGE_SIZE) {
+        sum = sum.wrapping_add(page[0]);
+    }
+    black_box(sum);
+    value.len() as u64
+}
+
+impl MoneroDB {
+    /// Reads the blocks in `range` and their transactions in order so their pages
+    /// are in memory before a random access workload runs, returning the number
+    /// of bytes touched.
+    ///
+    /// Block blobs, block info, the pruned and prunable parts of every transaction
+    /// and their output indices are touched. Pruned transaction data is skipped.
+    ///
+    pub fn prefetch(&self, range: Range<u64>) -> Result<u64, Error> {
+        let mut touched = 0;
+        for height in range {
+            self.check_cancelled(height)?;
+            let key = height.to_le_bytes();
+            touched += read_item(self, self.sub_dbs.block_info, &ZERO_KEY, &key, 2, touch)?;
+            let block = self.get_block(height)?;
+            touched += read_item(self, self.sub_dbs.blocks, &key, &[0], 15, touch)?;
+
+            for tx_hash in std::iter::once(block.miner_tx.hash()).chain(block.tx_hashes) {
+                let tx_id = self.get_tx_indices(&tx_hash)?.data.tx_id.to_le_bytes();
+                for sub_db in [
+                    self.sub_dbs.txs_pruned,
+                    self.sub_dbs.txs_prunable,
+                    self.sub_dbs.tx_outputs,
+                ] {
+                    match read_item(self, sub_db, &tx_id, &[0], 15, touch) {
+                        Ok(bytes) => touched += bytes,
+                        Err(e) if e.is_not_found() => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        }
+        Ok(touched)
+    }
+}
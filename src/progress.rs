@@ -0,0 +1,102 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Progress reporting of long running operations
+
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::parallel::ScanOptions;
+
+pub(crate) type ProgressCallback = Arc<dyn Fn(&Progress) + Send + Sync>;
+
+/// Progress of a scan, audit, export or import, passed to the callback set with
+/// [`ScanOptions::on_progress`]
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    /// Name of the method running, such as `audit_block_hashes` or `Pipeline::run`
+    pub operation: &'static str,
+    /// Height processed up to, exclusive
+    pub height: u64,
+    /// Height the operation stops at, exclusive, if known
+    pub end_height: Option<u64>,
+    /// Number of blocks processed so far
+    pub blocks: u64,
+    /// Blocks processed per second since the operation started
+    pub blocks_per_sec: f64,
+    /// Estimated time until the operation finishes, if the end is known and any
+    /// blocks were processed
+    pub eta: Option<Duration>,
+}
+
+/// Turns the heights an operation reached into [`Progress`] reports
+pub(crate) struct ProgressTracker<'a> {
+    callback: Option<&'a ProgressCallback>,
+    operation: &'static str,
+    start_height: u64,
+    end_height: Option<u64>,
+    started: Instant,
+}
+
+impl<'a> ProgressTracker<'a> {
+    pub(crate) fn new(
+        options: &'a ScanOptions,
+        operation: &'static str,
+        range: Range<u64>,
+    ) -> Self {
+        ProgressTracker::with_end(options, operation, range.start, Some(range.end))
+    }
+
+    pub(crate) fn with_end(
+        options: &'a ScanOptions,
+        operation: &'static str,
+        start_height: u64,
+        end_height: Option<u64>,
+    ) -> Self {
+        ProgressTracker {
+            callback: options.on_progress.as_ref(),
+            operation,
+            start_height,
+            end_height,
+            started: Instant::now(),
+        }
+    }
+
+    /// Reports that every block below `height` was processed
+    pub(crate) fn report(&self, height: u64) {
+        let callback = match self.callback {
+            Some(callback) => callback,
+            None => return,
+        };
+        let blocks = height.saturating_sub(self.start_height);
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let blocks_per_sec = if elapsed > 0.0 {
+            blocks as f64 / elapsed
+        } else {
+            0.0
+        };
+        let eta = self
+            .end_height
+            .filter(|_| blocks_per_sec > 0.0)
+            .map(|end| Duration::from_secs_f64(end.saturating_sub(height) as f64 / blocks_per_sec));
+        callback(&Progress {
+            operation: self.operation,
+            height,
+            end_height: self.end_height,
+            blocks,
+            blocks_per_sec,
+            eta,
+        });
+    }
+}
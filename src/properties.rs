@@ -0,0 +1,152 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Access to the properties table
+
+use lmdb::{Cursor, Transaction, WriteFlags};
+
+use super::monero_db::put_item;
+use super::{Error, MoneroDB, ReadWriteDb};
+
+/// Property keys are stored with a trailing null byte
+fn property_key(key: &str) -> Vec<u8> {
+    let mut bytes = key.as_bytes().to_vec();
+    bytes.push(0);
+    bytes
+}
+
+/// Value of a property, decoded if the key is known
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PropertyValue {
+    /// The `version` property
+    Version(u32),
+    /// The `pruning_seed` property
+    PruningSeed(u32),
+    /// The `max_block_size` property
+    MaxBlockSize(u64),
+    /// A property with an unknown key or an unexpected length
+    Raw(Vec<u8>),
+}
+
+impl PropertyValue {
+    fn decode(key: &str, value: &[u8]) -> Self {
+        match (key, value.len()) {
+            ("version", 4) => PropertyValue::Version(u32::from_le_bytes(value.try_into().unwrap())),
+            ("pruning_seed", 4) => {
+                PropertyValue::PruningSeed(u32::from_le_bytes(value.try_into().unwrap()))
+            }
+            ("max_block_size", 8) => {
+                PropertyValue::MaxBlockSize(u64::from_le_bytes(value.try_into().unwrap()))
+            }
+            _ => PropertyValue::Raw(value.to_vec()),
+        }
+    }
+}
+
+/// An entry of the properties table
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Property {
+    /// Key of the property, without the trailing null byte
+    pub key: String,
+    /// Value of the property
+    pub value: PropertyValue,
+}
+
+/// All known properties of the database, read in a single transaction
+///
+/// Properties missing from the database are `None`.
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DbProperties {
+    /// Version of the database
+    pub version: Option<u32>,
+    /// Pruning seed of the database, 0 if not pruned
+    pub pruning_seed: Option<u32>,
+    /// The max block size
+    pub max_block_size: Option<u64>,
+}
+
+impl MoneroDB {
+    /// Gets all known properties of the database at once
+    ///
+    pub fn db_properties(&self) -> Result<DbProperties, Error> {
+        let mut db_properties = DbProperties::default();
+        for property in self.properties()? {
+            match property.value {
+                PropertyValue::Version(version) => db_properties.version = Some(version),
+                PropertyValue::PruningSeed(seed) => db_properties.pruning_seed = Some(seed),
+                PropertyValue::MaxBlockSize(size) => db_properties.max_block_size = Some(size),
+                PropertyValue::Raw(_) => {}
+            }
+        }
+        Ok(db_properties)
+    }
+
+    /// Gets every entry of the properties table, including ones this library
+    /// does not know about
+    ///
+    pub fn properties(&self) -> Result<Vec<Property>, Error> {
+        let transaction = self.begin_read()?;
+        let curser = transaction.open_ro_cursor(self.sub_dbs.properties)?;
+        let mut properties = Vec::new();
+        let mut op = 0;
+        loop {
+            let (key, value) = match curser.get(None, None, op) {
+                Ok(entry) => entry,
+                Err(lmdb::Error::NotFound) => break,
+                Err(e) => return Err(e.into()),
+            };
+            op = 8;
+            let key = key.unwrap_or_default();
+            let key = String::from_utf8_lossy(key.strip_suffix(&[0]).unwrap_or(key)).into_owned();
+            properties.push(Property {
+                value: PropertyValue::decode(&key, value),
+                key,
+            });
+        }
+        Ok(properties)
+    }
+}
+
+impl ReadWriteDb {
+    /// Sets a property to the given raw value, overwriting any existing value
+    ///
+    pub fn set_property(&self, key: &str, value: &[u8]) -> Result<(), Error> {
+        put_item(
+            &self.env,
+            self.sub_dbs.properties,
+            &property_key(key),
+            &value.to_vec(),
+            WriteFlags::empty(),
+        )
+    }
+
+    /// Sets the version of the database
+    ///
+    pub fn set_db_version(&self, version: u32) -> Result<(), Error> {
+        self.set_property("version", &version.to_le_bytes())
+    }
+
+    /// Sets the pruning seed of the database
+    ///
+    pub fn set_pruning_seed(&self, pruning_seed: u32) -> Result<(), Error> {
+        self.set_property("pruning_seed", &pruning_seed.to_le_bytes())
+    }
+
+    /// Sets the max block size
+    ///
+    pub fn set_max_block_size(&self, max_block_size: u64) -> Result<(), Error> {
+        self.set_property("max_block_size", &max_block_size.to_le_bytes())
+    }
+}
@@ -0,0 +1,108 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Retrying reads that fail with transient errors
+
+use lmdb::RoTransaction;
+use std::thread;
+use std::time::Duration;
+
+use super::{Error, MoneroDB};
+
+/// `EBUSY`, returned by LMDB on some platforms while another process holds a lock
+const EBUSY: i32 = 16;
+
+/// How reads retry transient failures, set with [`OpenOptions::retry_policy`].
+///
+/// Starting a read fails transiently when every reader slot is in use and with
+/// `EBUSY` on some platforms. The retries wait `initial_backoff`, doubling after
+/// each attempt up to `max_backoff`.
+///
+/// [`Error::MapResized`] is not retried, LMDB only lets the new map size be adopted
+/// while no transaction is open in the process, which a handle shared between
+/// threads can't ensure. The database has to be reopened instead.
+///
+/// [`Error::MapResized`]: crate::Error::MapResized
+///
+/// [`OpenOptions::retry_policy`]: crate::OpenOptions::retry_policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt
+    pub max_retries: u32,
+    /// Wait before the first retry
+    pub initial_backoff: Duration,
+    /// Longest wait between two retries
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Creates the default policy, retrying 5 times starting with a 10ms wait
+    ///
+    pub fn new() -> Self {
+        RetryPolicy::default()
+    }
+
+    /// Sets the number of retries after the first attempt
+    ///
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sets the wait before the first retry and the longest wait between retries
+    ///
+    pub fn backoff(mut self, initial_backoff: Duration, max_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self.max_backoff = max_backoff;
+        self
+    }
+}
+
+impl MoneroDB {
+    /// Begins a read transaction, retrying transient failures as set with
+    /// [`OpenOptions::retry_policy`](crate::OpenOptions::retry_policy)
+    pub(crate) fn begin_read(&self) -> Result<RoTransaction<'_>, Error> {
+        let policy = match self.options.retry_policy {
+            Some(policy) => policy,
+            None => return Ok(self.env.begin_ro_txn()?),
+        };
+        let mut backoff = policy.initial_backoff;
+        let mut retries = 0;
+        loop {
+            let error = match self.env.begin_ro_txn() {
+                Ok(transaction) => return Ok(transaction),
+                Err(e) => e,
+            };
+            let transient = match error {
+                lmdb::Error::ReadersFull => true,
+                lmdb::Error::Other(code) => code == EBUSY,
+                _ => false,
+            };
+            if !transient || retries >= policy.max_retries {
+                return Err(error.into());
+            }
+            thread::sleep(backoff);
+            backoff = (backoff * 2).min(policy.max_backoff);
+            retries += 1;
+        }
+    }
+}
@@ -0,0 +1,211 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Ring member resolution
+
+use lmdb::{Cursor, RoTransaction, Transaction};
+use monero::blockdata::transaction::TxIn;
+use monero::consensus::deserialize;
+use monero::database::transaction::{TransactionPruned, TxIndex};
+use monero::{Hash, PublicKey, VarInt};
+use std::io::Write;
+use std::ops::Range;
+
+use super::write::zero_commit;
+use super::{Error, MoneroDB, ZERO_KEY};
+
+/// An output referenced by the ring of a transaction input
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RingMember {
+    /// Index of the output among the outputs with the same amount
+    pub amount_index: u64,
+    /// Global output ID
+    pub output_id: u64,
+    /// One time public key of the output
+    pub public_key: PublicKey,
+    /// Amount commitment, for pre-RCT outputs this is the commitment to the
+    /// visible amount with a blinding factor of 1
+    pub commitment: [u8; 32],
+    /// Height of the block containing the output
+    pub height: u64,
+    /// Unlock time of the transaction that created the output
+    pub unlock_time: u64,
+}
+
+/// Converts the key offsets of an input into absolute amount indices.
+///
+/// Inputs store the first ring member's index and then the distance from each
+/// member to the previous one, so the indices are the running sums of the offsets.
+///
+pub fn resolve_key_offsets(key_offsets: &[u64]) -> Vec<u64> {
+    key_offsets
+        .iter()
+        .scan(0u64, |index, offset| {
+            *index = index.saturating_add(*offset);
+            Some(*index)
+        })
+        .collect()
+}
+
+impl MoneroDB {
+    /// Writes the ring members of every RCT input in the blocks of `range` to `writer`.
+    ///
+    /// One line is written per input in the form
+    /// `height,tx_hash,input_index,global_index global_index ...` where the global
+    /// indices are the absolute output ids of the ring members.
+    ///
+    pub fn export_rings<W: Write>(&self, range: Range<u64>, writer: &mut W) -> Result<(), Error> {
+        for height in range {
+            self.check_cancelled(height)?;
+            let block = self.get_block(height)?;
+            for tx_hash in &block.tx_hashes {
+                let tx = self.get_tx_pruned_by_hash(tx_hash)?;
+                for (input_index, input) in tx.prefix.inputs.iter().enumerate() {
+                    let key_offsets = match input {
+                        TxIn::ToKey {
+                            amount,
+                            key_offsets,
+                            ..
+                        } if amount.0 == 0 => key_offsets,
+                        _ => continue,
+                    };
+
+                    let members: Vec<String> = self
+                        .get_ring_output_ids(0, key_offsets)?
+                        .iter()
+                        .map(|output_id| output_id.to_string())
+                        .collect();
+                    writeln!(
+                        writer,
+                        "{},{},{},{}",
+                        height,
+                        hex::encode(tx_hash.as_bytes()),
+                        input_index,
+                        members.join(" ")
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Gets the outputs referenced by the ring of a `ToKey` input, other inputs
+    /// have no ring and return no members
+    ///
+    pub fn get_ring_members(&self, input: &TxIn) -> Result<Vec<RingMember>, Error> {
+        let transaction = self.begin_read()?;
+        self.read_ring_members(&transaction, input)
+    }
+
+    /// Gets the ring members of every input of the transaction, in input order.
+    ///
+    /// All lookups share one read transaction, so the rings are consistent with each
+    /// other even while blocks are being written.
+    ///
+    pub fn get_tx_rings(&self, txn_hash: &Hash) -> Result<Vec<Vec<RingMember>>, Error> {
+        self.check_table_version(self.sub_dbs.tx_indices)?;
+        let transaction = self.begin_read()?;
+
+        let curser = transaction.open_ro_cursor(self.sub_dbs.tx_indices)?;
+        let (_, tx_index) = curser
+            .get(Some(&ZERO_KEY), Some(txn_hash.as_bytes()), 2)
+            .map_err(|e| Error::from_lookup(e, "tx_indices", txn_hash.as_bytes()))?;
+        let tx_id = deserialize::<TxIndex>(tx_index)?.data.tx_id.to_le_bytes();
+
+        let curser = transaction.open_ro_cursor(self.sub_dbs.txs_pruned)?;
+        let (_, tx) = curser
+            .get(Some(&tx_id), None, 15)
+            .map_err(|e| Error::from_lookup(e, "txs_pruned", &tx_id))?;
+        let tx = deserialize::<TransactionPruned>(tx)?;
+
+        tx.prefix
+            .inputs
+            .iter()
+            .map(|input| self.read_ring_members(&transaction, input))
+            .collect()
+    }
+
+    /// Gets the ring members of `input` within an existing read transaction
+    pub(crate) fn read_ring_members(
+        &self,
+        transaction: &RoTransaction,
+        input: &TxIn,
+    ) -> Result<Vec<RingMember>, Error> {
+        let (amount, key_offsets) = match input {
+            TxIn::ToKey {
+                amount,
+                key_offsets,
+                ..
+            } => (amount.0, key_offsets),
+            _ => return Ok(Vec::new()),
+        };
+        self.check_table_version(self.sub_dbs.output_amounts)?;
+        let curser = transaction.open_ro_cursor(self.sub_dbs.output_amounts)?;
+        let key_offsets: Vec<u64> = key_offsets.iter().map(|offset| offset.0).collect();
+
+        resolve_key_offsets(&key_offsets)
+            .into_iter()
+            .map(|amount_index| {
+                let index_key = amount_index.to_le_bytes();
+                let (_, outkey) = curser
+                    .get(Some(&amount.to_le_bytes()), Some(&index_key), 2)
+                    .map_err(|e| Error::from_lookup(e, "output_amounts", &index_key))?;
+                parse_outkey(amount, outkey).ok_or_else(|| Error::InvalidOutkey {
+                    amount,
+                    amount_index,
+                })
+            })
+            .collect()
+    }
+
+    /// Converts the relative key offsets of an input into the global output ids
+    /// of its ring members
+    ///
+    pub(crate) fn get_ring_output_ids(
+        &self,
+        amount: u64,
+        key_offsets: &[VarInt],
+    ) -> Result<Vec<u64>, Error> {
+        let key_offsets: Vec<u64> = key_offsets.iter().map(|offset| offset.0).collect();
+        let mut output_ids = Vec::with_capacity(key_offsets.len());
+        for amount_index in resolve_key_offsets(&key_offsets) {
+            let output_id = if amount == 0 {
+                self.get_output_rct_outkey(amount, amount_index)?.output_id
+            } else {
+                self.get_output_pre_rct_outkey(amount, amount_index)?
+                    .output_id
+            };
+            output_ids.push(output_id);
+        }
+        Ok(output_ids)
+    }
+}
+
+/// Reads an `output_amounts` entry, laid out as the amount index, output ID, public
+/// key, unlock time and height followed by the commitment for RCT outputs
+///
+fn parse_outkey(amount: u64, outkey: &[u8]) -> Option<RingMember> {
+    let read_u64 = |at: usize| Some(u64::from_le_bytes(outkey.get(at..at + 8)?.try_into().ok()?));
+    let commitment = match amount {
+        0 => outkey.get(64..96)?.try_into().ok()?,
+        amount => zero_commit(amount),
+    };
+    Some(RingMember {
+        amount_index: read_u64(0)?,
+        output_id: read_u64(8)?,
+        public_key: PublicKey::from_slice(outkey.get(16..48)?).ok()?,
+        unlock_time: read_u64(48)?,
+        height: read_u64(56)?,
+        commitment,
+    })
+}
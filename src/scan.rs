@@ -0,0 +1,390 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Wallet output scanning
+
+use monero::blockdata::transaction::KeyImage;
+use monero::cryptonote::onetime_key::SubKeyChecker;
+use monero::cryptonote::subaddress::Index;
+use monero::util::key::ViewPair;
+use monero::{Hash, PrivateKey, PublicKey, Transaction};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::consensus::{
+    DEFAULT_TX_SPENDABLE_AGE, LOCKED_TX_ALLOWED_DELTA_BLOCKS, LOCKED_TX_ALLOWED_DELTA_SECONDS,
+    MAX_BLOCK_NUMBER, MINED_MONEY_UNLOCK_WINDOW,
+};
+use super::parallel::{scan_parallel, DEFAULT_CHUNK_SIZE};
+use super::{Error, MoneroDB};
+
+/// Number of subaddress accounts wallet2 looks ahead by default
+const DEFAULT_MAJOR_LOOKAHEAD: u32 = 50;
+/// Number of subaddresses per account wallet2 looks ahead by default
+const DEFAULT_MINOR_LOOKAHEAD: u32 = 200;
+
+/// The size of the subaddress table checked when scanning outputs
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubaddressLookahead {
+    /// Number of accounts (major indices) to check
+    pub major: u32,
+    /// Number of subaddresses (minor indices) to check per account
+    pub minor: u32,
+}
+
+impl Default for SubaddressLookahead {
+    fn default() -> Self {
+        SubaddressLookahead {
+            major: DEFAULT_MAJOR_LOOKAHEAD,
+            minor: DEFAULT_MINOR_LOOKAHEAD,
+        }
+    }
+}
+
+/// The keys of a wallet needed to detect outputs sent to it
+///
+#[derive(Debug, Clone)]
+pub struct ScanKeys {
+    /// Private view key and public spend key of the wallet
+    pub view_pair: ViewPair,
+    /// Subaddress table to check outputs against
+    pub lookahead: SubaddressLookahead,
+}
+
+impl ScanKeys {
+    /// Creates scan keys using wallet2's default subaddress lookahead
+    ///
+    pub fn new(view_pair: ViewPair) -> Self {
+        ScanKeys {
+            view_pair,
+            lookahead: SubaddressLookahead::default(),
+        }
+    }
+
+    /// Builds the table of subaddress spend keys inside the lookahead, which is
+    /// costly so should be done once per scan rather than per transaction.
+    fn checker(&self) -> SubKeyChecker<'_> {
+        SubKeyChecker::new(
+            &self.view_pair,
+            0..self.lookahead.major,
+            0..self.lookahead.minor,
+        )
+    }
+}
+
+/// An output found to belong to the scanned wallet
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnedOutput {
+    /// Height of the block containing the transaction
+    pub height: u64,
+    /// Hash of the transaction containing the output
+    pub tx_hash: Hash,
+    /// Index of the output in the transaction
+    pub index: usize,
+    /// One-time public key of the output
+    pub key: PublicKey,
+    /// Amount of the output, `None` if it could not be decoded
+    pub amount: Option<u64>,
+    /// Subaddress the output was sent to
+    pub subaddress: Index,
+    /// Unlock time of the transaction
+    pub unlock_time: u64,
+    /// If the output belongs to a miner transaction
+    pub coinbase: bool,
+}
+
+impl OwnedOutput {
+    /// Returns if the output can be spent at the given blockchain height, using
+    /// the same rules as wallet2
+    ///
+    pub fn is_unlocked(&self, blockchain_height: u64) -> bool {
+        let spendable_age = if self.coinbase {
+            MINED_MONEY_UNLOCK_WINDOW
+        } else {
+            DEFAULT_TX_SPENDABLE_AGE
+        };
+        if self.height + spendable_age > blockchain_height {
+            return false;
+        }
+
+        if self.unlock_time < MAX_BLOCK_NUMBER {
+            blockchain_height - 1 + LOCKED_TX_ALLOWED_DELTA_BLOCKS >= self.unlock_time
+        } else {
+            unix_time() + LOCKED_TX_ALLOWED_DELTA_SECONDS >= self.unlock_time
+        }
+    }
+}
+
+/// Balance of a wallet
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Balance {
+    /// Total amount of unspent outputs
+    pub confirmed: u64,
+    /// Amount of unspent outputs that can currently be spent
+    pub unlocked: u64,
+}
+
+/// Outputs found while restoring a wallet, along with how far the restore got
+///
+/// The cache can be written out with [`OutputCache::write_to`] and passed back to
+/// [`MoneroDB::resume_wallet_restore`] to continue an interrupted restore.
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutputCache {
+    /// Every block below this height has been scanned
+    pub scanned_height: u64,
+    /// Outputs belonging to the wallet, ordered by height
+    pub outputs: Vec<OwnedOutput>,
+}
+
+impl OutputCache {
+    /// Creates an empty cache that starts scanning at `from_height`
+    ///
+    pub fn new(from_height: u64) -> Self {
+        OutputCache {
+            scanned_height: from_height,
+            outputs: Vec::new(),
+        }
+    }
+
+    /// Writes the cache to `writer`
+    ///
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.scanned_height.to_le_bytes())?;
+        writer.write_all(&(self.outputs.len() as u64).to_le_bytes())?;
+        for output in &self.outputs {
+            writer.write_all(&output.height.to_le_bytes())?;
+            writer.write_all(output.tx_hash.as_bytes())?;
+            writer.write_all(&(output.index as u64).to_le_bytes())?;
+            writer.write_all(output.key.as_bytes())?;
+            writer.write_all(&[output.amount.is_some() as u8])?;
+            writer.write_all(&output.amount.unwrap_or(0).to_le_bytes())?;
+            writer.write_all(&output.subaddress.major.to_le_bytes())?;
+            writer.write_all(&output.subaddress.minor.to_le_bytes())?;
+            writer.write_all(&output.unlock_time.to_le_bytes())?;
+            writer.write_all(&[output.coinbase as u8])?;
+        }
+        Ok(())
+    }
+
+    /// Reads a cache previously written with [`OutputCache::write_to`]
+    ///
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let scanned_height = read_u64(reader)?;
+        let count = read_u64(reader)?;
+        let mut outputs = Vec::new();
+        for _ in 0..count {
+            let height = read_u64(reader)?;
+            let tx_hash = Hash::from_slice(&read_array::<_, 32>(reader)?);
+            let index = read_u64(reader)? as usize;
+            let key = PublicKey::from_slice(&read_array::<_, 32>(reader)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let has_amount = read_array::<_, 1>(reader)?[0] != 0;
+            let amount = read_u64(reader)?;
+            let major = u32::from_le_bytes(read_array(reader)?);
+            let minor = u32::from_le_bytes(read_array(reader)?);
+            let unlock_time = read_u64(reader)?;
+            let coinbase = read_array::<_, 1>(reader)?[0] != 0;
+            outputs.push(OwnedOutput {
+                height,
+                tx_hash,
+                index,
+                key,
+                amount: has_amount.then_some(amount),
+                subaddress: Index { major, minor },
+                unlock_time,
+                coinbase,
+            });
+        }
+        Ok(OutputCache {
+            scanned_height,
+            outputs,
+        })
+    }
+}
+
+/// Gets the current unix time in seconds
+pub(crate) fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0)
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+pub(crate) fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    Ok(u64::from_le_bytes(read_array(reader)?))
+}
+
+impl MoneroDB {
+    /// Restores the outputs of a wallet by scanning every block from `from_height`
+    /// to the top of the chain, spread over the threads set with
+    /// [`MoneroDB::set_scan_options`].
+    ///
+    pub fn restore_wallet_outputs(
+        &self,
+        keys: &ScanKeys,
+        from_height: u64,
+    ) -> Result<OutputCache, Error> {
+        self.resume_wallet_restore(keys, OutputCache::new(from_height), |_| {})
+    }
+
+    /// Continues a wallet restore from `cache.scanned_height` to the top of the chain.
+    ///
+    /// `checkpoint` is called with the progress so far each time another batch of
+    /// blocks has been scanned, so it can be persisted and resumed after a crash.
+    ///
+    pub fn resume_wallet_restore<F: FnMut(&OutputCache)>(
+        &self,
+        keys: &ScanKeys,
+        mut cache: OutputCache,
+        mut checkpoint: F,
+    ) -> Result<OutputCache, Error> {
+        let blockchain_height = self.get_blockchain_height()?;
+        let checker = keys.checker();
+        scan_parallel(
+            cache.scanned_height..blockchain_height,
+            &self.options.scan,
+            "resume_wallet_restore",
+            DEFAULT_CHUNK_SIZE,
+            |range| self.scan_outputs_with(&checker, range),
+            |range, outputs| {
+                cache.outputs.extend(outputs);
+                cache.scanned_height = range.end;
+                checkpoint(&cache);
+                Ok(())
+            },
+        )?;
+        Ok(cache)
+    }
+
+    /// Computes the balance of a view-only wallet over the whole blockchain.
+    ///
+    /// `known_key_images` maps the one-time public keys of owned outputs to their
+    /// key images, as imported into a view-only wallet. Outputs whose key image is
+    /// in `spent_keys` are not counted, outputs without a known key image are
+    /// assumed unspent.
+    ///
+    pub fn compute_balance(
+        &self,
+        view_key: &PrivateKey,
+        spend_pub: &PublicKey,
+        known_key_images: &HashMap<PublicKey, KeyImage>,
+    ) -> Result<Balance, Error> {
+        let keys = ScanKeys::new(ViewPair {
+            view: *view_key,
+            spend: *spend_pub,
+        });
+        let blockchain_height = self.get_blockchain_height()?;
+        let outputs = self.scan_outputs(&keys, 0..blockchain_height)?;
+
+        let mut balance = Balance::default();
+        for output in outputs {
+            if let Some(key_image) = known_key_images.get(&output.key) {
+                if self.is_key_image_spent(key_image.image.as_bytes())? {
+                    continue;
+                }
+            }
+            let amount = output.amount.unwrap_or(0);
+            balance.confirmed += amount;
+            if output.is_unlocked(blockchain_height) {
+                balance.unlocked += amount;
+            }
+        }
+        Ok(balance)
+    }
+
+    /// Scans the transactions of the blocks in `range` for outputs belonging to the wallet.
+    ///
+    /// Outputs sent to any subaddress inside the lookahead table of `keys` are detected,
+    /// including ones using additional transaction public keys.
+    ///
+    pub fn scan_outputs(
+        &self,
+        keys: &ScanKeys,
+        range: Range<u64>,
+    ) -> Result<Vec<OwnedOutput>, Error> {
+        self.scan_outputs_with(&keys.checker(), range)
+    }
+
+    fn scan_outputs_with(
+        &self,
+        checker: &SubKeyChecker,
+        range: Range<u64>,
+    ) -> Result<Vec<OwnedOutput>, Error> {
+        let mut owned = Vec::new();
+        for height in range {
+            let block = self.get_block(height)?;
+            let miner_tx_hash = block.miner_tx.hash();
+            scan_tx(
+                checker,
+                height,
+                &miner_tx_hash,
+                &block.miner_tx,
+                true,
+                &mut owned,
+            )?;
+
+            for tx_hash in &block.tx_hashes {
+                let tx = self.get_tx_pruned_by_hash(tx_hash)?;
+                let tx = Transaction {
+                    prefix: tx.prefix,
+                    signatures: Vec::new(),
+                    rct_signatures: tx.rct_signatures,
+                };
+                scan_tx(checker, height, tx_hash, &tx, false, &mut owned)?;
+            }
+        }
+        Ok(owned)
+    }
+}
+
+/// Finds the outputs of `tx` belonging to `keys`. The hash is passed in as `tx` may
+/// be rebuilt from its pruned part, which does not hash to the transaction hash.
+pub(crate) fn scan_tx(
+    checker: &SubKeyChecker,
+    height: u64,
+    tx_hash: &Hash,
+    tx: &Transaction,
+    coinbase: bool,
+    owned: &mut Vec<OwnedOutput>,
+) -> Result<(), Error> {
+    let outputs = tx.check_outputs_with(checker)?;
+    if outputs.is_empty() {
+        return Ok(());
+    }
+
+    for output in outputs {
+        owned.push(OwnedOutput {
+            height,
+            tx_hash: *tx_hash,
+            index: output.index(),
+            key: output.pubkey(),
+            amount: output.amount().map(|amount| amount.as_pico()),
+            subaddress: output.sub_index(),
+            unlock_time: tx.prefix.unlock_time.0,
+            coinbase,
+        });
+    }
+    Ok(())
+}
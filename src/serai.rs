@@ -0,0 +1,66 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Conversions to and from `monero-serai` types
+//!
+//! Enabled with the `serai` feature. Types are converted through their consensus
+//! encoding, which both libraries share.
+
+use monero::consensus::{deserialize, serialize};
+use monero::Hash;
+use monero_serai::block::Block as SeraiBlock;
+use monero_serai::transaction::Transaction as SeraiTransaction;
+
+use super::{Error, MoneroDB};
+
+/// Converts a block into a `monero-serai` block
+///
+pub fn block_to_serai(block: &monero::Block) -> Result<SeraiBlock, Error> {
+    Ok(SeraiBlock::read(&mut serialize(block).as_slice())?)
+}
+
+/// Converts a `monero-serai` block into a block
+///
+pub fn block_from_serai(block: &SeraiBlock) -> Result<monero::Block, Error> {
+    Ok(deserialize(&block.serialize())?)
+}
+
+/// Converts a transaction into a `monero-serai` transaction
+///
+pub fn transaction_to_serai(tx: &monero::Transaction) -> Result<SeraiTransaction, Error> {
+    Ok(SeraiTransaction::read(&mut serialize(tx).as_slice())?)
+}
+
+/// Converts a `monero-serai` transaction into a transaction
+///
+pub fn transaction_from_serai(tx: &SeraiTransaction) -> Result<monero::Transaction, Error> {
+    Ok(deserialize(&tx.serialize())?)
+}
+
+impl MoneroDB {
+    /// Gets the block at `block_height` as a `monero-serai` block
+    ///
+    pub fn get_serai_block(&self, block_height: u64) -> Result<SeraiBlock, Error> {
+        Ok(SeraiBlock::read(
+            &mut self.get_block_blob(block_height)?.as_slice(),
+        )?)
+    }
+
+    /// Gets the full transaction as a `monero-serai` transaction, fails if the
+    /// prunable part has been pruned
+    ///
+    pub fn get_serai_transaction(&self, txn_hash: &Hash) -> Result<SeraiTransaction, Error> {
+        Ok(SeraiTransaction::read(
+            &mut self.get_tx_blob(txn_hash)?.as_slice(),
+        )?)
+    }
+}
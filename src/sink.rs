@@ -0,0 +1,420 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Destinations for exported chain data
+//!
+//! A [`ChainSink`] receives blocks, their transactions and the outputs of those
+//! transactions in chain order. [`MoneroDB::export_to_sink`] feeds a sink from a
+//! snapshot of the chain and [`SinkHandler`] feeds one from an
+//! [`Indexer`](crate::Indexer) following the chain.
+
+use monero::blockdata::transaction::TxOut;
+use monero::consensus::{deserialize, serialize};
+use monero::cryptonote::hash::Hashable;
+use monero::{Block, Hash};
+use std::io::{self, Write};
+use std::ops::Range;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+
+use super::indexer::IndexHandler;
+use super::lazy::LazyBlock;
+use super::parallel::{pipeline, ByteBudget};
+use super::progress::ProgressTracker;
+use super::{Error, MoneroDB};
+
+/// Receives chain data, blocks are followed by their transactions, the miner
+/// transaction first, and each transaction by its outputs
+///
+pub trait ChainSink {
+    /// Receives a block
+    ///
+    fn block(&mut self, height: u64, block: &Block) -> Result<(), Error>;
+
+    /// Receives a transaction of the last block
+    ///
+    fn transaction(
+        &mut self,
+        height: u64,
+        tx_hash: &Hash,
+        tx: &monero::Transaction,
+    ) -> Result<(), Error> {
+        let _ = (height, tx_hash, tx);
+        Ok(())
+    }
+
+    /// Receives an output of the last transaction
+    ///
+    fn output(
+        &mut self,
+        height: u64,
+        tx_hash: &Hash,
+        index: usize,
+        output: &TxOut,
+    ) -> Result<(), Error> {
+        let _ = (height, tx_hash, index, output);
+        Ok(())
+    }
+
+    /// Called once everything has been sent, sinks buffering their input write it
+    /// out here
+    ///
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// An item sent by a [`ChannelSink`]
+///
+#[derive(Debug, Clone)]
+pub enum SinkItem {
+    /// A block and its height
+    Block {
+        /// Height of the block
+        height: u64,
+        /// The block
+        block: Block,
+    },
+    /// A transaction and the height of its block
+    Transaction {
+        /// Height of the block containing the transaction
+        height: u64,
+        /// Hash of the transaction
+        tx_hash: Hash,
+        /// The transaction
+        tx: monero::Transaction,
+    },
+    /// An output of a transaction
+    Output {
+        /// Height of the block containing the transaction
+        height: u64,
+        /// Hash of the transaction
+        tx_hash: Hash,
+        /// Index of the output within the transaction
+        index: usize,
+        /// The output
+        output: TxOut,
+    },
+}
+
+impl SinkItem {
+    /// Serialized size of the block, transaction or output
+    fn size(&self) -> u64 {
+        let size = match self {
+            SinkItem::Block { block, .. } => serialize(block).len(),
+            SinkItem::Transaction { tx, .. } => serialize(tx).len(),
+            SinkItem::Output { output, .. } => serialize(output).len(),
+        };
+        size as u64
+    }
+}
+
+/// A sink sending everything it receives over a channel, fails once the receiver
+/// is dropped
+///
+#[derive(Debug, Clone)]
+pub struct ChannelSink {
+    sender: Sender<SinkItem>,
+    outputs: bool,
+    budget: Option<Arc<ByteBudget>>,
+}
+
+impl ChannelSink {
+    /// Creates a sink sending blocks, transactions and, if `outputs` is set,
+    /// outputs to `sender`
+    ///
+    pub fn new(sender: Sender<SinkItem>, outputs: bool) -> Self {
+        ChannelSink {
+            sender,
+            outputs,
+            budget: None,
+        }
+    }
+
+    /// Creates a sink and the receiver of what it sends, sending blocks until
+    /// `max_bytes_in_flight` bytes of items have been sent but not yet received.
+    ///
+    /// Exports into the sink pause while the receiver is behind, instead of
+    /// queueing the whole chain in memory.
+    ///
+    pub fn bounded(outputs: bool, max_bytes_in_flight: u64) -> (Self, SinkReceiver) {
+        let (sender, receiver) = mpsc::channel();
+        let budget = Arc::new(ByteBudget::new(max_bytes_in_flight));
+        let sink = ChannelSink {
+            sender,
+            outputs,
+            budget: Some(budget.clone()),
+        };
+        (sink, SinkReceiver { receiver, budget })
+    }
+
+    fn send(&self, item: SinkItem) -> Result<(), Error> {
+        if let Some(budget) = &self.budget {
+            budget.acquire(item.size());
+        }
+        self.sender
+            .send(item)
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe).into())
+    }
+}
+
+/// Receiving end of a sink created by [`ChannelSink::bounded`], iterating over the
+/// items sent until every sender is dropped
+///
+#[derive(Debug)]
+pub struct SinkReceiver {
+    receiver: Receiver<SinkItem>,
+    budget: Arc<ByteBudget>,
+}
+
+impl Iterator for SinkReceiver {
+    type Item = SinkItem;
+
+    fn next(&mut self) -> Option<SinkItem> {
+        let item = self.receiver.recv().ok()?;
+        self.budget.release(item.size());
+        Some(item)
+    }
+}
+
+impl Drop for SinkReceiver {
+    fn drop(&mut self) {
+        self.budget.close();
+    }
+}
+
+impl ChainSink for ChannelSink {
+    fn block(&mut self, height: u64, block: &Block) -> Result<(), Error> {
+        self.send(SinkItem::Block {
+            height,
+            block: block.clone(),
+        })
+    }
+
+    fn transaction(
+        &mut self,
+        height: u64,
+        tx_hash: &Hash,
+        tx: &monero::Transaction,
+    ) -> Result<(), Error> {
+        self.send(SinkItem::Transaction {
+            height,
+            tx_hash: *tx_hash,
+            tx: tx.clone(),
+        })
+    }
+
+    fn output(
+        &mut self,
+        height: u64,
+        tx_hash: &Hash,
+        index: usize,
+        output: &TxOut,
+    ) -> Result<(), Error> {
+        if !self.outputs {
+            return Ok(());
+        }
+        self.send(SinkItem::Output {
+            height,
+            tx_hash: *tx_hash,
+            index,
+            output: output.clone(),
+        })
+    }
+}
+
+/// Record tags of a [`FileSink`]
+const RECORD_BLOCK: u8 = 0;
+const RECORD_TRANSACTION: u8 = 1;
+
+/// A sink writing blocks and transactions to a writer, usually a buffered file.
+///
+/// Each record is a tag byte, 0 for a block and 1 for a transaction, the height
+/// as a little endian u64, then the serialized block or transaction prefixed with
+/// its length as a little endian u64. Outputs are part of their transaction and
+/// not written separately.
+///
+#[derive(Debug)]
+pub struct FileSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> FileSink<W> {
+    /// Creates a sink writing to `writer`
+    ///
+    pub fn new(writer: W) -> Self {
+        FileSink { writer }
+    }
+
+    /// Returns the underlying writer
+    ///
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_record(&mut self, tag: u8, height: u64, blob: &[u8]) -> Result<(), Error> {
+        self.writer.write_all(&[tag])?;
+        self.writer.write_all(&height.to_le_bytes())?;
+        self.writer.write_all(&(blob.len() as u64).to_le_bytes())?;
+        self.writer.write_all(blob)?;
+        Ok(())
+    }
+}
+
+impl<W: Write> ChainSink for FileSink<W> {
+    fn block(&mut self, height: u64, block: &Block) -> Result<(), Error> {
+        self.write_record(RECORD_BLOCK, height, &serialize(block))
+    }
+
+    fn transaction(
+        &mut self,
+        height: u64,
+        _tx_hash: &Hash,
+        tx: &monero::Transaction,
+    ) -> Result<(), Error> {
+        self.write_record(RECORD_TRANSACTION, height, &serialize(tx))
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Passes a transaction and its outputs to `sink`
+fn send_tx<S: ChainSink + ?Sized>(
+    sink: &mut S,
+    height: u64,
+    tx_hash: &Hash,
+    tx: &monero::Transaction,
+) -> Result<(), Error> {
+    sink.transaction(height, tx_hash, tx)?;
+    for (index, output) in tx.prefix.outputs.iter().enumerate() {
+        sink.output(height, tx_hash, index, output)?;
+    }
+    Ok(())
+}
+
+/// Passes a block, its transactions read with `get_tx` and their outputs to `sink`
+fn send_block<S: ChainSink + ?Sized>(
+    sink: &mut S,
+    height: u64,
+    block: &Block,
+    get_tx: impl Fn(&Hash) -> Result<monero::Transaction, Error>,
+) -> Result<(), Error> {
+    sink.block(height, block)?;
+    send_tx(sink, height, &block.miner_tx.hash(), &block.miner_tx)?;
+    for tx_hash in &block.tx_hashes {
+        send_tx(sink, height, tx_hash, &get_tx(tx_hash)?)?;
+    }
+    Ok(())
+}
+
+/// Number of blocks the reader of [`MoneroDB::export_to_sink`] copies ahead of the
+/// decoding threads
+const EXPORT_READ_AHEAD: usize = 64;
+
+/// A block and its transactions copied out of the database, not yet decoded
+struct RawExportBlock {
+    height: u64,
+    block: LazyBlock,
+    txs: Vec<Vec<u8>>,
+}
+
+impl RawExportBlock {
+    fn decode(self) -> Result<(u64, Block, Vec<monero::Transaction>), Error> {
+        let txs = self
+            .txs
+            .iter()
+            .map(|tx| deserialize(tx))
+            .collect::<Result<_, _>>()?;
+        Ok((self.height, self.block.to_block()?, txs))
+    }
+}
+
+impl MoneroDB {
+    /// Sends the blocks in `range`, their transactions and outputs to `sink` from a
+    /// single snapshot of the chain, then flushes it.
+    ///
+    /// One thread copies the blocks and transactions out of the database, taking
+    /// the page faults, while the threads set with [`MoneroDB::set_scan_options`]
+    /// decode them. The sink receives everything in order on the calling thread.
+    /// As with [`MoneroDB::snapshot`], read only handles need
+    /// [`crate::OpenOptions::lock_readers`].
+    ///
+    pub fn export_to_sink<S: ChainSink + ?Sized>(
+        &self,
+        range: Range<u64>,
+        sink: &mut S,
+    ) -> Result<(), Error> {
+        let end = range.end.min(self.get_blockchain_height()?);
+        let progress = ProgressTracker::new(&self.options.scan, "export_to_sink", range.start..end);
+        pipeline(
+            self.options.scan.threads,
+            EXPORT_READ_AHEAD,
+            |send| {
+                let snapshot = self.snapshot()?;
+                let mut blocks = snapshot.blocks(range)?;
+                while let Some((height, blob)) = blocks.next_raw()? {
+                    let block = LazyBlock::from_blob(blob.to_vec())?;
+                    let txs = block
+                        .tx_hashes()?
+                        .iter()
+                        .map(|tx_hash| snapshot.get_tx_blob(tx_hash))
+                        .collect::<Result<_, _>>()?;
+                    if !send(RawExportBlock { height, block, txs }) {
+                        break;
+                    }
+                }
+                Ok(())
+            },
+            RawExportBlock::decode,
+            |(height, block, txs)| {
+                self.check_cancelled(height)?;
+                sink.block(height, &block)?;
+                send_tx(sink, height, &block.miner_tx.hash(), &block.miner_tx)?;
+                for (tx_hash, tx) in block.tx_hashes.iter().zip(&txs) {
+                    send_tx(sink, height, tx_hash, tx)?;
+                }
+                progress.report(height + 1);
+                Ok(())
+            },
+        )?;
+        sink.flush()
+    }
+}
+
+/// An [`IndexHandler`] feeding the blocks added to the chain to a [`ChainSink`]
+///
+/// Blocks popped by reorganizations and pool transactions are not sent.
+///
+pub struct SinkHandler<'a, S: ChainSink> {
+    db: &'a MoneroDB,
+    sink: S,
+}
+
+impl<'a, S: ChainSink> SinkHandler<'a, S> {
+    /// Creates a handler reading transactions from `db` and sending them to `sink`
+    ///
+    pub fn new(db: &'a MoneroDB, sink: S) -> Self {
+        SinkHandler { db, sink }
+    }
+}
+
+impl<'a, S: ChainSink> IndexHandler for SinkHandler<'a, S> {
+    fn on_block_added(&mut self, height: u64, block: &Block) -> Result<(), Error> {
+        send_block(&mut self.sink, height, block, |tx_hash| {
+            self.db.get_tx(tx_hash)
+        })?;
+        self.sink.flush()
+    }
+}
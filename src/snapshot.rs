@@ -0,0 +1,448 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Consistent read snapshots
+//!
+//! LMDB read transactions see the database as it was when they began, a snapshot
+//! keeps one open so a long export is not affected by monerod appending blocks or
+//! reorganizing the chain in the meantime.
+//!
+//! This only holds while the snapshot is registered in the lock file, otherwise a
+//! writer in another process, such as monerod, may reuse pages it still reads.
+//! Read only handles are opened without locking unless
+//! [`OpenOptions::lock_readers`](crate::OpenOptions::lock_readers) is set, and
+//! snapshots can not be taken through them.
+
+use lmdb::{Cursor, RoCursor, RoTransaction, Transaction};
+use monero::consensus::deserialize;
+use monero::consensus::encode::Error::ParseFailed;
+use monero::cryptonote::hash::Hashable;
+use monero::{Block, Hash};
+use std::collections::VecDeque;
+use std::io::Read;
+use std::ops::Range;
+
+use super::block_info::{short_entry, BlockMetadata};
+use super::monero_db::deserialize_from;
+use super::{Error, MoneroDB, ZERO_KEY};
+
+/// Position of a transaction scan, saved to resume a long scan where it stopped
+///
+/// Transactions are numbered within their block, the miner transaction is at
+/// offset 0 followed by the transactions in the order of the block's hashes.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScanCursor {
+    /// Height of the block
+    pub height: u64,
+    /// Offset of the transaction within the block
+    pub tx_offset: u64,
+}
+
+impl ScanCursor {
+    /// Creates a cursor at the start of the block at `height`
+    ///
+    pub fn at_height(height: u64) -> Self {
+        ScanCursor {
+            height,
+            tx_offset: 0,
+        }
+    }
+
+    /// Encodes the cursor as 16 bytes
+    ///
+    pub fn to_bytes(&self) -> [u8; 16] {
+        let mut bytes = [0; 16];
+        bytes[..8].copy_from_slice(&self.height.to_le_bytes());
+        bytes[8..].copy_from_slice(&self.tx_offset.to_le_bytes());
+        bytes
+    }
+
+    /// Decodes a cursor encoded with [`ScanCursor::to_bytes`]
+    ///
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        ScanCursor {
+            height: u64::from_le_bytes(bytes[..8].try_into().unwrap()),
+            tx_offset: u64::from_le_bytes(bytes[8..].try_into().unwrap()),
+        }
+    }
+}
+
+/// A consistent view of the database, created by [`MoneroDB::snapshot`]
+///
+/// Every read through the snapshot and its iterators sees the chain as it was when
+/// the snapshot was taken. Pages freed by later writes can not be reused while the
+/// snapshot is alive, so long lived snapshots make the database file grow.
+///
+pub struct ReadSnapshot<'a> {
+    db: &'a MoneroDB,
+    transaction: RoTransaction<'a>,
+}
+
+impl MoneroDB {
+    /// Takes a snapshot of the database, fails with [`Error::UnlockedSnapshot`] if
+    /// the handle is read only and was not opened with
+    /// [`OpenOptions::lock_readers`](crate::OpenOptions::lock_readers)
+    ///
+    pub fn snapshot(&self) -> Result<ReadSnapshot<'_>, Error> {
+        if self.options.no_lock() {
+            return Err(Error::UnlockedSnapshot);
+        }
+        self.short_snapshot()
+    }
+
+    /// Takes a snapshot without checking for locking, for reads that last no
+    /// longer than a single getter
+    pub(crate) fn short_snapshot(&self) -> Result<ReadSnapshot<'_>, Error> {
+        Ok(ReadSnapshot {
+            db: self,
+            transaction: self.begin_read()?,
+        })
+    }
+}
+
+impl<'a> ReadSnapshot<'a> {
+    /// Gets the height of the chain in the snapshot (1 + height of max block)
+    ///
+    pub fn height(&self) -> Result<u64, Error> {
+        let stats = self.transaction.stat(self.db.sub_dbs.block_heights)?;
+        Ok(stats.entries() as u64)
+    }
+
+    /// Gets the height and hash of the top block in the snapshot, `None` if the
+    /// database holds no blocks
+    ///
+    pub fn tip(&self) -> Result<Option<(u64, Hash)>, Error> {
+        match self.height()?.checked_sub(1) {
+            Some(height) => Ok(Some((height, self.block_metadata(height)?.hash))),
+            None => Ok(None),
+        }
+    }
+
+    /// Gets the `block_info` entry of the block at `height`
+    ///
+    pub fn block_metadata(&self, height: u64) -> Result<BlockMetadata, Error> {
+        self.db.check_table_version(self.db.sub_dbs.block_info)?;
+        let key = height.to_le_bytes();
+        let curser = self
+            .transaction
+            .open_ro_cursor(self.db.sub_dbs.block_info)?;
+        let value = curser
+            .get(Some(&ZERO_KEY), Some(&key), 2)
+            .map_err(|e| Error::from_lookup(e, "block_info", &key))?
+            .1;
+        BlockMetadata::from_bytes(value).ok_or_else(|| short_entry(&key))
+    }
+
+    /// Iterates over the blocks in `range` in height order, stopping at the tip of
+    /// the snapshot
+    ///
+    pub fn blocks(&self, range: Range<u64>) -> Result<SnapshotBlocks<'_>, Error> {
+        Ok(SnapshotBlocks {
+            curser: self.transaction.open_ro_cursor(self.db.sub_dbs.blocks)?,
+            next_height: range.start,
+            end: range.end,
+            started: false,
+        })
+    }
+
+    /// Iterates over the transactions, miner transactions included, from `from`
+    /// up to the block at `end_height`, excluded, in chain order
+    ///
+    pub fn transactions(
+        &self,
+        from: ScanCursor,
+        end_height: u64,
+    ) -> Result<SnapshotTransactions<'a, '_>, Error> {
+        Ok(SnapshotTransactions {
+            snapshot: self,
+            blocks: self.blocks(from.height..end_height)?,
+            next: from,
+            pending: VecDeque::new(),
+            pending_miner_tx: None,
+        })
+    }
+
+    /// Gets the transaction with the given hash, fails with [`Error::Pruned`] if
+    /// its prunable part was removed when the database was pruned
+    ///
+    /// The transaction is decoded straight from its pruned and prunable parts in
+    /// the memory map, without copying them into one blob first.
+    ///
+    pub fn get_tx(&self, tx_hash: &Hash) -> Result<monero::Transaction, Error> {
+        let (tx_id, pruned, prunable) = self.tx_parts(tx_hash)?;
+        let len = (pruned.len() + prunable.len()) as u64;
+        deserialize_from(pruned.chain(prunable), len).map_err(|source| Error::Corrupt {
+            db: "txs_pruned",
+            key: tx_id.to_vec(),
+            source,
+        })
+    }
+
+    /// Gets the serialized transaction with the given hash, see
+    /// [`ReadSnapshot::get_tx`]
+    pub(crate) fn get_tx_blob(&self, tx_hash: &Hash) -> Result<Vec<u8>, Error> {
+        let (_, pruned, prunable) = self.tx_parts(tx_hash)?;
+        Ok([pruned, prunable].concat())
+    }
+
+    /// Gets the pruning seed of the database, read in this snapshot's transaction as
+    /// a second read transaction on the same thread fails on locked environments
+    fn pruning_seed(&self) -> Result<u32, Error> {
+        let key = b"pruning_seed\0";
+        let value = match self.transaction.get(self.db.sub_dbs.properties, key) {
+            Ok(value) => value,
+            Err(lmdb::Error::NotFound) => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        match value.get(..4) {
+            Some(seed) => Ok(u32::from_le_bytes(seed.try_into().unwrap())),
+            None => Err(Error::Corrupt {
+                db: "properties",
+                key: key.to_vec(),
+                source: ParseFailed("pruning seed too short"),
+            }),
+        }
+    }
+
+    /// Looks up the ID and the pruned and prunable parts of a transaction
+    fn tx_parts(&self, tx_hash: &Hash) -> Result<([u8; 8], &[u8], &[u8]), Error> {
+        self.db.check_table_version(self.db.sub_dbs.tx_indices)?;
+        let sub_dbs = &self.db.sub_dbs;
+        let index = self.transaction.open_ro_cursor(sub_dbs.tx_indices)?;
+        let value = index
+            .get(Some(&ZERO_KEY), Some(tx_hash.as_bytes()), 2)
+            .map_err(|e| Error::from_lookup(e, "tx_indices", tx_hash.as_bytes()))?
+            .1;
+        let tx_id: [u8; 8] = match value.get(32..40) {
+            Some(tx_id) => tx_id.try_into().unwrap(),
+            None => {
+                return Err(Error::Corrupt {
+                    db: "tx_indices",
+                    key: tx_hash.as_bytes().to_vec(),
+                    source: ParseFailed("tx index entry too short"),
+                });
+            }
+        };
+
+        let pruned = self
+            .transaction
+            .get(sub_dbs.txs_pruned, &tx_id)
+            .map_err(|e| Error::from_lookup(e, "txs_pruned", &tx_id))?;
+        self.db
+            .check_value_size("txs_pruned", &tx_id, pruned.len())?;
+        let prunable = match self.transaction.get(sub_dbs.txs_prunable, &tx_id) {
+            Ok(prunable) => prunable,
+            Err(lmdb::Error::NotFound) if self.pruning_seed()? != 0 => {
+                return Err(Error::Pruned {
+                    db: "txs_prunable",
+                    key: tx_id.to_vec(),
+                });
+            }
+            Err(e) => return Err(Error::from_lookup(e, "txs_prunable", &tx_id)),
+        };
+        self.db
+            .check_value_size("txs_prunable", &tx_id, prunable.len())?;
+        Ok((tx_id, pruned, prunable))
+    }
+}
+
+/// Iterator over the transactions of a [`ReadSnapshot`], created by
+/// [`ReadSnapshot::transactions`]
+///
+/// Each item holds the cursor to resume from to continue after the transaction.
+///
+pub struct SnapshotTransactions<'a, 'txn> {
+    snapshot: &'txn ReadSnapshot<'a>,
+    blocks: SnapshotBlocks<'txn>,
+    next: ScanCursor,
+    pending: VecDeque<Hash>,
+    pending_miner_tx: Option<monero::Transaction>,
+}
+
+impl<'a, 'txn> SnapshotTransactions<'a, 'txn> {
+    fn read_next(&mut self) -> Result<Option<(ScanCursor, Hash, monero::Transaction)>, Error> {
+        while self.pending_miner_tx.is_none() && self.pending.is_empty() {
+            let (height, block) = match self.blocks.next().transpose()? {
+                Some(block) => block,
+                None => return Ok(None),
+            };
+            let skip = if height == self.next.height {
+                self.next.tx_offset
+            } else {
+                0
+            };
+            self.next = ScanCursor::at_height(height);
+            if skip == 0 {
+                self.pending_miner_tx = Some(block.miner_tx);
+            } else {
+                self.next.tx_offset = skip;
+            }
+            self.pending = block
+                .tx_hashes
+                .into_iter()
+                .skip(skip.saturating_sub(1) as usize)
+                .collect();
+        }
+
+        let (tx_hash, tx) = match self.pending_miner_tx.take() {
+            Some(miner_tx) => (miner_tx.hash(), miner_tx),
+            None => {
+                let tx_hash = self.pending.pop_front().unwrap();
+                (tx_hash, self.snapshot.get_tx(&tx_hash)?)
+            }
+        };
+        self.next.tx_offset += 1;
+        Ok(Some((self.next, tx_hash, tx)))
+    }
+}
+
+impl<'a, 'txn> Iterator for SnapshotTransactions<'a, 'txn> {
+    type Item = Result<(ScanCursor, Hash, monero::Transaction), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.read_next().transpose();
+        if matches!(next, Some(Err(_))) {
+            self.pending.clear();
+            self.pending_miner_tx = None;
+            self.blocks.end = self.blocks.next_height;
+        }
+        next
+    }
+}
+
+/// Iterator over the blocks of a [`ReadSnapshot`] and their heights
+///
+pub struct SnapshotBlocks<'txn> {
+    curser: RoCursor<'txn>,
+    next_height: u64,
+    end: u64,
+    started: bool,
+}
+
+impl<'txn> SnapshotBlocks<'txn> {
+    /// Reads the next serialized block and its height without decoding it
+    pub(crate) fn next_raw(&mut self) -> Result<Option<(u64, &'txn [u8])>, Error> {
+        if self.next_height >= self.end {
+            return Ok(None);
+        }
+        let key = self.next_height.to_le_bytes();
+        let entry = if self.started {
+            self.curser.get(None, None, 8)
+        } else {
+            self.started = true;
+            self.curser.get(Some(&key), None, 15)
+        };
+        let blob = match entry {
+            Ok((_, blob)) => blob,
+            Err(lmdb::Error::NotFound) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        self.next_height += 1;
+        Ok(Some((self.next_height - 1, blob)))
+    }
+
+    fn read_next(&mut self) -> Result<Option<(u64, Block)>, Error> {
+        let (height, blob) = match self.next_raw()? {
+            Some(next) => next,
+            None => return Ok(None),
+        };
+        let block = deserialize(blob).map_err(|source| Error::Corrupt {
+            db: "blocks",
+            key: height.to_le_bytes().to_vec(),
+            source,
+        })?;
+        Ok(Some((height, block)))
+    }
+}
+
+impl<'txn> Iterator for SnapshotBlocks<'txn> {
+    type Item = Result<(u64, Block), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.read_next().transpose();
+        if !matches!(next, Some(Ok(_))) {
+            self.end = self.next_height;
+        }
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScanCursor;
+    use crate::{genesis_block, MoneroDB, ReadWriteDb};
+    use curve25519_dalek::constants::ED25519_BASEPOINT_COMPRESSED;
+    use monero::blockdata::transaction::TxIn;
+    use monero::consensus::deserialize;
+    use monero::cryptonote::hash::Hashable;
+    use monero::{Block, Hash, Network, Transaction, VarInt};
+
+    /// Deserializes a version 1 transaction with a single input of ring size 1
+    /// spending key image `key_image`, and a single output
+    fn tx(key_image: u8) -> Transaction {
+        let mut blob = vec![1, 0, 1, 0x02, 100, 1, 1];
+        blob.extend([key_image; 32]);
+        blob.extend([1, 50, 0x02]);
+        blob.extend(ED25519_BASEPOINT_COMPRESSED.as_bytes());
+        blob.push(0);
+        blob.extend([0; 64]);
+        deserialize(&blob).unwrap()
+    }
+
+    /// Adds a block with `txs` on top of the chain
+    fn add_block(db: &ReadWriteDb, txs: Vec<Transaction>) {
+        let height = db.get_blockchain_height().unwrap();
+        let mut block: Block = genesis_block(Network::Mainnet);
+        block.header.prev_id = db.get_block_hash(height - 1).unwrap();
+        block.miner_tx.prefix.inputs = vec![TxIn::Gen {
+            height: VarInt(height),
+        }];
+        block.tx_hashes = txs.iter().map(Hashable::hash).collect();
+        db.add_block(&block, &txs, 100, 100, 1).unwrap();
+    }
+
+    fn tx_hashes(db: &MoneroDB, from: ScanCursor) -> Vec<(ScanCursor, Hash)> {
+        let snapshot = db.snapshot().unwrap();
+        snapshot
+            .transactions(from, snapshot.height().unwrap())
+            .unwrap()
+            .map(|item| item.map(|(cursor, tx_hash, _)| (cursor, tx_hash)))
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn resume_at_offset() {
+        let dir = std::env::temp_dir().join(format!("monero_db_cursor_{}", std::process::id()));
+        let db = MoneroDB::create(&dir, Network::Mainnet).unwrap();
+        add_block(&db, vec![tx(1), tx(2)]);
+        add_block(&db, vec![tx(3)]);
+
+        let all = tx_hashes(&db, ScanCursor::at_height(0));
+        let cursors: Vec<_> = all.iter().map(|(cursor, _)| *cursor).collect();
+        let expected = [(0, 1), (1, 1), (1, 2), (1, 3), (2, 1), (2, 2)];
+        assert_eq!(
+            cursors,
+            expected.map(|(height, tx_offset)| ScanCursor { height, tx_offset })
+        );
+        for (i, (cursor, _)) in all.iter().enumerate() {
+            let cursor = ScanCursor::from_bytes(cursor.to_bytes());
+            assert_eq!(tx_hashes(&db, cursor), all[i + 1..]);
+        }
+        assert_eq!(tx_hashes(&db, ScanCursor::at_height(1)), all[1..]);
+
+        drop(db);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,500 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Chain statistics
+
+use monero::blockdata::transaction::TxIn;
+use monero::cryptonote::hash::Hashable;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use super::consensus::{difficulty_target, TIMESTAMP_CHECK_WINDOW};
+use super::monero_db::read_item;
+use super::parallel::{scan_parallel, DEFAULT_CHUNK_SIZE};
+use super::{BlockHeight, Error, MoneroDB};
+
+/// Histogram mapping a value to the number of times it was seen
+pub type Distribution = BTreeMap<usize, u64>;
+
+/// Ring size and input/output count distributions of the transactions in a block
+///
+/// Miner transactions are not included.
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RingStats {
+    /// Height of the block
+    pub height: u64,
+    /// Number of inputs per ring size
+    pub ring_sizes: Distribution,
+    /// Number of transactions per input count
+    pub inputs_per_tx: Distribution,
+    /// Number of transactions per output count
+    pub outputs_per_tx: Distribution,
+}
+
+/// Transaction, input and output counts of a block
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BlockIoCounts {
+    /// Height of the block
+    pub height: u64,
+    /// Number of transactions, excluding the miner transaction
+    pub txs: u64,
+    /// Number of inputs, excluding the miner transaction input
+    pub inputs: u64,
+    /// Number of outputs, including the miner transaction outputs
+    pub outputs: u64,
+    /// Number of RCT outputs
+    pub rct_outputs: u64,
+}
+
+/// Statistics of the blocks of one hard fork version
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct HardForkStats {
+    /// Hard fork version
+    pub version: u8,
+    /// Heights of the blocks with this version
+    pub heights: Range<u64>,
+    /// Number of transactions, excluding miner transactions
+    pub txs: u64,
+    /// Sum of the fees paid
+    pub fees: u64,
+    /// Sum of the block weights
+    pub total_weight: u64,
+    /// Number of inputs per ring size
+    pub ring_sizes: Distribution,
+}
+
+/// Timestamps used to compute block intervals
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampSource {
+    /// The timestamps miners put in the blocks
+    Block,
+    /// The median timestamp of the last 60 blocks up to the block, which
+    /// miners cannot easily skew
+    MedianTimePast,
+}
+
+/// Summary of the time between blocks, in seconds
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct IntervalStats {
+    /// Number of intervals
+    pub count: u64,
+    /// Mean interval
+    pub mean: f64,
+    /// Shortest interval, negative if a block has an earlier timestamp than its parent
+    pub min: i64,
+    /// 10th percentile
+    pub p10: i64,
+    /// Median interval
+    pub median: i64,
+    /// 90th percentile
+    pub p90: i64,
+    /// 99th percentile
+    pub p99: i64,
+    /// Longest interval
+    pub max: i64,
+}
+
+impl IntervalStats {
+    fn from_intervals(mut intervals: Vec<i64>) -> Self {
+        if intervals.is_empty() {
+            return IntervalStats::default();
+        }
+        intervals.sort_unstable();
+        let percentile = |p: usize| intervals[(intervals.len() - 1) * p / 100];
+        IntervalStats {
+            count: intervals.len() as u64,
+            mean: intervals.iter().sum::<i64>() as f64 / intervals.len() as f64,
+            min: intervals[0],
+            p10: percentile(10),
+            median: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: intervals[intervals.len() - 1],
+        }
+    }
+}
+
+/// Fields that can be requested from [`MoneroDB::timeseries`]
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeseriesField {
+    /// Block timestamp
+    Timestamp,
+    /// Block difficulty
+    Difficulty,
+    /// Cumulative difficulty of the chain up to the block
+    CumulativeDifficulty,
+    /// Block weight
+    Weight,
+    /// Newly emitted coins, excluding fees
+    Reward,
+}
+
+/// A single point of a [`MoneroDB::timeseries`], fields that were not requested are `None`
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TimeseriesPoint {
+    /// Height of the block
+    pub height: u64,
+    /// Block timestamp
+    pub timestamp: Option<u64>,
+    /// Block difficulty
+    pub difficulty: Option<u128>,
+    /// Cumulative difficulty of the chain up to the block
+    pub cumulative_difficulty: Option<u128>,
+    /// Block weight
+    pub weight: Option<u64>,
+    /// Newly emitted coins, excluding fees
+    pub reward: Option<u64>,
+}
+
+impl MoneroDB {
+    /// Gets the [`RingStats`] of every block in `range`
+    ///
+    pub fn ring_stats(&self, range: Range<u64>) -> Result<Vec<RingStats>, Error> {
+        let mut stats = Vec::new();
+        for height in range {
+            let block = self.get_block(height)?;
+            let mut block_stats = RingStats {
+                height,
+                ..Default::default()
+            };
+            for tx_hash in &block.tx_hashes {
+                let tx = self.get_tx_pruned_by_hash(tx_hash)?;
+                for input in &tx.prefix.inputs {
+                    if let TxIn::ToKey { key_offsets, .. } = input {
+                        *block_stats.ring_sizes.entry(key_offsets.len()).or_default() += 1;
+                    }
+                }
+                *block_stats
+                    .inputs_per_tx
+                    .entry(tx.prefix.inputs.len())
+                    .or_default() += 1;
+                *block_stats
+                    .outputs_per_tx
+                    .entry(tx.prefix.outputs.len())
+                    .or_default() += 1;
+            }
+            stats.push(block_stats);
+        }
+        Ok(stats)
+    }
+
+    /// Gets the [`BlockIoCounts`] of the block at `height`.
+    ///
+    /// Only the block header and miner transaction are decoded. RCT outputs are
+    /// counted from the cumulative RCT output counts of the block and the one
+    /// before it, so only version 1 transactions have their outputs looked up in
+    /// the output index table. Inputs are read from the start of each
+    /// transaction prefix.
+    ///
+    pub fn get_block_io_counts(
+        &self,
+        height: impl Into<BlockHeight>,
+    ) -> Result<BlockIoCounts, Error> {
+        let height = height.into().get();
+        let block = self.get_block_lazy(height)?;
+        let miner_tx = block.miner_tx()?;
+        let first_tx_id = self.get_tx_indices(&miner_tx.hash())?.data.tx_id;
+        let rct_outputs = self.get_block_info(height)?.cumulative_rct_outs
+            - match height {
+                0 => 0,
+                _ => self.get_block_info(height - 1)?.cumulative_rct_outs,
+            };
+
+        let mut counts = BlockIoCounts {
+            height,
+            txs: block.tx_hashes()?.len() as u64,
+            outputs: rct_outputs,
+            rct_outputs,
+            ..Default::default()
+        };
+        for tx_id in first_tx_id..=first_tx_id + counts.txs {
+            let key = tx_id.to_le_bytes();
+            let (version, inputs) = if tx_id == first_tx_id {
+                (miner_tx.prefix.version.0, 0)
+            } else {
+                match read_item(self, self.sub_dbs.txs_pruned, &key, &[0], 15, |tx| {
+                    Some((u64::from(*tx.first()?), input_count(tx)?))
+                })? {
+                    Some(header) => header,
+                    None => {
+                        let prefix = self.get_tx_pruned(tx_id)?.prefix;
+                        (prefix.version.0, prefix.inputs.len() as u64)
+                    }
+                }
+            };
+            counts.inputs += inputs;
+            if version == 1 {
+                counts.outputs +=
+                    read_item(self, self.sub_dbs.tx_outputs, &key, &[0], 15, |indices| {
+                        indices.len() as u64 / 8
+                    })?;
+            }
+        }
+        Ok(counts)
+    }
+
+    /// Gets the number of transactions, miner transactions included, in the blocks
+    /// below `height`.
+    ///
+    /// Transaction IDs are assigned in chain order, so this is the ID of the miner
+    /// transaction of the block at `height`.
+    ///
+    pub fn get_tx_count_at(&self, height: u64) -> Result<u64, Error> {
+        if height >= self.get_blockchain_height()? {
+            return self.get_tx_count();
+        }
+        let miner_tx_hash = self.get_block(height)?.miner_tx.hash();
+        Ok(self.get_tx_indices(&miner_tx_hash)?.data.tx_id)
+    }
+
+    /// Estimates the network hashrate, in hashes per second, at `height` from the
+    /// difficulty of the `window` blocks up to and including it.
+    ///
+    pub fn estimate_hashrate(&self, height: u64, window: u64) -> Result<u128, Error> {
+        let window = window.clamp(1, height.max(1));
+        let start = self.get_block_info(height.saturating_sub(window))?;
+        let end = self.get_block_info(height)?;
+        let target = difficulty_target(self.get_hf_version(height)?);
+
+        let difficulty = end.cumulative_difficulty() - start.cumulative_difficulty();
+        Ok(difficulty / (window as u128 * target as u128))
+    }
+
+    /// Gets the estimated hashrate at every height in `range`, see [`MoneroDB::estimate_hashrate`]
+    ///
+    pub fn hashrate_series(
+        &self,
+        range: Range<u64>,
+        window: u64,
+    ) -> Result<Vec<(u64, u128)>, Error> {
+        range
+            .map(|height| Ok((height, self.estimate_hashrate(height, window)?)))
+            .collect()
+    }
+
+    /// Computes statistics on the solve times of the blocks in `range`
+    ///
+    pub fn block_interval_stats(
+        &self,
+        range: Range<u64>,
+        source: TimestampSource,
+    ) -> Result<IntervalStats, Error> {
+        let start = range.start.max(1);
+        if start >= range.end {
+            return Ok(IntervalStats::default());
+        }
+
+        let first = match source {
+            TimestampSource::Block => start - 1,
+            TimestampSource::MedianTimePast => (start - 1).saturating_sub(TIMESTAMP_CHECK_WINDOW),
+        };
+        let timestamps = (first..range.end)
+            .map(|height| Ok(self.get_block_info(height)?.timestamp as i64))
+            .collect::<Result<Vec<i64>, Error>>()?;
+
+        let times: Vec<i64> = match source {
+            TimestampSource::Block => timestamps,
+            TimestampSource::MedianTimePast => (start - 1..range.end)
+                .map(|height| {
+                    let end = (height - first) as usize;
+                    let begin = end.saturating_sub(TIMESTAMP_CHECK_WINDOW as usize - 1);
+                    let mut window = timestamps[begin..=end].to_vec();
+                    window.sort_unstable();
+                    window[window.len() / 2]
+                })
+                .collect(),
+        };
+
+        let intervals = times.windows(2).map(|pair| pair[1] - pair[0]).collect();
+        Ok(IntervalStats::from_intervals(intervals))
+    }
+
+    /// Streams the requested `fields` of every block in `range` to `f`, in height order.
+    ///
+    /// All values come from a single pass over `block_info`, which is much cheaper
+    /// than calling the per-field getters for every block.
+    ///
+    pub fn timeseries<F: FnMut(TimeseriesPoint)>(
+        &self,
+        range: Range<u64>,
+        fields: &[TimeseriesField],
+        mut f: F,
+    ) -> Result<(), Error> {
+        let wants = |field| fields.contains(&field);
+        // start one block early so the first difficulty and reward can be computed
+        let first = range.start.saturating_sub(1);
+        let mut prev: Option<(u128, u64)> = None;
+
+        self.for_each_block_info(first..range.end, |info| {
+            let height = info.height;
+            let cumulative_difficulty = info.cumulative_difficulty();
+            let coins = info.total_coins_generated;
+            let (prev_difficulty, prev_coins) = prev.unwrap_or((0, 0));
+            prev = Some((cumulative_difficulty, coins));
+            if height < range.start {
+                return Ok(());
+            }
+
+            f(TimeseriesPoint {
+                height,
+                timestamp: wants(TimeseriesField::Timestamp).then_some(info.timestamp),
+                difficulty: wants(TimeseriesField::Difficulty)
+                    .then_some(cumulative_difficulty - prev_difficulty),
+                cumulative_difficulty: wants(TimeseriesField::CumulativeDifficulty)
+                    .then_some(cumulative_difficulty),
+                weight: wants(TimeseriesField::Weight).then_some(info.weight),
+                reward: wants(TimeseriesField::Reward).then_some(coins - prev_coins),
+            });
+            Ok(())
+        })
+    }
+
+    /// Gets the [`HardForkStats`] of every hard fork version in the chain, in
+    /// height order. Every block and transaction is read, spread over the threads
+    /// set with [`MoneroDB::set_scan_options`].
+    ///
+    pub fn stats_by_hardfork(&self) -> Result<Vec<HardForkStats>, Error> {
+        let chain_height = self.get_blockchain_height()?;
+        let activations = self.hf_activation_heights()?;
+        let mut forks = Vec::new();
+        for (i, activation) in activations.iter().enumerate() {
+            let (version, start) = (activation.version, activation.height);
+            let end = activations
+                .get(i + 1)
+                .map_or(chain_height, |next| next.height);
+
+            let mut fork = HardForkStats {
+                version,
+                heights: start..end,
+                ..Default::default()
+            };
+            scan_parallel(
+                start..end,
+                &self.options.scan,
+                "stats_by_hardfork",
+                DEFAULT_CHUNK_SIZE,
+                |chunk| {
+                    let mut weight = 0;
+                    for height in chunk.clone() {
+                        weight += self.get_block_weight(height)?;
+                    }
+                    Ok((
+                        self.ring_stats(chunk.clone())?,
+                        self.total_fees(chunk)?,
+                        weight,
+                    ))
+                },
+                |_, (ring_stats, fees, weight)| {
+                    for block in ring_stats {
+                        fork.txs += block.inputs_per_tx.values().sum::<u64>();
+                        for (ring_size, count) in block.ring_sizes {
+                            *fork.ring_sizes.entry(ring_size).or_default() += count;
+                        }
+                    }
+                    fork.fees += fees;
+                    fork.total_weight += weight;
+                    Ok(())
+                },
+            )?;
+            forks.push(fork);
+        }
+        Ok(forks)
+    }
+
+    /// Finds the highest block at or before `unix_ts`, the block that was the tip
+    /// at that time. Returns `None` if the genesis block is newer.
+    ///
+    /// The search is a binary search, which needs increasing timestamps. Block
+    /// timestamps can be out of order by up to a few hours,
+    /// [`TimestampSource::MedianTimePast`] never decreases at the cost of lagging
+    /// the block timestamps by about an hour.
+    ///
+    pub fn find_block_by_time(
+        &self,
+        unix_ts: u64,
+        source: TimestampSource,
+    ) -> Result<Option<u64>, Error> {
+        Ok(self
+            .first_block_after(unix_ts.saturating_add(1), source)?
+            .checked_sub(1))
+    }
+
+    /// Gets the heights of the blocks with a timestamp from `start` up to, not
+    /// including, `end`, see [`MoneroDB::find_block_by_time`] for the caveats.
+    ///
+    pub fn height_range_for_time(
+        &self,
+        start: u64,
+        end: u64,
+        source: TimestampSource,
+    ) -> Result<Range<u64>, Error> {
+        let first = self.first_block_after(start, source)?;
+        let last = self.first_block_after(end, source)?.max(first);
+        Ok(first..last)
+    }
+
+    /// Binary searches for the lowest height with a time of at least `unix_ts`,
+    /// gives the chain height if there is none
+    fn first_block_after(&self, unix_ts: u64, source: TimestampSource) -> Result<u64, Error> {
+        let (mut low, mut high) = (0, self.get_blockchain_height()?);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.block_time(mid, source)? < unix_ts {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        Ok(low)
+    }
+
+    pub(crate) fn block_time(&self, height: u64, source: TimestampSource) -> Result<u64, Error> {
+        match source {
+            TimestampSource::Block => self.get_block_timestamp(height),
+            TimestampSource::MedianTimePast => {
+                let first = (height + 1).saturating_sub(TIMESTAMP_CHECK_WINDOW);
+                let mut window = (first..=height)
+                    .map(|height| self.get_block_timestamp(height))
+                    .collect::<Result<Vec<u64>, Error>>()?;
+                window.sort_unstable();
+                Ok(window[window.len() / 2])
+            }
+        }
+    }
+}
+
+/// Reads the input count from the start of a serialized transaction, which begins
+/// with the version, unlock time and number of inputs as varints
+///
+fn input_count(tx: &[u8]) -> Option<u64> {
+    // each varint ends with the first byte without the continuation bit
+    let mut varints = tx.split_inclusive(|byte| byte & 0x80 == 0);
+    let count = varints.nth(2)?;
+    if count.len() > 10 || count.last()? & 0x80 != 0 {
+        return None;
+    }
+    Some(count.iter().enumerate().fold(0, |value, (i, byte)| {
+        value | u64::from(byte & 0x7f) << (7 * i)
+    }))
+}
@@ -10,7 +10,7 @@
 // The above copyright notice and this permission notice shall be included in all
 // copies or substantial portions of the Software.
 
-use crate::Error;
+use crate::{Error, OpenError};
 use lmdb::{Database, DatabaseFlags, Environment, Transaction};
 
 pub(crate) struct MoneroSubDB {
@@ -31,64 +31,67 @@ pub(crate) struct MoneroSubDB {
     pub(crate) alt_blocks: Database,
     pub(crate) hf_versions: Database,
     pub(crate) properties: Database,
+    /// Only present in databases created by old daemon versions
+    pub(crate) hf_starting_heights: Option<Database>,
 }
 
+/// Flag telling LMDB to create the database if it does not exist
+const MDB_CREATE: u32 = 0x40000;
+
 impl MoneroSubDB {
-    fn open_sub_dbs(env: &Environment) -> Result<Self, Error> {
+    fn open_sub_dbs(env: &Environment, create: bool) -> Result<Self, Error> {
+        let open_subdb = |name, flags| open_subdb(env, name, flags, create);
         Ok(MoneroSubDB {
-            blocks: open_subdb(env, "blocks", DatabaseFlags::INTEGER_KEY)?,
+            blocks: open_subdb("blocks", DatabaseFlags::INTEGER_KEY)?,
             block_info: open_subdb(
-                env,
                 "block_info",
                 DatabaseFlags::INTEGER_KEY | DatabaseFlags::DUP_SORT | DatabaseFlags::DUP_FIXED,
             )?,
             block_heights: open_subdb(
-                env,
                 "block_heights",
                 DatabaseFlags::INTEGER_KEY | DatabaseFlags::DUP_SORT | DatabaseFlags::DUP_FIXED,
             )?,
-            txs_pruned: open_subdb(env, "txs_pruned", DatabaseFlags::INTEGER_KEY)?,
-            txs_prunable: open_subdb(env, "txs_prunable", DatabaseFlags::INTEGER_KEY)?,
+            txs_pruned: open_subdb("txs_pruned", DatabaseFlags::INTEGER_KEY)?,
+            txs_prunable: open_subdb("txs_prunable", DatabaseFlags::INTEGER_KEY)?,
             txs_prunable_hash: open_subdb(
-                env,
                 "txs_prunable_hash",
                 DatabaseFlags::INTEGER_KEY | DatabaseFlags::DUP_SORT | DatabaseFlags::DUP_FIXED,
             )?,
             txs_prunable_tip: open_subdb(
-                env,
                 "txs_prunable_tip",
                 DatabaseFlags::INTEGER_KEY | DatabaseFlags::DUP_SORT | DatabaseFlags::DUP_FIXED,
             )?,
             tx_indices: open_subdb(
-                env,
                 "tx_indices",
                 DatabaseFlags::INTEGER_KEY | DatabaseFlags::DUP_SORT | DatabaseFlags::DUP_FIXED,
             )?,
             tx_outputs: open_subdb(
-                env,
                 "tx_outputs",
                 DatabaseFlags::INTEGER_KEY | DatabaseFlags::DUP_SORT | DatabaseFlags::DUP_FIXED,
             )?,
             output_txs: open_subdb(
-                env,
                 "output_txs",
                 DatabaseFlags::INTEGER_KEY | DatabaseFlags::DUP_SORT | DatabaseFlags::DUP_FIXED,
             )?,
             output_amounts: open_subdb(
-                env,
                 "output_amounts",
                 DatabaseFlags::INTEGER_KEY | DatabaseFlags::DUP_SORT | DatabaseFlags::DUP_FIXED,
             )?,
             spent_keys: open_subdb(
-                env,
                 "spent_keys",
                 DatabaseFlags::INTEGER_KEY | DatabaseFlags::DUP_SORT | DatabaseFlags::DUP_FIXED,
             )?,
-            txpool_meta: open_subdb(env, "txpool_meta", DatabaseFlags::empty())?,
-            txpool_blob: open_subdb(env, "txpool_blob", DatabaseFlags::empty())?,
-            alt_blocks: open_subdb(env, "alt_blocks", DatabaseFlags::empty())?,
-            hf_versions: open_subdb(env, "hf_versions", DatabaseFlags::INTEGER_KEY)?,
-            properties: open_subdb(env, "properties", DatabaseFlags::empty())?,
+            txpool_meta: open_subdb("txpool_meta", DatabaseFlags::empty())?,
+            txpool_blob: open_subdb("txpool_blob", DatabaseFlags::empty())?,
+            alt_blocks: open_subdb("alt_blocks", DatabaseFlags::empty())?,
+            hf_versions: open_subdb("hf_versions", DatabaseFlags::INTEGER_KEY)?,
+            properties: open_subdb("properties", DatabaseFlags::empty())?,
+            hf_starting_heights: open_optional_subdb(
+                env,
+                "hf_starting_heights",
+                DatabaseFlags::empty(),
+                false,
+            )?,
         })
     }
 
@@ -111,13 +114,99 @@ impl MoneroSubDB {
         Ok(())
     }
 
+    /// Gets the name of a sub-database
+    pub fn name(&self, db: Database) -> &'static str {
+        [
+            (self.blocks, "blocks"),
+            (self.block_heights, "block_heights"),
+            (self.block_info, "block_info"),
+            (self.txs_pruned, "txs_pruned"),
+            (self.txs_prunable, "txs_prunable"),
+            (self.txs_prunable_hash, "txs_prunable_hash"),
+            (self.txs_prunable_tip, "txs_prunable_tip"),
+            (self.tx_indices, "tx_indices"),
+            (self.tx_outputs, "tx_outputs"),
+            (self.output_txs, "output_txs"),
+            (self.output_amounts, "output_amounts"),
+            (self.spent_keys, "spent_keys"),
+            (self.txpool_meta, "txpool_meta"),
+            (self.txpool_blob, "txpool_blob"),
+            (self.alt_blocks, "alt_blocks"),
+            (self.hf_versions, "hf_versions"),
+            (self.properties, "properties"),
+        ]
+        .into_iter()
+        .chain(
+            self.hf_starting_heights
+                .map(|db| (db, "hf_starting_heights")),
+        )
+        .find(|(sub_db, _)| *sub_db == db)
+        .map_or("unknown", |(_, name)| name)
+    }
+
+    /// Returns if the table only holds consensus serialized data and hashes, so its
+    /// format does not change with the database version
+    pub fn is_version_independent(&self, db: Database) -> bool {
+        [
+            self.blocks,
+            self.block_heights,
+            self.txs_pruned,
+            self.txs_prunable,
+            self.txs_prunable_hash,
+            self.spent_keys,
+            self.txpool_blob,
+            self.hf_versions,
+            self.properties,
+        ]
+        .contains(&db)
+    }
+
     pub fn new(env: &Environment) -> Result<Self, Error> {
-        let sub_dbs = MoneroSubDB::open_sub_dbs(env)?;
+        let sub_dbs = MoneroSubDB::open_sub_dbs(env, false)?;
+        sub_dbs.set_sort(env)?;
+        Ok(sub_dbs)
+    }
+
+    /// Creates any missing sub-databases before opening them
+    pub fn create(env: &Environment) -> Result<Self, Error> {
+        let sub_dbs = MoneroSubDB::open_sub_dbs(env, true)?;
         sub_dbs.set_sort(env)?;
         Ok(sub_dbs)
     }
 }
 
-fn open_subdb(env: &Environment, name: &str, flags: DatabaseFlags) -> Result<Database, Error> {
-    Ok(env.open_db_with_flags(Some(name), flags.bits())?)
+fn open_subdb(
+    env: &Environment,
+    name: &'static str,
+    flags: DatabaseFlags,
+    create: bool,
+) -> Result<Database, Error> {
+    let mut flags = flags.bits();
+    if create {
+        flags |= MDB_CREATE;
+    }
+    match env.open_db_with_flags(Some(name), flags) {
+        Ok(db) => Ok(db),
+        Err(lmdb::Error::NotFound) => Err(OpenError::MissingTable(name).into()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Opens a sub-database outside the current monerod schema, returns `None` if it
+/// does not exist and `create` is not set
+pub(crate) fn open_optional_subdb(
+    env: &Environment,
+    name: &str,
+    flags: DatabaseFlags,
+    create: bool,
+) -> Result<Option<Database>, Error> {
+    let mut flags = flags.bits();
+    if create {
+        flags |= MDB_CREATE;
+    }
+    match env.open_db_with_flags(Some(name), flags) {
+        Ok(db) => Ok(Some(db)),
+        Err(lmdb::Error::NotFound) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
 }
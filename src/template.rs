@@ -0,0 +1,267 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Block template assembly for mining
+
+use curve25519_dalek::scalar::Scalar;
+use monero::blockdata::transaction::{
+    ExtraField, SubField, TransactionPrefix, TxIn, TxOut, TxOutTarget,
+};
+use monero::consensus::{deserialize, serialize};
+use monero::cryptonote::onetime_key::KeyGenerator;
+use monero::util::address::AddressType;
+use monero::{Address, Block, BlockHeader, Hash, PrivateKey, PublicKey, VarInt};
+use rand_core::OsRng;
+use std::collections::HashSet;
+
+use super::consensus::{
+    COINBASE_BLOB_RESERVED_SIZE, MINED_MONEY_UNLOCK_WINDOW, RANDOMX_MAJOR_VERSION,
+};
+use super::emission::{base_block_reward, calculate_penalized_reward};
+use super::pow::{hashing_blob, seed_height};
+use super::scan::unix_time;
+use super::stats::TimestampSource;
+use super::{Error, MoneroDB};
+
+/// Longest extra nonce that fits in the nonce field of the miner transaction extra
+const MAX_EXTRA_NONCE_SIZE: usize = 255;
+/// First hard fork with RingCT miner transactions, which pay the reward to a single
+/// output
+const MIN_TEMPLATE_VERSION: u8 = 4;
+/// Last hard fork accepting miner outputs without a view tag, later ones need
+/// tagged keys which templates do not build yet
+const MAX_TEMPLATE_VERSION: u8 = 14;
+
+/// A block ready to be mined on top of the chain, created by
+/// [`MoneroDB::build_block_template`]
+///
+#[derive(Debug, Clone)]
+pub struct BlockTemplate {
+    /// The block, with a nonce of zero
+    pub block: Block,
+    /// Height the block will have
+    pub height: u64,
+    /// Difficulty the block's proof of work must meet
+    pub difficulty: u128,
+    /// Hash seeding RandomX for the block, `None` before hard fork 12
+    pub seed_hash: Option<Hash>,
+    /// Amount paid by the miner transaction, the block reward plus the fees
+    pub reward: u64,
+    /// Sum of the fees of the included transactions
+    pub fees: u64,
+    /// Weight of the block including the miner transaction
+    pub weight: u64,
+    /// Median weight the block is checked against
+    pub median_weight: u64,
+    /// Offset of the extra nonce in [`BlockTemplate::template_blob`]
+    pub reserved_offset: usize,
+}
+
+impl BlockTemplate {
+    /// Serializes the whole block, as monerod's `blocktemplate_blob`
+    pub fn template_blob(&self) -> Vec<u8> {
+        serialize(&self.block)
+    }
+
+    /// Builds the blob hashed for the proof of work, as monerod's `blockhashing_blob`
+    pub fn hashing_blob(&self) -> Vec<u8> {
+        hashing_blob(&self.block)
+    }
+}
+
+/// Serializes a RingCT miner transaction paying `amount` to `wallet_address`
+fn miner_tx_blob(
+    wallet_address: &Address,
+    tx_key: &PrivateKey,
+    height: u64,
+    amount: u64,
+    extra_nonce: &[u8],
+) -> Vec<u8> {
+    let key_generator = KeyGenerator::from_random(
+        wallet_address.public_view,
+        wallet_address.public_spend,
+        *tx_key,
+    );
+    let prefix = TransactionPrefix {
+        version: VarInt(2),
+        unlock_time: VarInt(height + MINED_MONEY_UNLOCK_WINDOW),
+        inputs: vec![TxIn::Gen {
+            height: VarInt(height),
+        }],
+        outputs: vec![TxOut {
+            amount: VarInt(amount),
+            target: TxOutTarget::ToKey {
+                key: key_generator.one_time_key(0),
+            },
+        }],
+        // the nonce goes last so it ends the prefix
+        extra: ExtraField(vec![
+            SubField::TxPublicKey(PublicKey::from_private_key(tx_key)),
+            SubField::Nonce(extra_nonce.to_vec()),
+        ]),
+    };
+    let mut blob = serialize(&prefix);
+    // RingCT type null, miner transactions have no signatures
+    blob.push(0);
+    blob
+}
+
+impl MoneroDB {
+    /// Builds a block template on top of the chain paying `wallet_address`, with
+    /// `extra_nonce` in the extra field of the miner transaction for the pool to fill.
+    ///
+    /// Pool transactions are picked by fee per unit of weight, skipping those that
+    /// do not fit under twice the median weight or whose fee does not make up for
+    /// the oversize block penalty they cause. Transactions still in the Dandelion++
+    /// stem phase, marked as not to be relayed or as double spends are never picked.
+    ///
+    /// The block gets the hard fork version scheduled for its height on the
+    /// database's network, or else keeps the version of the chain tip, and votes
+    /// for it.
+    /// Templates can only be built from hard fork 4 up to hard fork 14, as hard
+    /// fork 15 onwards needs view tagged miner outputs, and not for subaddresses.
+    ///
+    pub fn build_block_template(
+        &self,
+        wallet_address: &Address,
+        extra_nonce: &[u8],
+    ) -> Result<BlockTemplate, Error> {
+        if extra_nonce.len() > MAX_EXTRA_NONCE_SIZE {
+            return Err(Error::InvalidBlock(format!(
+                "extra nonce of {} bytes is longer than {}",
+                extra_nonce.len(),
+                MAX_EXTRA_NONCE_SIZE
+            )));
+        }
+        if let AddressType::SubAddress = wallet_address.addr_type {
+            return Err(Error::InvalidBlock(
+                "miner transactions can not pay to a subaddress".to_string(),
+            ));
+        }
+        let height = self.get_blockchain_height()?;
+        if height == 0 {
+            return Err(Error::InvalidBlock(
+                "the chain has no genesis block".to_string(),
+            ));
+        }
+        let tip = height - 1;
+        let hf_version = match self.scheduled_hf_version(height)? {
+            Some(hf_version) => hf_version,
+            None => self.get_hf_version(tip)?,
+        };
+        if hf_version < MIN_TEMPLATE_VERSION {
+            return Err(Error::InvalidBlock(format!(
+                "templates need hard fork {}, the chain is at {}",
+                MIN_TEMPLATE_VERSION, hf_version
+            )));
+        }
+        if hf_version > MAX_TEMPLATE_VERSION {
+            return Err(Error::InvalidBlock(format!(
+                "templates can be built up to hard fork {}, the chain is at {}",
+                MAX_TEMPLATE_VERSION, hf_version
+            )));
+        }
+
+        let median_weight = self.effective_median_weight(height, hf_version)?;
+        let base_reward =
+            base_block_reward(self.get_block_already_generated_coins(tip)?, hf_version);
+        let max_weight = if hf_version >= 5 {
+            2 * median_weight
+        } else {
+            130 * median_weight / 100
+        }
+        .saturating_sub(COINBASE_BLOB_RESERVED_SIZE);
+        let reward_at = |weight: u64| {
+            calculate_penalized_reward(base_reward, weight, median_weight).unwrap_or(0)
+        };
+
+        let mut candidates = self.mining_candidates()?;
+        // highest fee per weight first, compared without dividing
+        candidates.sort_by(|a, b| {
+            (b.fee as u128 * a.weight as u128).cmp(&(a.fee as u128 * b.weight as u128))
+        });
+        let (mut txs_weight, mut fees) = (0, 0);
+        let mut best_reward = reward_at(COINBASE_BLOB_RESERVED_SIZE);
+        let mut key_images = HashSet::new();
+        let mut tx_hashes = Vec::new();
+        for candidate in candidates {
+            let weight = txs_weight + candidate.weight;
+            if weight > max_weight {
+                continue;
+            }
+            let reward = reward_at(weight + COINBASE_BLOB_RESERVED_SIZE) + fees + candidate.fee;
+            if reward < best_reward
+                || candidate
+                    .key_images
+                    .iter()
+                    .any(|key_image| key_images.contains(key_image))
+            {
+                continue;
+            }
+            key_images.extend(candidate.key_images);
+            tx_hashes.push(candidate.tx_hash);
+            best_reward = reward;
+            txs_weight = weight;
+            fees += candidate.fee;
+        }
+
+        let tx_key = PrivateKey {
+            scalar: Scalar::random(&mut OsRng),
+        };
+        // the reward only shrinks once the miner transaction weight is known, so the
+        // second blob is never larger than the first
+        let draft = miner_tx_blob(
+            wallet_address,
+            &tx_key,
+            height,
+            base_reward + fees,
+            extra_nonce,
+        );
+        let weight = txs_weight + draft.len() as u64;
+        let reward = reward_at(weight) + fees;
+        let miner_tx_blob = miner_tx_blob(wallet_address, &tx_key, height, reward, extra_nonce);
+
+        let block = Block {
+            header: BlockHeader {
+                major_version: VarInt(hf_version as u64),
+                minor_version: VarInt(hf_version as u64),
+                timestamp: VarInt(
+                    unix_time().max(self.block_time(tip, TimestampSource::MedianTimePast)?),
+                ),
+                prev_id: self.get_block_hash(tip)?,
+                nonce: 0,
+            },
+            miner_tx: deserialize(&miner_tx_blob)?,
+            tx_hashes,
+        };
+        let seed_hash = if hf_version as u64 >= RANDOMX_MAJOR_VERSION {
+            Some(self.get_block_hash(seed_height(height))?)
+        } else {
+            None
+        };
+        // the extra nonce ends the prefix, which is followed by the RingCT type
+        let reserved_offset =
+            serialize(&block.header).len() + miner_tx_blob.len() - 1 - extra_nonce.len();
+
+        Ok(BlockTemplate {
+            block,
+            height,
+            difficulty: self.get_next_difficulty()?,
+            seed_hash,
+            reward,
+            fees,
+            weight: txs_weight + miner_tx_blob.len() as u64,
+            median_weight,
+            reserved_offset,
+        })
+    }
+}
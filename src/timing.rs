@@ -0,0 +1,116 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Opt-in query timing and slow query reporting
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::MoneroDB;
+
+/// Timing totals of the queries made to one sub-database
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct QueryStats {
+    /// Number of queries
+    pub calls: u64,
+    /// Total time spent in queries
+    pub total_time: Duration,
+    /// Longest single query
+    pub max_time: Duration,
+    /// Total size of the values read
+    pub bytes: u64,
+}
+
+/// A query that took longer than the slow query threshold
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlowQuery {
+    /// Name of the sub-database queried
+    pub sub_db: &'static str,
+    /// Time the query took
+    pub duration: Duration,
+    /// Size of the value read
+    pub bytes: usize,
+}
+
+type SlowQueryCallback = Box<dyn Fn(&SlowQuery) + Send + Sync>;
+
+pub(crate) struct QueryTimer {
+    stats: Mutex<HashMap<&'static str, QueryStats>>,
+    slow_threshold: Duration,
+    on_slow_query: SlowQueryCallback,
+}
+
+impl fmt::Debug for QueryTimer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueryTimer")
+            .field("stats", &self.stats)
+            .field("slow_threshold", &self.slow_threshold)
+            .finish()
+    }
+}
+
+impl QueryTimer {
+    pub(crate) fn record(&self, sub_db: &'static str, duration: Duration, bytes: usize) {
+        if let Ok(mut stats) = self.stats.lock() {
+            let stats = stats.entry(sub_db).or_default();
+            stats.calls += 1;
+            stats.total_time += duration;
+            stats.max_time = stats.max_time.max(duration);
+            stats.bytes += bytes as u64;
+        }
+        if duration >= self.slow_threshold {
+            (self.on_slow_query)(&SlowQuery {
+                sub_db,
+                duration,
+                bytes,
+            });
+        }
+    }
+}
+
+impl MoneroDB {
+    /// Starts timing every item lookup made through this handle, `on_slow_query`
+    /// is called for each lookup taking at least `slow_threshold`.
+    ///
+    /// Any statistics already collected are reset.
+    ///
+    pub fn enable_query_timing<F>(&mut self, slow_threshold: Duration, on_slow_query: F)
+    where
+        F: Fn(&SlowQuery) + Send + Sync + 'static,
+    {
+        self.query_timer = Some(QueryTimer {
+            stats: Mutex::new(HashMap::new()),
+            slow_threshold,
+            on_slow_query: Box::new(on_slow_query),
+        });
+    }
+
+    /// Stops timing lookups and discards the collected statistics
+    ///
+    pub fn disable_query_timing(&mut self) {
+        self.query_timer = None;
+    }
+
+    /// Gets the timing statistics per sub-database collected since timing was enabled
+    ///
+    pub fn query_stats(&self) -> HashMap<&'static str, QueryStats> {
+        self.query_timer
+            .as_ref()
+            .and_then(|query_timer| query_timer.stats.lock().ok().map(|stats| stats.clone()))
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,752 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Transaction pool maintenance
+//!
+//! Pool metadata is read from its on disk layout, `txpool_tx_meta_t` in monerod,
+//! which also holds the Dandelion++ state of each transaction.
+
+use lmdb::{Cursor, RwTransaction, Transaction, WriteFlags};
+use monero::blockdata::transaction::TxIn;
+use monero::consensus::encode::Error::ParseFailed;
+use monero::consensus::{deserialize, serialize};
+use monero::cryptonote::hash::Hashable;
+use monero::database::transaction::TxPoolMeta;
+use monero::Hash;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use super::scan::{read_u64, unix_time};
+use super::{Error, MoneroDB, ReadWriteDb, ZERO_KEY};
+
+/// Offsets of the `txpool_tx_meta_t` fields used here
+const META_WEIGHT: usize = 64;
+const META_FEE: usize = 72;
+const META_RECEIVE_TIME: usize = 96;
+const META_LAST_RELAYED_TIME: usize = 104;
+const META_RELAYED: usize = 113;
+const META_DO_NOT_RELAY: usize = 114;
+pub(crate) const META_FLAGS: usize = 115;
+const META_SIZE: usize = 192;
+
+/// Bit of the flags byte set once a conflicting transaction was seen
+pub(crate) const FLAG_DOUBLE_SPEND_SEEN: u8 = 1;
+/// Bits of the flags byte holding the Dandelion++ state
+const FLAG_DANDELION_STEM: u8 = 1 << 3;
+const FLAG_FORWARDING: u8 = 1 << 4;
+
+/// Relay state of a pool transaction
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TxPoolFlags {
+    /// The transaction has been relayed to peers
+    pub relayed: bool,
+    /// The transaction must not be relayed
+    pub do_not_relay: bool,
+    /// A transaction spending the same key images was seen
+    pub double_spend_seen: bool,
+}
+
+/// Dandelion++ relay phase of a pool transaction
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum DandelionState {
+    /// The transaction is only passed to the next stem peer until its embargo
+    /// runs out
+    Stem {
+        /// Unix time after which the transaction is fluffed if it has not been
+        /// seen in the fluff phase
+        embargo_until: u64,
+        /// The transaction was received from a stem peer rather than created
+        /// locally
+        forwarding: bool,
+    },
+    /// The transaction is broadcast to all peers
+    Fluff,
+}
+
+/// A pool transaction spending a key image that has been spent in the chain
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PoolConflict {
+    /// Hash of the pool transaction
+    #[cfg_attr(
+        feature = "serde",
+        serde(serialize_with = "crate::json::serialize_hash")
+    )]
+    pub tx_hash: Hash,
+    /// Key image of the pool transaction that was spent in the chain
+    #[cfg_attr(
+        feature = "serde",
+        serde(serialize_with = "crate::json::serialize_key")
+    )]
+    pub key_image: [u8; 32],
+}
+
+/// The fields of a pool entry needed to decide what to evict
+struct PoolEntry {
+    tx_hash: Hash,
+    weight: u64,
+    fee: u64,
+    receive_time: u64,
+}
+
+/// A pool transaction that may be mined, with the key images it spends
+pub(crate) struct MiningCandidate {
+    pub(crate) tx_hash: Hash,
+    pub(crate) weight: u64,
+    pub(crate) fee: u64,
+    pub(crate) key_images: Vec<[u8; 32]>,
+}
+
+fn short_meta(tx_hash: &Hash) -> Error {
+    Error::Corrupt {
+        db: "txpool_meta",
+        key: tx_hash.as_bytes().to_vec(),
+        source: ParseFailed("pool metadata too short"),
+    }
+}
+
+fn meta_u64(meta: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(meta[at..at + 8].try_into().unwrap())
+}
+
+fn meta_flags(meta: &[u8]) -> TxPoolFlags {
+    TxPoolFlags {
+        relayed: meta[META_RELAYED] != 0,
+        do_not_relay: meta[META_DO_NOT_RELAY] != 0,
+        double_spend_seen: meta[META_FLAGS] & FLAG_DOUBLE_SPEND_SEEN != 0,
+    }
+}
+
+fn meta_dandelion(meta: &[u8]) -> DandelionState {
+    if meta[META_FLAGS] & FLAG_DANDELION_STEM != 0 {
+        DandelionState::Stem {
+            embargo_until: meta_u64(meta, META_LAST_RELAYED_TIME),
+            forwarding: meta[META_FLAGS] & FLAG_FORWARDING != 0,
+        }
+    } else {
+        DandelionState::Fluff
+    }
+}
+
+/// Adds `tx` to the pool within `transaction`, failing with
+/// [`Error::InvalidTransaction`] if it is already in the pool or the chain, or if
+/// one of its key images is spent in the chain or by a pool transaction.
+///
+/// `pool_key_images` holds the key images spent by the pool as of `transaction`,
+/// see [`read_pool_key_images`], and gets those of `tx` once it is added. Pool
+/// transactions conflicting with `tx` are flagged as double spend seen even though
+/// `tx` is rejected, so `transaction` should be committed after such an error.
+///
+pub(crate) fn insert_pool_tx(
+    db: &MoneroDB,
+    transaction: &mut RwTransaction,
+    tx: &monero::Transaction,
+    tx_meta: &TxPoolMeta,
+    pool_key_images: &mut HashMap<[u8; 32], Hash>,
+) -> Result<(), Error> {
+    let tx_hash = tx.hash();
+    let key_images = check_pool_insert(db, transaction, tx, &tx_hash)?;
+    let mut conflicts = Vec::new();
+    for key_image in &key_images {
+        if let Some(conflict) = pool_key_images.get(key_image) {
+            if !conflicts.contains(conflict) {
+                conflicts.push(*conflict);
+            }
+        }
+    }
+    if !conflicts.is_empty() {
+        for conflict in &conflicts {
+            update_pool_meta(db, transaction, conflict, |meta| {
+                meta[META_FLAGS] |= FLAG_DOUBLE_SPEND_SEEN
+            })?;
+        }
+        return Err(Error::InvalidTransaction(format!(
+            "key images are already spent by pool transaction {}",
+            hex::encode(conflicts[0].as_bytes())
+        )));
+    }
+
+    transaction.put(
+        db.sub_dbs.txpool_meta,
+        &tx_hash.as_bytes(),
+        &serialize(tx_meta),
+        WriteFlags::NO_OVERWRITE,
+    )?;
+    transaction.put(
+        db.sub_dbs.txpool_blob,
+        &tx_hash.as_bytes(),
+        &serialize(tx),
+        WriteFlags::NO_OVERWRITE,
+    )?;
+    pool_key_images.extend(key_images.into_iter().map(|key_image| (key_image, tx_hash)));
+    Ok(())
+}
+
+/// Checks that `tx` is not in the pool or the chain and that its key images are
+/// not spent in the chain, returning its key images
+///
+fn check_pool_insert(
+    db: &MoneroDB,
+    transaction: &RwTransaction,
+    tx: &monero::Transaction,
+    tx_hash: &Hash,
+) -> Result<HashSet<[u8; 32]>, Error> {
+    match transaction.get(db.sub_dbs.txpool_meta, &tx_hash.as_bytes()) {
+        Ok(_) => {
+            return Err(Error::InvalidTransaction(format!(
+                "transaction {} is already in the pool",
+                hex::encode(tx_hash.as_bytes())
+            )));
+        }
+        Err(lmdb::Error::NotFound) => {}
+        Err(e) => return Err(e.into()),
+    }
+    if has_dup(transaction, db.sub_dbs.tx_indices, tx_hash.as_bytes())? {
+        return Err(Error::InvalidTransaction(format!(
+            "transaction {} is already in the chain",
+            hex::encode(tx_hash.as_bytes())
+        )));
+    }
+
+    let mut key_images = HashSet::new();
+    for input in &tx.prefix.inputs {
+        if let TxIn::ToKey { k_image, .. } = input {
+            let key_image = k_image.image.as_bytes();
+            if !key_images.insert(*key_image) {
+                return Err(Error::InvalidTransaction(format!(
+                    "key image {} is spent twice",
+                    hex::encode(key_image)
+                )));
+            }
+            if has_dup(transaction, db.sub_dbs.spent_keys, key_image)? {
+                return Err(Error::InvalidTransaction(format!(
+                    "key image {} is already spent",
+                    hex::encode(key_image)
+                )));
+            }
+        }
+    }
+    Ok(key_images)
+}
+
+/// Maps the key images spent by pool transactions to the hash of the spending
+/// transaction
+///
+pub(crate) fn read_pool_key_images(
+    db: &MoneroDB,
+    transaction: &impl Transaction,
+) -> Result<HashMap<[u8; 32], Hash>, Error> {
+    let mut key_images = HashMap::new();
+    let curser = transaction.open_ro_cursor(db.sub_dbs.txpool_blob)?;
+    let mut op = 0;
+    loop {
+        let (key, blob) = match curser.get(None, None, op) {
+            Ok((Some(key), blob)) => (key, blob),
+            Ok((None, _)) | Err(lmdb::Error::NotFound) => break,
+            Err(e) => return Err(e.into()),
+        };
+        op = 8;
+        let pool_tx: monero::Transaction = deserialize(blob)?;
+        for input in &pool_tx.prefix.inputs {
+            if let TxIn::ToKey { k_image, .. } = input {
+                key_images.insert(*k_image.image.as_bytes(), Hash::from_slice(key));
+            }
+        }
+    }
+    Ok(key_images)
+}
+
+/// Returns if the dup sorted sub-database `sub_db` holds `data` under the zero key
+fn has_dup(
+    transaction: &impl Transaction,
+    sub_db: lmdb::Database,
+    data: &[u8],
+) -> Result<bool, Error> {
+    let curser = transaction.open_ro_cursor(sub_db)?;
+    match curser.get(Some(&ZERO_KEY), Some(data), 2) {
+        Ok(_) => Ok(true),
+        Err(lmdb::Error::NotFound) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Rewrites the raw metadata of a pool transaction within `transaction`
+///
+pub(crate) fn update_pool_meta(
+    db: &MoneroDB,
+    transaction: &mut RwTransaction,
+    tx_hash: &Hash,
+    update: impl FnOnce(&mut [u8]),
+) -> Result<(), Error> {
+    let mut meta = transaction
+        .get(db.sub_dbs.txpool_meta, &tx_hash.as_bytes())
+        .map_err(|e| Error::from_lookup(e, "txpool_meta", tx_hash.as_bytes()))?
+        .to_vec();
+    if meta.len() < META_SIZE {
+        return Err(short_meta(tx_hash));
+    }
+    update(&mut meta);
+    transaction.put(
+        db.sub_dbs.txpool_meta,
+        &tx_hash.as_bytes(),
+        &meta,
+        WriteFlags::empty(),
+    )?;
+    Ok(())
+}
+
+/// Removes a transaction from both pool tables within `transaction`
+///
+fn delete_pool_tx(
+    db: &MoneroDB,
+    transaction: &mut RwTransaction,
+    tx_hash: &Hash,
+) -> Result<(), Error> {
+    for sub_db in [db.sub_dbs.txpool_meta, db.sub_dbs.txpool_blob] {
+        match transaction.del(sub_db, &tx_hash.as_bytes(), None) {
+            Ok(()) | Err(lmdb::Error::NotFound) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+impl MoneroDB {
+    /// Gets the hashes of every pool transaction
+    ///
+    pub fn get_txpool_hashes(&self) -> Result<Vec<Hash>, Error> {
+        let transaction = self.begin_read()?;
+        let curser = transaction.open_ro_cursor(self.sub_dbs.txpool_meta)?;
+        let mut tx_hashes = Vec::new();
+        let mut op = 0;
+        loop {
+            match curser.get(None, None, op) {
+                Ok((Some(key), _)) if key.len() == 32 => tx_hashes.push(Hash::from_slice(key)),
+                Ok((Some(_), _)) => {}
+                Ok((None, _)) | Err(lmdb::Error::NotFound) => break,
+                Err(e) => return Err(e.into()),
+            }
+            op = 8;
+        }
+        Ok(tx_hashes)
+    }
+
+    /// Gets the relay flags of a pool transaction
+    ///
+    pub fn get_txpool_flags(&self, tx_hash: &Hash) -> Result<TxPoolFlags, Error> {
+        self.read_pool_meta(tx_hash, meta_flags)
+    }
+
+    /// Gets the Dandelion++ state of a pool transaction
+    ///
+    pub fn get_txpool_dandelion(&self, tx_hash: &Hash) -> Result<DandelionState, Error> {
+        self.read_pool_meta(tx_hash, meta_dandelion)
+    }
+
+    /// Gets the hashes of the stem phase transactions whose embargo ran out at `now`,
+    /// these have to be fluffed.
+    ///
+    pub fn expired_txpool_embargoes(&self, now: u64) -> Result<Vec<Hash>, Error> {
+        let transaction = self.begin_read()?;
+        let curser = transaction.open_ro_cursor(self.sub_dbs.txpool_meta)?;
+        let mut expired = Vec::new();
+        let mut op = 0;
+        loop {
+            let (key, meta) = match curser.get(None, None, op) {
+                Ok((Some(key), meta)) => (key, meta),
+                Ok((None, _)) | Err(lmdb::Error::NotFound) => break,
+                Err(e) => return Err(e.into()),
+            };
+            op = 8;
+            if key.len() == 32
+                && meta.len() >= META_SIZE
+                && meta[META_FLAGS] & FLAG_DANDELION_STEM != 0
+                && meta_u64(meta, META_LAST_RELAYED_TIME) <= now
+            {
+                expired.push(Hash::from_slice(key));
+            }
+        }
+        Ok(expired)
+    }
+
+    /// Gets the key images spent by pool transactions, mapped to the hash of the
+    /// spending transaction.
+    ///
+    /// Every pool transaction is decoded, callers checking many transactions should
+    /// build the map once.
+    ///
+    pub fn txpool_key_images(&self) -> Result<HashMap<[u8; 32], Hash>, Error> {
+        let transaction = self.begin_read()?;
+        read_pool_key_images(self, &transaction)
+    }
+
+    /// Finds the pool transactions that can no longer be mined because one of their
+    /// key images has been spent in the chain, these should be removed from the pool.
+    ///
+    pub fn find_pool_conflicts(&self) -> Result<Vec<PoolConflict>, Error> {
+        let transaction = self.begin_read()?;
+        let mut conflicts = Vec::new();
+        for (key_image, tx_hash) in read_pool_key_images(self, &transaction)? {
+            if conflicts
+                .iter()
+                .any(|conflict: &PoolConflict| conflict.tx_hash == tx_hash)
+            {
+                continue;
+            }
+            if has_dup(&transaction, self.sub_dbs.spent_keys, &key_image)? {
+                conflicts.push(PoolConflict { tx_hash, key_image });
+            }
+        }
+        Ok(conflicts)
+    }
+
+    /// Gets the pool transactions a miner may include: relayable, past the
+    /// Dandelion++ stem phase, not flagged as double spends and with no key image
+    /// spent in the chain
+    pub(crate) fn mining_candidates(&self) -> Result<Vec<MiningCandidate>, Error> {
+        let transaction = self.begin_read()?;
+        let curser = transaction.open_ro_cursor(self.sub_dbs.txpool_meta)?;
+        let mut candidates = Vec::new();
+        let mut op = 0;
+        loop {
+            let (key, meta) = match curser.get(None, None, op) {
+                Ok((Some(key), meta)) => (key, meta),
+                Ok((None, _)) | Err(lmdb::Error::NotFound) => break,
+                Err(e) => return Err(e.into()),
+            };
+            op = 8;
+            if key.len() != 32
+                || meta.len() < META_SIZE
+                || meta[META_DO_NOT_RELAY] != 0
+                || meta[META_FLAGS] & (FLAG_DOUBLE_SPEND_SEEN | FLAG_DANDELION_STEM) != 0
+            {
+                continue;
+            }
+            let blob = match transaction.get(self.sub_dbs.txpool_blob, &key) {
+                Ok(blob) => blob,
+                Err(lmdb::Error::NotFound) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let pool_tx: monero::Transaction = deserialize(blob)?;
+            let mut key_images = Vec::new();
+            let mut spent = false;
+            for input in &pool_tx.prefix.inputs {
+                if let TxIn::ToKey { k_image, .. } = input {
+                    let key_image = *k_image.image.as_bytes();
+                    spent |= has_dup(&transaction, self.sub_dbs.spent_keys, &key_image)?;
+                    key_images.push(key_image);
+                }
+            }
+            if !spent {
+                candidates.push(MiningCandidate {
+                    tx_hash: Hash::from_slice(key),
+                    weight: meta_u64(meta, META_WEIGHT),
+                    fee: meta_u64(meta, META_FEE),
+                    key_images,
+                });
+            }
+        }
+        Ok(candidates)
+    }
+
+    /// Passes the raw metadata of a pool transaction to `f`
+    fn read_pool_meta<T>(&self, tx_hash: &Hash, f: impl FnOnce(&[u8]) -> T) -> Result<T, Error> {
+        let transaction = self.begin_read()?;
+        let meta = transaction
+            .get(self.sub_dbs.txpool_meta, &tx_hash.as_bytes())
+            .map_err(|e| Error::from_lookup(e, "txpool_meta", tx_hash.as_bytes()))?;
+        if meta.len() < META_SIZE {
+            return Err(short_meta(tx_hash));
+        }
+        Ok(f(meta))
+    }
+
+    /// Writes every pool transaction to `writer`, returning the number written.
+    ///
+    /// The format is the number of transactions as a little endian u64 followed by,
+    /// for each transaction, its raw metadata and blob each prefixed with their
+    /// length as a little endian u64.
+    ///
+    pub fn export_txpool<W: Write>(&self, writer: &mut W) -> Result<u64, Error> {
+        let transaction = self.begin_read()?;
+        let count = transaction.stat(self.sub_dbs.txpool_meta)?.entries() as u64;
+        writer.write_all(&count.to_le_bytes())?;
+
+        let curser = transaction.open_ro_cursor(self.sub_dbs.txpool_meta)?;
+        let mut written = 0;
+        let mut op = 0;
+        loop {
+            let (key, meta) = match curser.get(None, None, op) {
+                Ok((Some(key), meta)) => (key, meta),
+                Ok((None, _)) | Err(lmdb::Error::NotFound) => break,
+                Err(e) => return Err(e.into()),
+            };
+            op = 8;
+            let blob = transaction
+                .get(self.sub_dbs.txpool_blob, key)
+                .map_err(|e| Error::from_lookup(e, "txpool_blob", key))?;
+            for item in [meta, blob] {
+                writer.write_all(&(item.len() as u64).to_le_bytes())?;
+                writer.write_all(item)?;
+            }
+            written += 1;
+        }
+        Ok(written)
+    }
+}
+
+impl ReadWriteDb {
+    /// Sets the relay flags of a pool transaction, marking a fluff phase transaction
+    /// relayed also updates its last relayed time.
+    ///
+    pub fn set_txpool_flags(&self, tx_hash: &Hash, flags: TxPoolFlags) -> Result<(), Error> {
+        let mut transaction = self.env.begin_rw_txn()?;
+        update_pool_meta(self, &mut transaction, tx_hash, |meta| {
+            // stem transactions keep their embargo in the last relayed time
+            if flags.relayed && meta[META_FLAGS] & FLAG_DANDELION_STEM == 0 {
+                meta[META_LAST_RELAYED_TIME..META_LAST_RELAYED_TIME + 8]
+                    .copy_from_slice(&unix_time().to_le_bytes());
+            }
+            meta[META_RELAYED] = flags.relayed as u8;
+            meta[META_DO_NOT_RELAY] = flags.do_not_relay as u8;
+            if flags.double_spend_seen {
+                meta[META_FLAGS] |= FLAG_DOUBLE_SPEND_SEEN;
+            } else {
+                meta[META_FLAGS] &= !FLAG_DOUBLE_SPEND_SEEN;
+            }
+        })?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Sets the Dandelion++ state of a pool transaction.
+    ///
+    /// As in monerod the embargo of a stem transaction is stored in its last relayed
+    /// time, moving a transaction to the fluff phase resets it to 0 so it is relayed
+    /// again.
+    ///
+    pub fn set_txpool_dandelion(&self, tx_hash: &Hash, state: DandelionState) -> Result<(), Error> {
+        let mut transaction = self.env.begin_rw_txn()?;
+        update_pool_meta(self, &mut transaction, tx_hash, |meta| {
+            let (last_relayed_time, flags) = match state {
+                DandelionState::Stem {
+                    embargo_until,
+                    forwarding,
+                } => (
+                    embargo_until,
+                    FLAG_DANDELION_STEM | if forwarding { FLAG_FORWARDING } else { 0 },
+                ),
+                DandelionState::Fluff => (0, 0),
+            };
+            meta[META_LAST_RELAYED_TIME..META_LAST_RELAYED_TIME + 8]
+                .copy_from_slice(&last_relayed_time.to_le_bytes());
+            meta[META_FLAGS] &= !(FLAG_DANDELION_STEM | FLAG_FORWARDING);
+            meta[META_FLAGS] |= flags;
+        })?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Removes a transaction from the pool
+    ///
+    pub fn remove_txpool_tx(&self, tx_hash: &Hash) -> Result<(), Error> {
+        let mut transaction = self.env.begin_rw_txn()?;
+        delete_pool_tx(self, &mut transaction, tx_hash)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Removes pool transactions received more than `max_age` ago, then evicts the
+    /// transactions paying the lowest fee per unit of weight until the total weight
+    /// of the pool is at most `max_bytes`. Returns the hashes of the removed
+    /// transactions.
+    ///
+    pub fn prune_txpool(&self, max_age: Duration, max_bytes: u64) -> Result<Vec<Hash>, Error> {
+        let mut transaction = self.env.begin_rw_txn()?;
+
+        let mut entries = Vec::new();
+        {
+            let curser = transaction.open_ro_cursor(self.sub_dbs.txpool_meta)?;
+            let mut op = 0;
+            loop {
+                let (key, meta) = match curser.get(None, None, op) {
+                    Ok((Some(key), meta)) => (key, meta),
+                    Ok((None, _)) | Err(lmdb::Error::NotFound) => break,
+                    Err(e) => return Err(e.into()),
+                };
+                op = 8;
+                if key.len() != 32 || meta.len() < META_SIZE {
+                    continue;
+                }
+                entries.push(PoolEntry {
+                    tx_hash: Hash::from_slice(key),
+                    weight: meta_u64(meta, META_WEIGHT),
+                    fee: meta_u64(meta, META_FEE),
+                    receive_time: meta_u64(meta, META_RECEIVE_TIME),
+                });
+            }
+        }
+
+        let cutoff = unix_time().saturating_sub(max_age.as_secs());
+        let (expired, mut kept): (Vec<_>, Vec<_>) = entries
+            .into_iter()
+            .partition(|entry| entry.receive_time < cutoff);
+        let mut removed: Vec<Hash> = expired.into_iter().map(|entry| entry.tx_hash).collect();
+
+        // highest fee per weight first, compared without dividing
+        kept.sort_by(|a, b| {
+            (b.fee as u128 * a.weight as u128).cmp(&(a.fee as u128 * b.weight as u128))
+        });
+        let mut pool_bytes: u64 = kept.iter().map(|entry| entry.weight).sum();
+        while pool_bytes > max_bytes {
+            match kept.pop() {
+                Some(entry) => {
+                    pool_bytes -= entry.weight;
+                    removed.push(entry.tx_hash);
+                }
+                None => break,
+            }
+        }
+
+        for tx_hash in &removed {
+            delete_pool_tx(self, &mut transaction, tx_hash)?;
+        }
+        transaction.commit()?;
+        Ok(removed)
+    }
+
+    /// Adds the pool transactions written with [`MoneroDB::export_txpool`] to the
+    /// pool in a single write transaction, returning the number added.
+    /// Transactions rejected by [`ReadWriteDb::add_txpool_tx`], for example
+    /// because they are already in the pool or were mined since the export, are
+    /// skipped.
+    ///
+    pub fn import_txpool<R: Read>(&self, reader: &mut R) -> Result<u64, Error> {
+        let count = read_u64(reader)?;
+        let mut transaction = self.env.begin_rw_txn()?;
+        // decoded once for the batch, then kept up to date as transactions are added
+        let mut pool_key_images = read_pool_key_images(self, &transaction)?;
+        let mut added = Vec::new();
+        for _ in 0..count {
+            let tx_meta: TxPoolMeta = deserialize(&read_item(reader)?)?;
+            let tx: monero::Transaction = deserialize(&read_item(reader)?)?;
+            match insert_pool_tx(self, &mut transaction, &tx, &tx_meta, &mut pool_key_images) {
+                Ok(()) => added.push((tx, tx_meta)),
+                Err(Error::InvalidTransaction(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        transaction.commit()?;
+        for (tx, tx_meta) in &added {
+            self.notify_txpool_add(tx, tx_meta);
+        }
+        Ok(added.len() as u64)
+    }
+}
+
+/// Reads an item prefixed with its length as a little endian u64, as written by
+/// [`MoneroDB::export_txpool`]
+pub(crate) fn read_item<R: Read>(reader: &mut R) -> Result<Vec<u8>, Error> {
+    let len = read_u64(reader)?;
+    let mut item = Vec::new();
+    reader.by_ref().take(len).read_to_end(&mut item)?;
+    if item.len() as u64 != len {
+        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(item)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serializes a `txpool_tx_meta_t` field by field, as monerod lays it out
+    fn meta(relayed: u8, do_not_relay: u8, flags: u8) -> Vec<u8> {
+        let mut meta = Vec::with_capacity(META_SIZE);
+        // max_used_block_id and last_failed_id
+        meta.extend([0x11; 32]);
+        meta.extend([0x22; 32]);
+        // weight, fee, max_used_block_height, last_failed_height, receive_time and
+        // last_relayed_time
+        for value in [
+            1500,
+            30_000_000,
+            3_000_000,
+            2_999_999,
+            1_700_000_000,
+            1_700_000_100,
+        ] {
+            meta.extend(u64::to_le_bytes(value));
+        }
+        // kept_by_block, then the bit fields, then the padding
+        meta.extend([1, relayed, do_not_relay, flags]);
+        meta.resize(META_SIZE, 0);
+        meta
+    }
+
+    #[test]
+    fn meta_offsets() {
+        let meta = meta(1, 0, 0);
+        let decoded: TxPoolMeta = deserialize(&meta).unwrap();
+        assert_eq!(meta_u64(&meta, META_WEIGHT), decoded.weight);
+        assert_eq!(meta_u64(&meta, META_FEE), decoded.fee);
+        assert_eq!(meta_u64(&meta, META_RECEIVE_TIME), decoded.receive_time);
+        assert_eq!(
+            meta_u64(&meta, META_LAST_RELAYED_TIME),
+            decoded.last_relayed_time
+        );
+        assert_eq!(decoded.weight, 1500);
+        assert_eq!(decoded.last_relayed_time, 1_700_000_100);
+    }
+
+    #[test]
+    fn meta_flag_bits() {
+        // double_spend_seen is bit 0, dandelionpp_stem bit 3 and is_forwarding bit 4
+        assert_eq!(
+            meta_flags(&meta(1, 0, 0b1)),
+            TxPoolFlags {
+                relayed: true,
+                do_not_relay: false,
+                double_spend_seen: true,
+            }
+        );
+        assert_eq!(
+            meta_flags(&meta(0, 1, 0b11000)),
+            TxPoolFlags {
+                relayed: false,
+                do_not_relay: true,
+                double_spend_seen: false,
+            }
+        );
+        assert_eq!(meta_dandelion(&meta(0, 0, 0b1)), DandelionState::Fluff);
+        assert_eq!(
+            meta_dandelion(&meta(0, 0, 0b11000)),
+            DandelionState::Stem {
+                embargo_until: 1_700_000_100,
+                forwarding: true,
+            }
+        );
+        assert_eq!(
+            meta_dandelion(&meta(0, 0, 0b1000)),
+            DandelionState::Stem {
+                embargo_until: 1_700_000_100,
+                forwarding: false,
+            }
+        );
+    }
+}
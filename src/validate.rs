@@ -0,0 +1,153 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Read-only validation of a whole database
+//!
+//! Combines the audits into a single pass redoing the checks monerod makes when
+//! importing blocks, as far as this crate can: ring signatures, CLSAGs and range
+//! proofs are not verified. A signatures depth is split out as a follow-up, it
+//! needs verification code this crate and its dependencies do not have yet.
+
+use std::ops::Range;
+
+use super::audit::{BlockHashMismatch, TxRootAudit};
+use super::difficulty::DifficultyMismatch;
+use super::{Error, MoneroDB};
+
+/// How much of each block is validated, each depth includes the checks of the
+/// ones before it.
+///
+/// There is no depth verifying transaction signatures yet, see the module
+/// documentation.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ValidationDepth {
+    /// Block hashes, their index and the cumulative difficulties
+    Hashes,
+    /// The Merkle roots of the stored transactions
+    MerkleRoots,
+    /// The RandomX proof of work of blocks from hard fork 12
+    #[cfg(feature = "randomx")]
+    ProofOfWork,
+}
+
+/// A stage of [`MoneroDB::validate_chain`], passed to the progress callback
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationStage {
+    /// Recomputing block hashes
+    BlockHashes,
+    /// Recomputing block difficulties, done in one step
+    Difficulty,
+    /// Recomputing transaction Merkle roots
+    MerkleRoots,
+    /// Checking proofs of work
+    #[cfg(feature = "randomx")]
+    ProofOfWork,
+}
+
+/// Options of [`MoneroDB::validate_chain`]
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationOptions {
+    /// Heights of the blocks to validate, clamped to the chain
+    pub range: Range<u64>,
+    /// Checks made on each block
+    pub depth: ValidationDepth,
+}
+
+impl Default for ValidationOptions {
+    fn default() -> Self {
+        ValidationOptions {
+            range: 0..u64::MAX,
+            depth: ValidationDepth::MerkleRoots,
+        }
+    }
+}
+
+/// Results of [`MoneroDB::validate_chain`]
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ChainValidation {
+    /// Number of blocks validated
+    pub blocks: u64,
+    /// Blocks whose hash does not match
+    pub block_hashes: Vec<BlockHashMismatch>,
+    /// Blocks whose difficulty does not follow from the blocks before them
+    pub difficulty: Vec<DifficultyMismatch>,
+    /// Results of the Merkle root audit, if it was part of the validation
+    pub tx_roots: Option<TxRootAudit>,
+    /// Heights of the blocks whose proof of work does not meet their difficulty
+    pub invalid_pow: Vec<u64>,
+    /// Number of blocks whose proof of work could not be checked, blocks before
+    /// hard fork 12 or every block if proofs of work were not part of the validation
+    pub unchecked_pow: u64,
+}
+
+impl ChainValidation {
+    /// Returns if no check failed
+    ///
+    pub fn is_valid(&self) -> bool {
+        self.block_hashes.is_empty()
+            && self.difficulty.is_empty()
+            && self
+                .tx_roots
+                .iter()
+                .all(|tx_roots| tx_roots.mismatches.is_empty())
+            && self.invalid_pow.is_empty()
+    }
+}
+
+impl MoneroDB {
+    /// Validates the blocks in `options.range` to `options.depth`, calling
+    /// `progress` with the current stage and the height validated up to.
+    ///
+    /// This does not stop at the first failure, every failed check is reported.
+    ///
+    pub fn validate_chain<F: FnMut(ValidationStage, u64)>(
+        &self,
+        options: &ValidationOptions,
+        mut progress: F,
+    ) -> Result<ChainValidation, Error> {
+        let range = options.range.start..options.range.end.min(self.get_blockchain_height()?);
+        let mut validation = ChainValidation {
+            blocks: range.end.saturating_sub(range.start),
+            ..ChainValidation::default()
+        };
+
+        validation.block_hashes = self.audit_block_hashes(range.clone(), |height| {
+            progress(ValidationStage::BlockHashes, height)
+        })?;
+        validation.difficulty = self.verify_cumulative_difficulty(range.clone())?;
+        progress(ValidationStage::Difficulty, range.end);
+
+        if options.depth >= ValidationDepth::MerkleRoots {
+            validation.tx_roots = Some(self.audit_tx_roots(range.clone(), |height| {
+                progress(ValidationStage::MerkleRoots, height)
+            })?);
+        }
+
+        #[cfg(feature = "randomx")]
+        if options.depth >= ValidationDepth::ProofOfWork {
+            let (invalid, skipped) = self.find_invalid_pow(range.clone(), |height| {
+                progress(ValidationStage::ProofOfWork, height + 1)
+            })?;
+            validation.invalid_pow = invalid;
+            validation.unchecked_pow = skipped;
+            return Ok(validation);
+        }
+
+        validation.unchecked_pow = validation.blocks;
+        Ok(validation)
+    }
+}
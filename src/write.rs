@@ -0,0 +1,724 @@
+// Copyright (c) 2022 Boog900
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+//! Adding blocks to the main chain
+//!
+//! Values are written in the same packed little endian layout monerod uses.
+
+use curve25519_dalek::constants::ED25519_BASEPOINT_POINT;
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use curve25519_dalek::scalar::Scalar;
+use lmdb::{Cursor, Database, DatabaseFlags, RwTransaction, Transaction, WriteFlags};
+use monero::blockdata::transaction::{TxIn, TxOutTarget};
+use monero::consensus::{deserialize, serialize};
+use monero::cryptonote::hash::{keccak_256, Hashable};
+use monero::database::block::BlockInfo;
+use monero::database::transaction::TransactionPruned;
+use monero::util::ringct::RctSig;
+use monero::{Block, Hash};
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+
+use super::analysis::tx_fee;
+use super::block_info::{short_entry, BlockMetadata};
+use super::consensus::genesis_nonce;
+use super::orphans::{archive_orphan, ORPHAN_BLOCKS};
+use super::payment_id::{index_tx, open_payment_ids, unindex_tx};
+use super::scan::unix_time;
+use super::sub_db::open_optional_subdb;
+use super::{Error, MoneroDB, OpenOptions, ZERO_KEY};
+
+/// The second generator H used in Pedersen commitments
+const H: [u8; 32] = [
+    0x8b, 0x65, 0x59, 0x70, 0x15, 0x37, 0x99, 0xaf, 0x2a, 0xea, 0xdc, 0x9f, 0xf1, 0xad, 0xd0, 0xea,
+    0x6c, 0x72, 0x51, 0xd5, 0x41, 0x54, 0xcf, 0xa9, 0x2c, 0x17, 0x3a, 0x0d, 0xd3, 0x9c, 0x1f, 0x94,
+];
+
+/// Gets the commitment to `amount` with a blinding factor of 1, used for the
+/// outputs of RCT miner transactions
+pub(crate) fn zero_commit(amount: u64) -> [u8; 32] {
+    let h = CompressedEdwardsY(H)
+        .decompress()
+        .expect("H is a valid point");
+    (ED25519_BASEPOINT_POINT + h * Scalar::from(amount))
+        .compress()
+        .to_bytes()
+}
+
+/// Gets the amount index the next output with `amount` will get
+fn next_amount_index(
+    transaction: &RwTransaction,
+    db: &MoneroDB,
+    amount: u64,
+) -> Result<u64, Error> {
+    let curser = transaction.open_ro_cursor(db.sub_dbs.output_amounts)?;
+    match curser.get(Some(&amount.to_le_bytes()), None, 15) {
+        Ok(_) => {}
+        Err(lmdb::Error::NotFound) => return Ok(0),
+        Err(e) => return Err(e.into()),
+    }
+    let (_, last) = curser.get(None, None, 7)?;
+    Ok(u64::from_le_bytes(last[..8].try_into().unwrap()) + 1)
+}
+
+/// Last hard fork whose blocks can be written, later ones have view tagged
+/// outputs which are not indexed yet
+const MAX_WRITE_VERSION: u64 = 14;
+
+/// A block and the data needed to add it with [`ReadWriteDb::add_blocks`]
+///
+#[derive(Debug, Clone)]
+pub struct NewBlock {
+    /// The block
+    pub block: Block,
+    /// Transactions of the block in the order of its transaction hashes
+    pub txs: Vec<monero::Transaction>,
+    /// Weight of the block
+    pub block_weight: u64,
+    /// Long term weight of the block
+    pub long_term_block_weight: u64,
+    /// Difficulty of the block
+    pub difficulty: u128,
+}
+
+/// A [`MoneroDB`] opened for writing, see [`OpenOptions::open_rw`] and
+/// [`MoneroDB::create`].
+///
+/// Methods that modify the database only exist on this handle, so writing through
+/// a read only [`MoneroDB`] does not compile. It dereferences to [`MoneroDB`] for
+/// everything else.
+///
+/// [`OpenOptions::open_rw`]: crate::OpenOptions::open_rw
+pub struct ReadWriteDb {
+    pub(crate) db: MoneroDB,
+}
+
+impl Deref for ReadWriteDb {
+    type Target = MoneroDB;
+
+    fn deref(&self) -> &MoneroDB {
+        &self.db
+    }
+}
+
+impl DerefMut for ReadWriteDb {
+    fn deref_mut(&mut self) -> &mut MoneroDB {
+        &mut self.db
+    }
+}
+
+impl ReadWriteDb {
+    /// Opens the database in `dir` for writing with the default options
+    ///
+    pub fn open(dir: &Path) -> Result<Self, Error> {
+        OpenOptions::new().open_rw(dir)
+    }
+
+    /// Gives up writing, returning the read handle
+    ///
+    pub fn into_inner(self) -> MoneroDB {
+        self.db
+    }
+
+    /// Adds a block and its transactions to the top of the main chain.
+    ///
+    /// `txs` must be the transactions of the block in the order of its
+    /// transaction hashes. The cumulative difficulty, generated coins and RCT
+    /// output count are derived from the previous block. The map is grown when
+    /// it fills up.
+    ///
+    /// Blocks from hard fork 15 on can not be added, as their view tagged outputs
+    /// are not indexed yet.
+    ///
+    pub fn add_block(
+        &self,
+        block: &Block,
+        txs: &[monero::Transaction],
+        block_weight: u64,
+        long_term_block_weight: u64,
+        difficulty: u128,
+    ) -> Result<(), Error> {
+        let payment_ids = self.payment_id_index()?;
+        let height = self.retry_map_full(|| {
+            let mut transaction = self.env.begin_rw_txn()?;
+            let height = self.write_block(
+                &mut transaction,
+                payment_ids,
+                block,
+                txs,
+                block_weight,
+                long_term_block_weight,
+                difficulty,
+            )?;
+            transaction.commit()?;
+            Ok(height)
+        })?;
+        self.notify_chain_main(height, &[block]);
+        Ok(())
+    }
+
+    /// Adds blocks to the top of the main chain, committing them in batches of
+    /// the configured batch size and growing the map when it fills up.
+    ///
+    /// If the database was opened without syncing, it is synced once all blocks
+    /// have been added. As with [`ReadWriteDb::add_block`], blocks from hard fork
+    /// 15 on can not be added.
+    ///
+    pub fn add_blocks(&self, blocks: &[NewBlock]) -> Result<(), Error> {
+        for batch in blocks.chunks(self.options.batch_size.max(1)) {
+            self.retry_map_full(|| self.write_batch(batch))?;
+        }
+        if self.options.no_sync {
+            self.sync(true)?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the data written so far to disk. Unless `force` is set, the flush
+    /// may be skipped when the database was opened to not sync.
+    ///
+    /// Writers using [`crate::OpenOptions::no_sync`] call this to choose their own
+    /// durability points, for example every 1000 blocks.
+    ///
+    pub fn sync(&self, force: bool) -> Result<(), Error> {
+        Ok(self.env.sync(force)?)
+    }
+
+    fn write_batch(&self, batch: &[NewBlock]) -> Result<(), Error> {
+        let payment_ids = self.payment_id_index()?;
+        let mut transaction = self.env.begin_rw_txn()?;
+        let mut first_height = None;
+        for new_block in batch {
+            let height = self.write_block(
+                &mut transaction,
+                payment_ids,
+                &new_block.block,
+                &new_block.txs,
+                new_block.block_weight,
+                new_block.long_term_block_weight,
+                new_block.difficulty,
+            )?;
+            first_height.get_or_insert(height);
+        }
+        transaction.commit()?;
+
+        if let Some(first_height) = first_height {
+            let blocks: Vec<&Block> = batch.iter().map(|new_block| &new_block.block).collect();
+            self.notify_chain_main(first_height, &blocks);
+        }
+        Ok(())
+    }
+
+    /// Removes the top `count` blocks from the main chain, returning them with their
+    /// transactions, top block first, so they can be added back in reverse order.
+    ///
+    /// The genesis block and blocks at or below the highest checkpoint can not be
+    /// popped. The transactions are not returned to the pool. With
+    /// [`crate::OpenOptions::archive_orphans`] the popped blocks are archived, see
+    /// [`MoneroDB::orphans`].
+    ///
+    pub fn pop_blocks(&self, count: u64) -> Result<Vec<NewBlock>, Error> {
+        let archive = if self.options.archive_orphans {
+            open_optional_subdb(&self.env, ORPHAN_BLOCKS, DatabaseFlags::empty(), true)?
+        } else {
+            None
+        };
+        // the index is kept up to date even if no longer written to
+        let payment_ids = open_payment_ids(&self.env, false)?;
+        let mut transaction = self.env.begin_rw_txn()?;
+        let height = transaction.stat(self.sub_dbs.block_heights)?.entries() as u64;
+        if count >= height {
+            return Err(Error::InvalidBlock(format!(
+                "can not pop {} of {} blocks, the genesis block must stay",
+                count, height
+            )));
+        }
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        self.check_block_modifiable(height - count)?;
+
+        let popped_at = unix_time();
+        let mut popped = Vec::with_capacity(count as usize);
+        for height in (height - count..height).rev() {
+            let new_block = self.remove_block(&mut transaction, height)?;
+            if let Some(payment_ids) = payment_ids {
+                for tx in &new_block.txs {
+                    unindex_tx(&mut transaction, payment_ids, &tx.hash(), &tx.prefix)?;
+                }
+            }
+            if let Some(archive) = archive {
+                archive_orphan(&mut transaction, archive, height, popped_at, &new_block)?;
+            }
+            popped.push(new_block);
+        }
+        transaction.commit()?;
+        self.notify_chain_pop(height - count);
+        Ok(popped)
+    }
+
+    /// Opens the payment ID index, creating it, when added blocks are indexed.
+    /// Tables can't be opened while a write transaction is, so this is done first.
+    fn payment_id_index(&self) -> Result<Option<Database>, Error> {
+        if self.options.index_payment_ids {
+            open_payment_ids(&self.env, true)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn grow_map(&self) -> Result<(), Error> {
+        let map_size = self.env.info()?.map_size();
+        self.env.set_map_size(map_size + self.options.map_growth)?;
+        Ok(())
+    }
+
+    /// Runs `write`, growing the map and running it again each time it fails
+    /// because the map is full. `write` must commit its own transaction.
+    fn retry_map_full<T>(&self, mut write: impl FnMut() -> Result<T, Error>) -> Result<T, Error> {
+        loop {
+            match write() {
+                Err(Error::DatabaseError(lmdb::Error::MapFull)) => self.grow_map()?,
+                result => return result,
+            }
+        }
+    }
+
+    fn write_block(
+        &self,
+        transaction: &mut RwTransaction,
+        payment_ids: Option<Database>,
+        block: &Block,
+        txs: &[monero::Transaction],
+        block_weight: u64,
+        long_term_block_weight: u64,
+        difficulty: u128,
+    ) -> Result<u64, Error> {
+        if block.header.major_version.0 > MAX_WRITE_VERSION {
+            return Err(Error::InvalidBlock(format!(
+                "hard fork {} blocks have view tagged outputs, which can not be written",
+                block.header.major_version.0
+            )));
+        }
+        if txs.len() != block.tx_hashes.len()
+            || txs
+                .iter()
+                .zip(&block.tx_hashes)
+                .any(|(tx, hash)| tx.hash() != *hash)
+        {
+            return Err(Error::InvalidBlock(
+                "transactions do not match the blocks transaction hashes".to_string(),
+            ));
+        }
+
+        let height = transaction.stat(self.sub_dbs.block_heights)?.entries() as u64;
+        let (prev_difficulty, prev_coins, prev_rct_outs) = if height == 0 {
+            (0, 0, 0)
+        } else {
+            let curser = transaction.open_ro_cursor(self.sub_dbs.block_info)?;
+            let (_, value) = curser.get(Some(&ZERO_KEY), Some(&(height - 1).to_le_bytes()), 2)?;
+            let prev: BlockInfo = deserialize(value)?;
+            if prev.block_hash != block.header.prev_id {
+                return Err(Error::InvalidBlock(
+                    "block does not build on the top block".to_string(),
+                ));
+            }
+            (
+                prev.cumulative_difficulty(),
+                prev.total_coins_generated,
+                prev.cumulative_rct_outs,
+            )
+        };
+        let fees: u64 = txs
+            .iter()
+            .map(|tx| tx_fee(&tx.prefix, &tx.rct_signatures))
+            .sum();
+        let miner_outputs: u64 = block
+            .miner_tx
+            .prefix
+            .outputs
+            .iter()
+            .map(|output| output.amount.0)
+            .sum();
+        let coins = prev_coins + miner_outputs.saturating_sub(fees);
+        let cumulative_difficulty = prev_difficulty + difficulty;
+        let rct_outs: u64 = std::iter::once(&block.miner_tx)
+            .chain(txs)
+            .filter(|tx| tx.prefix.version.0 > 1)
+            .map(|tx| tx.prefix.outputs.len() as u64)
+            .sum();
+
+        let block_hash = block.id();
+        let height_key = height.to_le_bytes();
+
+        transaction.put(
+            self.sub_dbs.blocks,
+            &height_key,
+            &serialize(block),
+            WriteFlags::APPEND,
+        )?;
+
+        // mdb_block_info_4
+        let mut block_info = Vec::with_capacity(96);
+        block_info.extend_from_slice(&height_key);
+        block_info.extend_from_slice(&block.header.timestamp.0.to_le_bytes());
+        block_info.extend_from_slice(&coins.to_le_bytes());
+        block_info.extend_from_slice(&block_weight.to_le_bytes());
+        block_info.extend_from_slice(&(cumulative_difficulty as u64).to_le_bytes());
+        block_info.extend_from_slice(&((cumulative_difficulty >> 64) as u64).to_le_bytes());
+        block_info.extend_from_slice(block_hash.as_bytes());
+        block_info.extend_from_slice(&(prev_rct_outs + rct_outs).to_le_bytes());
+        block_info.extend_from_slice(&long_term_block_weight.to_le_bytes());
+        transaction.put(
+            self.sub_dbs.block_info,
+            &ZERO_KEY,
+            &block_info,
+            WriteFlags::APPEND_DUP,
+        )?;
+
+        // blk_height
+        let mut block_height = block_hash.as_bytes().to_vec();
+        block_height.extend_from_slice(&height_key);
+        transaction.put(
+            self.sub_dbs.block_heights,
+            &ZERO_KEY,
+            &block_height,
+            WriteFlags::NO_DUP_DATA,
+        )?;
+
+        self.add_transaction(transaction, &block.miner_tx, height, true)?;
+        for tx in txs {
+            self.add_transaction(transaction, tx, height, false)?;
+            if let Some(payment_ids) = payment_ids {
+                index_tx(transaction, payment_ids, &tx.hash(), &tx.prefix)?;
+            }
+        }
+
+        transaction.put(
+            self.sub_dbs.hf_versions,
+            &height_key,
+            &[block.header.major_version.0 as u8],
+            WriteFlags::APPEND,
+        )?;
+        Ok(height)
+    }
+
+    fn remove_block(
+        &self,
+        transaction: &mut RwTransaction,
+        height: u64,
+    ) -> Result<NewBlock, Error> {
+        let height_key = height.to_le_bytes();
+        let block: Block = deserialize(
+            transaction
+                .get(self.sub_dbs.blocks, &height_key)
+                .map_err(|e| Error::from_lookup(e, "blocks", &height_key))?,
+        )?;
+        let (info, prev_difficulty) = {
+            let curser = transaction.open_ro_cursor(self.sub_dbs.block_info)?;
+            let read_info = |height: u64| -> Result<BlockMetadata, Error> {
+                let key = height.to_le_bytes();
+                let (_, value) = curser.get(Some(&ZERO_KEY), Some(&key), 2)?;
+                BlockMetadata::from_bytes(value).ok_or_else(|| short_entry(&key))
+            };
+            let info = read_info(height)?;
+            (info, read_info(height - 1)?.cumulative_difficulty)
+        };
+
+        let mut txs = Vec::with_capacity(block.tx_hashes.len());
+        for tx_hash in block.tx_hashes.iter().rev() {
+            txs.push(self.remove_transaction(transaction, tx_hash)?);
+        }
+        txs.reverse();
+        self.remove_transaction(transaction, &block.miner_tx.hash())?;
+
+        transaction.del(self.sub_dbs.hf_versions, &height_key, None)?;
+        transaction.del(
+            self.sub_dbs.block_heights,
+            &ZERO_KEY,
+            Some(info.hash.as_bytes()),
+        )?;
+        transaction.del(self.sub_dbs.block_info, &ZERO_KEY, Some(&height_key))?;
+        transaction.del(self.sub_dbs.blocks, &height_key, None)?;
+        Ok(NewBlock {
+            block,
+            txs,
+            block_weight: info.weight,
+            long_term_block_weight: info.long_term_weight,
+            difficulty: info.cumulative_difficulty - prev_difficulty,
+        })
+    }
+
+    /// Removes the transaction from the tables written by `add_transaction`, it must
+    /// be the last transaction added
+    fn remove_transaction(
+        &self,
+        transaction: &mut RwTransaction,
+        tx_hash: &Hash,
+    ) -> Result<monero::Transaction, Error> {
+        let tx_id = {
+            let curser = transaction.open_ro_cursor(self.sub_dbs.tx_indices)?;
+            let (_, tx_index) = curser
+                .get(Some(&ZERO_KEY), Some(tx_hash.as_bytes()), 2)
+                .map_err(|e| Error::from_lookup(e, "tx_indices", tx_hash.as_bytes()))?;
+            u64::from_le_bytes(tx_index[32..40].try_into().unwrap())
+        };
+        let tx_id_key = tx_id.to_le_bytes();
+
+        let mut blob = transaction
+            .get(self.sub_dbs.txs_pruned, &tx_id_key)
+            .map_err(|e| Error::from_lookup(e, "txs_pruned", &tx_id_key))?
+            .to_vec();
+        match transaction.get(self.sub_dbs.txs_prunable, &tx_id_key) {
+            Ok(prunable) => blob.extend_from_slice(prunable),
+            Err(lmdb::Error::NotFound) => {
+                return Err(Error::Pruned {
+                    db: "txs_prunable",
+                    key: tx_id_key.to_vec(),
+                })
+            }
+            Err(e) => return Err(e.into()),
+        }
+        let tx: monero::Transaction = deserialize(&blob)?;
+
+        let amount_indices = transaction
+            .get(self.sub_dbs.tx_outputs, &tx_id_key)
+            .map_err(|e| Error::from_lookup(e, "tx_outputs", &tx_id_key))?
+            .to_vec();
+        for (local_index, output) in tx.prefix.outputs.iter().enumerate().rev() {
+            let output_id = transaction.stat(self.sub_dbs.output_txs)?.entries() as u64 - 1;
+            transaction.del(
+                self.sub_dbs.output_txs,
+                &ZERO_KEY,
+                Some(&output_id.to_le_bytes()),
+            )?;
+            let amount = if tx.prefix.version.0 > 1 {
+                0
+            } else {
+                output.amount.0
+            };
+            let amount_index = amount_indices
+                .get(local_index * 8..local_index * 8 + 8)
+                .ok_or(Error::NotFound {
+                    db: "tx_outputs",
+                    key: tx_id_key.to_vec(),
+                })?;
+            transaction.del(
+                self.sub_dbs.output_amounts,
+                &amount.to_le_bytes(),
+                Some(amount_index),
+            )?;
+        }
+        transaction.del(self.sub_dbs.tx_outputs, &tx_id_key, None)?;
+
+        for input in &tx.prefix.inputs {
+            if let TxIn::ToKey { k_image, .. } = input {
+                transaction.del(
+                    self.sub_dbs.spent_keys,
+                    &ZERO_KEY,
+                    Some(k_image.image.as_bytes()),
+                )?;
+            }
+        }
+
+        transaction.del(self.sub_dbs.txs_prunable_tip, &tx_id_key, None)?;
+        if tx.prefix.version.0 > 1 {
+            transaction.del(self.sub_dbs.txs_prunable_hash, &tx_id_key, None)?;
+        }
+        transaction.del(self.sub_dbs.txs_prunable, &tx_id_key, None)?;
+        transaction.del(self.sub_dbs.txs_pruned, &tx_id_key, None)?;
+        transaction.del(self.sub_dbs.tx_indices, &ZERO_KEY, Some(tx_hash.as_bytes()))?;
+        Ok(tx)
+    }
+
+    fn add_transaction(
+        &self,
+        transaction: &mut RwTransaction,
+        tx: &monero::Transaction,
+        height: u64,
+        miner_tx: bool,
+    ) -> Result<(), Error> {
+        let tx_id = transaction.stat(self.sub_dbs.txs_pruned)?.entries() as u64;
+        let tx_id_key = tx_id.to_le_bytes();
+        let tx_hash = tx.hash();
+
+        // txindex
+        let mut tx_index = tx_hash.as_bytes().to_vec();
+        tx_index.extend_from_slice(&tx_id_key);
+        tx_index.extend_from_slice(&tx.prefix.unlock_time.0.to_le_bytes());
+        tx_index.extend_from_slice(&height.to_le_bytes());
+        transaction.put(
+            self.sub_dbs.tx_indices,
+            &ZERO_KEY,
+            &tx_index,
+            WriteFlags::NO_DUP_DATA,
+        )?;
+
+        let pruned = serialize(&TransactionPruned {
+            prefix: tx.prefix.clone(),
+            rct_signatures: RctSig {
+                sig: tx.rct_signatures.sig.clone(),
+                p: None,
+            },
+        });
+        let full = serialize(tx);
+        let prunable = &full[pruned.len()..];
+        transaction.put(
+            self.sub_dbs.txs_pruned,
+            &tx_id_key,
+            &pruned,
+            WriteFlags::APPEND,
+        )?;
+        transaction.put(
+            self.sub_dbs.txs_prunable,
+            &tx_id_key,
+            prunable,
+            WriteFlags::APPEND,
+        )?;
+        if tx.prefix.version.0 > 1 {
+            transaction.put(
+                self.sub_dbs.txs_prunable_hash,
+                &tx_id_key,
+                &keccak_256(prunable),
+                WriteFlags::APPEND,
+            )?;
+        }
+        transaction.put(
+            self.sub_dbs.txs_prunable_tip,
+            &tx_id_key,
+            &height.to_le_bytes(),
+            WriteFlags::empty(),
+        )?;
+
+        for input in &tx.prefix.inputs {
+            if let TxIn::ToKey { k_image, .. } = input {
+                transaction
+                    .put(
+                        self.sub_dbs.spent_keys,
+                        &ZERO_KEY,
+                        k_image.image.as_bytes(),
+                        WriteFlags::NO_DUP_DATA,
+                    )
+                    .map_err(|e| match e {
+                        lmdb::Error::KeyExist => Error::InvalidBlock(format!(
+                            "key image {} is already spent",
+                            hex::encode(k_image.image.as_bytes())
+                        )),
+                        e => e.into(),
+                    })?;
+            }
+        }
+
+        let mut amount_indices = Vec::with_capacity(tx.prefix.outputs.len() * 8);
+        for (local_index, output) in tx.prefix.outputs.iter().enumerate() {
+            let key = match &output.target {
+                TxOutTarget::ToKey { key } => key,
+                _ => {
+                    return Err(Error::InvalidBlock(format!(
+                        "output {} of {} is not to a key",
+                        local_index,
+                        hex::encode(tx_hash.as_bytes())
+                    )))
+                }
+            };
+            let output_id = transaction.stat(self.sub_dbs.output_txs)?.entries() as u64;
+
+            // outtx
+            let mut out_tx = output_id.to_le_bytes().to_vec();
+            out_tx.extend_from_slice(tx_hash.as_bytes());
+            out_tx.extend_from_slice(&(local_index as u64).to_le_bytes());
+            transaction.put(
+                self.sub_dbs.output_txs,
+                &ZERO_KEY,
+                &out_tx,
+                WriteFlags::APPEND_DUP,
+            )?;
+
+            // outkey, or pre_rct_outkey for outputs with a visible amount
+            let amount = if tx.prefix.version.0 > 1 {
+                0
+            } else {
+                output.amount.0
+            };
+            let amount_index = next_amount_index(transaction, self, amount)?;
+            let mut outkey = amount_index.to_le_bytes().to_vec();
+            outkey.extend_from_slice(&output_id.to_le_bytes());
+            outkey.extend_from_slice(key.as_bytes());
+            outkey.extend_from_slice(&tx.prefix.unlock_time.0.to_le_bytes());
+            outkey.extend_from_slice(&height.to_le_bytes());
+            if amount == 0 {
+                let commitment = match &tx.rct_signatures.sig {
+                    Some(sig) if !miner_tx => sig.out_pk[local_index].mask.key,
+                    _ => zero_commit(output.amount.0),
+                };
+                outkey.extend_from_slice(&commitment);
+            }
+            transaction.put(
+                self.sub_dbs.output_amounts,
+                &amount.to_le_bytes(),
+                &outkey,
+                WriteFlags::APPEND_DUP,
+            )?;
+            amount_indices.extend_from_slice(&amount_index.to_le_bytes());
+        }
+        transaction.put(
+            self.sub_dbs.tx_outputs,
+            &tx_id_key,
+            &amount_indices,
+            WriteFlags::APPEND,
+        )?;
+        Ok(())
+    }
+}
+
+/// Miner transaction of the mainnet and testnet genesis blocks
+const GENESIS_TX: &str = "013c01ff0001ffffffffffff03029b2e4c0281c0b02e7c53291a94d1d0cbff8883f8024f5142ee494ffbbd08807121017767aafcde9be00dcfd098715ebcf7f410daebc582fda69d24a28e9d0bc890d1";
+
+/// Miner transaction of the stagenet genesis block
+const STAGENET_GENESIS_TX: &str = "013c01ff0001ffffffffffff0302df5d56da0c7d643ddd1ce61901c7bdc5fb1738bfe39fbe69c28a3a7032729c0f2101168d0c4ca86fb55a4cf6a36d31431be1c53a3bd7411bb24e8832410289fa6f3b";
+
+/// Gets the genesis block of a network
+///
+pub fn genesis_block(network: monero::Network) -> Block {
+    let genesis_tx = match network {
+        monero::Network::Stagenet => STAGENET_GENESIS_TX,
+        monero::Network::Mainnet | monero::Network::Testnet => GENESIS_TX,
+    };
+    let miner_tx = monero::consensus::deserialize(&hex::decode(genesis_tx).unwrap())
+        .expect("genesis transaction is valid");
+    Block {
+        header: monero::BlockHeader {
+            major_version: monero::VarInt(1),
+            minor_version: monero::VarInt(0),
+            timestamp: monero::VarInt(0),
+            prev_id: Hash::from_slice(&[0; 32]),
+            nonce: genesis_nonce(network),
+        },
+        miner_tx,
+        tx_hashes: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::genesis_block;
+    use crate::consensus::genesis_hash;
+    use monero::cryptonote::hash::Hashable;
+    use monero::Network;
+
+    #[test]
+    fn genesis_block_ids() {
+        for network in [Network::Mainnet, Network::Testnet, Network::Stagenet] {
+            assert_eq!(genesis_block(network).id(), genesis_hash(network));
+        }
+    }
+}